@@ -0,0 +1,122 @@
+use std::{collections::HashMap, hash::Hash};
+
+use crate::{
+    bidag::FromChildren,
+    labeled::{LabeledTerm, LabeledTermRef},
+    unify::Substitution,
+};
+
+// the distinct labels in order of their first occurrence, with their multiplicities
+fn multiset<T: Clone + Hash + Eq>(labels: Vec<&T>) -> Vec<(T, usize)> {
+    let mut counts: Vec<(T, usize)> = Vec::new();
+    let mut indices = HashMap::new();
+    for label in labels {
+        let index = *indices.entry(label).or_insert_with(|| {
+            counts.push((label.clone(), 0));
+            counts.len() - 1
+        });
+        counts[index].1 += 1;
+    }
+    counts
+}
+
+// Chooses how often every atom goes into the term bound to every variable, one (variable, atom)
+// cell after the other, so that all atoms are used up and no variable is bound to nothing.
+fn assign(
+    cell: usize,
+    multiplicities: &[usize],
+    remaining: &mut [usize],
+    counts: &mut [Vec<usize>],
+    found: &mut Vec<Vec<Vec<usize>>>,
+) {
+    let atoms = remaining.len();
+    if cell == multiplicities.len() * atoms {
+        if remaining.iter().all(|&left| left == 0) {
+            found.push(counts.to_vec());
+        }
+        return;
+    }
+
+    let (variable, atom) = (cell / atoms, cell % atoms);
+    let multiplicity = multiplicities[variable];
+    for count in 0..=remaining[atom] / multiplicity {
+        counts[variable][atom] = count;
+        if atom + 1 == atoms && counts[variable].iter().all(|&count| count == 0) {
+            continue;
+        }
+        remaining[atom] -= count * multiplicity;
+        assign(cell + 1, multiplicities, remaining, counts, found);
+        remaining[atom] += count * multiplicity;
+    }
+    counts[variable][atom] = 0;
+}
+
+impl<T: Clone + Hash + Eq> LabeledTerm<T> {
+    /// All matches of `self` against `subject` with the operation associative and commutative.
+    /// The leaves of `self` are variables and those of `subject` constants. Modulo AC a term is
+    /// just the multiset of its leaves, so one substitution stands for all bracketings and orders
+    /// of the terms it binds, which are built as right combs.
+    pub fn match_ac(self: &LabeledTermRef<T>, subject: &LabeledTermRef<T>) -> Vec<Substitution<T>> {
+        let variables = multiset(self.leaf_labels());
+        let atoms = multiset(subject.leaf_labels());
+        let multiplicities: Vec<_> = variables.iter().map(|(_, count)| *count).collect();
+        let mut remaining: Vec<_> = atoms.iter().map(|(_, count)| *count).collect();
+        let mut found = Vec::new();
+        assign(
+            0,
+            &multiplicities,
+            &mut remaining,
+            &mut vec![vec![0; atoms.len()]; variables.len()],
+            &mut found,
+        );
+
+        found
+            .into_iter()
+            .map(|counts| {
+                let mut substitution = Substitution::new();
+                for ((variable, _), counts) in variables.iter().zip(counts) {
+                    let mut labels = atoms
+                        .iter()
+                        .zip(counts)
+                        .flat_map(|((label, _), count)| std::iter::repeat_n(label, count))
+                        .rev();
+                    let last = LabeledTermRef::from_leaf(labels.next().unwrap().clone());
+                    let bound = labels.fold(last, |right, label| {
+                        LabeledTermRef::from_children(
+                            LabeledTermRef::from_leaf(label.clone()),
+                            right,
+                        )
+                    });
+                    substitution.insert(variable.clone(), bound);
+                }
+                substitution
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lterm;
+
+    #[test]
+    fn match_ac() {
+        // every split of three constants into two nonempty parts
+        let matches = lterm!(x * y).match_ac(&lterm!(a * (b * c)));
+        assert_eq!(matches.len(), 6);
+        let other = lterm!(c * (b * a)).match_ac(&lterm!((a * b) * c));
+        assert_eq!(other.len(), 6);
+
+        let matches = lterm!(x * x).match_ac(&lterm!((a * b) * (b * a)));
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].to_string(), "{x ↦ a * b}");
+
+        let matches = lterm!(x * (y * x)).match_ac(&lterm!(a * (b * (a * b))));
+        let mut found: Vec<_> = matches.iter().map(|m| m.to_string()).collect();
+        found.sort();
+        assert_eq!(found, ["{x ↦ a, y ↦ b * b}", "{x ↦ b, y ↦ a * a}"]);
+
+        assert!(lterm!((x * y) * z).match_ac(&lterm!(a * b)).is_empty());
+        assert!(lterm!(x * x).match_ac(&lterm!(a * (a * b))).is_empty());
+    }
+}
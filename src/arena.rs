@@ -0,0 +1,242 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    bidag::{BinaryChildren, PARALLEL_SPLIT_DEPTH},
+    byaddr::TermByAddress,
+    term::{Term, TermRef},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ArenaNode {
+    Variable,
+    Operation(NodeId, NodeId),
+}
+
+/// Terms stored as a flat vector of nodes. Children are always pushed before their parents, so
+/// node ids are a topological order of every term in the arena and no traversal needs recursion.
+pub struct TermArena {
+    nodes: Vec<ArenaNode>,
+}
+
+impl Default for TermArena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TermArena {
+    pub fn new() -> Self {
+        TermArena {
+            nodes: vec![ArenaNode::Variable],
+        }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut nodes = Vec::with_capacity(capacity.max(1));
+        nodes.push(ArenaNode::Variable);
+        TermArena { nodes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether only the shared variable node is stored.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 1
+    }
+
+    /// All variables are the same node.
+    pub fn variable(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    pub fn operation(&mut self, left: NodeId, right: NodeId) -> NodeId {
+        let id = NodeId(u32::try_from(self.nodes.len()).expect("term arena is full"));
+        self.nodes.push(ArenaNode::Operation(left, right));
+        id
+    }
+
+    pub fn get(&self, id: NodeId) -> ArenaNode {
+        self.nodes[id.index()]
+    }
+
+    pub fn children(&self, id: NodeId) -> Option<(NodeId, NodeId)> {
+        match self.get(id) {
+            ArenaNode::Variable => None,
+            ArenaNode::Operation(left, right) => Some((left, right)),
+        }
+    }
+
+    /// Bottom-up fold over the term rooted at `id`, with the same semantics as
+    /// `BinaryChildren::reduce`. Shared nodes are visited once per occurrence.
+    pub fn reduce<S, F: FnMut(NodeId, S, S) -> S, L: FnMut(NodeId) -> S>(
+        &self,
+        id: NodeId,
+        reduction: &mut F,
+        labeler: &mut L,
+    ) -> S {
+        let mut results = Vec::new();
+        let mut stack = vec![(id, false)];
+
+        while let Some((node, expanded)) = stack.pop() {
+            match self.children(node) {
+                None => results.push(labeler(node)),
+                Some((left, right)) => {
+                    if expanded {
+                        let right_result = results.pop().unwrap();
+                        let left_result = results.pop().unwrap();
+                        results.push(reduction(node, left_result, right_result));
+                    } else {
+                        stack.push((node, true));
+                        stack.push((right, false));
+                        stack.push((left, false));
+                    }
+                }
+            }
+        }
+
+        results.pop().unwrap()
+    }
+
+    /// Parallel version of `reduce`, see `BinaryChildren::reduce_par`.
+    pub fn reduce_par<S, F, L>(&self, id: NodeId, reduction: &F, labeler: &L) -> S
+    where
+        S: Send,
+        F: Fn(NodeId, S, S) -> S + Sync,
+        L: Fn(NodeId) -> S + Sync,
+    {
+        fn helper<S, F, L>(
+            arena: &TermArena,
+            id: NodeId,
+            depth: usize,
+            reduction: &F,
+            labeler: &L,
+        ) -> S
+        where
+            S: Send,
+            F: Fn(NodeId, S, S) -> S + Sync,
+            L: Fn(NodeId) -> S + Sync,
+        {
+            match arena.children(id) {
+                Some((left, right)) if depth > 0 => {
+                    let (left_result, right_result) = rayon::join(
+                        || helper(arena, left, depth - 1, reduction, labeler),
+                        || helper(arena, right, depth - 1, reduction, labeler),
+                    );
+                    reduction(id, left_result, right_result)
+                }
+                _ => arena.reduce(
+                    id,
+                    &mut |node, left, right| reduction(node, left, right),
+                    &mut |leaf| labeler(leaf),
+                ),
+            }
+        }
+
+        helper(self, id, PARALLEL_SPLIT_DEPTH, reduction, labeler)
+    }
+
+    pub fn leaf_count(&self, id: NodeId) -> usize {
+        self.reduce(id, &mut |_, left, right| left + right, &mut |_| 1)
+    }
+
+    /// Copies `term` into the arena. Physically shared subterms stay shared.
+    pub fn insert(&mut self, term: &TermRef) -> NodeId {
+        let mut inserted = HashMap::new();
+        self.insert_helper(term, &mut inserted)
+    }
+
+    fn insert_helper<'t>(
+        &mut self,
+        term: &'t TermRef,
+        inserted: &mut HashMap<TermByAddress<'t>, NodeId>,
+    ) -> NodeId {
+        match term.children() {
+            None => self.variable(),
+            Some((left, right)) => {
+                if let Some(id) = inserted.get(&TermByAddress::from(term.as_ref())) {
+                    return *id;
+                }
+                let left_id = self.insert_helper(left, inserted);
+                let right_id = self.insert_helper(right, inserted);
+                let id = self.operation(left_id, right_id);
+                inserted.insert(TermByAddress::from(term.as_ref()), id);
+                id
+            }
+        }
+    }
+
+    /// Converts the term rooted at `id` back to the `Rc` representation. Shared arena nodes become
+    /// shared `Rc`s.
+    pub fn to_term(&self, id: NodeId) -> TermRef {
+        let mut converted: Vec<Option<TermRef>> = vec![None; id.index() + 1];
+        converted[0] = Some(Rc::new(Term::Variable));
+
+        let mut stack = vec![id];
+        while let Some(&node) = stack.last() {
+            if converted[node.index()].is_some() {
+                stack.pop();
+                continue;
+            }
+            let (left, right) = self.children(node).unwrap();
+            match (&converted[left.index()], &converted[right.index()]) {
+                (Some(left_term), Some(right_term)) => {
+                    converted[node.index()] =
+                        Some(Term::operation(left_term.clone(), right_term.clone()));
+                    stack.pop();
+                }
+                (left_done, right_done) => {
+                    if right_done.is_none() {
+                        stack.push(right);
+                    }
+                    if left_done.is_none() {
+                        stack.push(left);
+                    }
+                }
+            }
+        }
+
+        converted[id.index()].take().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::term;
+
+    #[test]
+    fn arena() {
+        let mut arena = TermArena::with_capacity(0);
+        assert!(arena.is_empty());
+        let term = term!((a * (b * c)) * (d * e));
+        let id = arena.insert(&term);
+        assert!(!arena.is_empty());
+        assert_eq!(arena.len(), 1 + 4);
+        assert_eq!(arena.to_term(id), term);
+        assert_eq!(arena.leaf_count(id), 5);
+        let depth = |_, left: usize, right: usize| left.max(right) + 1;
+        assert_eq!(arena.reduce_par(id, &depth, &|_| 0), 3);
+
+        // shared subterms are stored once and come back shared
+        let square = Term::operation(term.clone(), term.clone());
+        let shared = arena.insert(&square);
+        assert_eq!(arena.len(), 1 + 4 + 4 + 1);
+        let (left, right) = arena.children(shared).unwrap();
+        assert_eq!(left, right);
+        let converted = arena.to_term(shared);
+        let (left, right) = converted.children().unwrap();
+        assert!(Rc::ptr_eq(left, right));
+        assert_eq!(converted, square);
+    }
+}
@@ -1,4 +1,12 @@
-use crate::maps::NodeIndex;
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hasher,
+};
+
+use crate::{maps::NodeIndex, position::Position};
+
+/// Number of levels at the top of a tree that `reduce_par` splits into parallel tasks.
+pub const PARALLEL_SPLIT_DEPTH: usize = 10;
 
 pub trait BinaryChildren {
     fn children(&self) -> Option<(&Self, &Self)>;
@@ -7,20 +15,112 @@ pub trait BinaryChildren {
         self.children().is_none()
     }
 
+    /// Identifies the physical node, for handles this is the address of the pointee.
+    fn address(&self) -> *const () {
+        self as *const Self as *const ()
+    }
+
     fn reduce<S, F: FnMut(&Self, S, S) -> S, L: FnMut(&Self) -> S>(
         &self,
         reduction: &mut F,
         labeler: &mut L,
     ) -> S {
-        match self.children() {
-            None => labeler(self),
-            Some((left, right)) => {
-                let result_left = left.reduce(reduction, labeler);
-                let result_right = right.reduce(reduction, labeler);
+        // explicit stacks instead of recursion, so deep skinny terms don't overflow the call stack
+        let mut results = Vec::new();
+        let mut stack = vec![(self, false)];
 
-                reduction(self, result_left, result_right)
+        while let Some((node, expanded)) = stack.pop() {
+            match node.children() {
+                None => results.push(labeler(node)),
+                Some((left, right)) => {
+                    if expanded {
+                        let result_right = results.pop().unwrap();
+                        let result_left = results.pop().unwrap();
+                        results.push(reduction(node, result_left, result_right));
+                    } else {
+                        stack.push((node, true));
+                        stack.push((right, false));
+                        stack.push((left, false));
+                    }
+                }
             }
         }
+
+        results.pop().unwrap()
+    }
+
+    /// Like `reduce`, but every physical node is processed once and its result is cloned for
+    /// further occurrences, so the work is linear in the size of the DAG instead of the tree.
+    fn reduce_shared<S: Clone, F: FnMut(&Self, S, S) -> S, L: FnMut(&Self) -> S>(
+        &self,
+        reduction: &mut F,
+        labeler: &mut L,
+    ) -> S {
+        let mut memo: HashMap<*const (), S> = HashMap::new();
+        let mut results = Vec::new();
+        let mut stack = vec![(self, false)];
+
+        // a node is always finished before another occurrence of it is popped, because the stack
+        // completes the left subtree before it touches the right one
+        while let Some((node, expanded)) = stack.pop() {
+            if !expanded && let Some(result) = memo.get(&node.address()) {
+                results.push(result.clone());
+                continue;
+            }
+            let result = match node.children() {
+                None => labeler(node),
+                Some((left, right)) => {
+                    if !expanded {
+                        stack.push((node, true));
+                        stack.push((right, false));
+                        stack.push((left, false));
+                        continue;
+                    }
+                    let result_right = results.pop().unwrap();
+                    let result_left = results.pop().unwrap();
+                    reduction(node, result_left, result_right)
+                }
+            };
+            memo.insert(node.address(), result.clone());
+            results.push(result);
+        }
+
+        results.pop().unwrap()
+    }
+
+    /// Like `reduce`, but the subtrees below the top `PARALLEL_SPLIT_DEPTH` levels are reduced
+    /// in parallel, each of them sequentially.
+    fn reduce_par<S: Send, F: Fn(&Self, S, S) -> S + Sync, L: Fn(&Self) -> S + Sync>(
+        &self,
+        reduction: &F,
+        labeler: &L,
+    ) -> S
+    where
+        Self: Sync,
+    {
+        fn helper<N, S, F, L>(node: &N, depth: usize, reduction: &F, labeler: &L) -> S
+        where
+            N: BinaryChildren + Sync + ?Sized,
+            S: Send,
+            F: Fn(&N, S, S) -> S + Sync,
+            L: Fn(&N) -> S + Sync,
+        {
+            match node.children() {
+                Some((left, right)) if depth > 0 => {
+                    let (left_result, right_result) = rayon::join(
+                        || helper(left, depth - 1, reduction, labeler),
+                        || helper(right, depth - 1, reduction, labeler),
+                    );
+                    reduction(node, left_result, right_result)
+                }
+                _ => node.reduce(
+                    &mut |node, left, right| reduction(node, left, right),
+                    &mut |leaf| labeler(leaf),
+                ),
+            }
+        }
+
+        helper(self, PARALLEL_SPLIT_DEPTH, reduction, labeler)
     }
 
     fn propagate<S, F: FnMut(&Self, S) -> (S, S), L: FnMut(&Self, S)>(
@@ -29,14 +129,122 @@ pub trait BinaryChildren {
         propagation: &mut F,
         finalizer: &mut L,
     ) {
-        match self.children() {
-            None => finalizer(self, value),
-            Some((left, right)) => {
-                let (left_prop, right_prop) = propagation(self, value);
-                left.propagate(left_prop, propagation, finalizer);
-                right.propagate(right_prop, propagation, finalizer);
+        let mut stack = vec![(self, value)];
+
+        while let Some((node, value)) = stack.pop() {
+            match node.children() {
+                None => finalizer(node, value),
+                Some((left, right)) => {
+                    let (left_prop, right_prop) = propagation(node, value);
+                    stack.push((right, right_prop));
+                    stack.push((left, left_prop));
+                }
+            }
+        }
+    }
+
+    /// Compares the shapes of two trees, ignoring anything stored in the nodes.
+    fn shape_eq(&self, other: &Self) -> bool {
+        let mut stack = vec![(self, other)];
+
+        while let Some((a, b)) = stack.pop() {
+            if std::ptr::eq(a, b) {
+                continue;
+            }
+            match (a.children(), b.children()) {
+                (None, None) => {}
+                (Some((a_left, a_right)), Some((b_left, b_right))) => {
+                    stack.push((a_right, b_right));
+                    stack.push((a_left, b_left));
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    fn hash_shape<H: Hasher>(&self, state: &mut H) {
+        self.walk_preorder(&mut |node| state.write_u8(node.is_leaf() as u8));
+    }
+
+    fn walk_preorder<F: FnMut(&Self)>(&self, visitor: &mut F) {
+        let mut stack = vec![self];
+
+        while let Some(node) = stack.pop() {
+            visitor(node);
+            if let Some((left, right)) = node.children() {
+                stack.push(right);
+                stack.push(left);
+            }
+        }
+    }
+
+    fn walk_with_positions<F: FnMut(&Position, &Self)>(&self, visitor: &mut F) {
+        let mut stack = vec![(Position::root(), self)];
+
+        while let Some((position, node)) = stack.pop() {
+            visitor(&position, node);
+            if let Some((left, right)) = node.children() {
+                stack.push((position.right(), right));
+                stack.push((position.left(), left));
+            }
+        }
+    }
+
+    /// Visits the nodes in level order, left to right within a level, with their depth.
+    fn walk_bfs<F: FnMut(&Self, usize)>(&self, visitor: &mut F) {
+        let mut queue = VecDeque::from([(self, 0)]);
+
+        while let Some((node, depth)) = queue.pop_front() {
+            visitor(node, depth);
+            if let Some((left, right)) = node.children() {
+                queue.push_back((left, depth + 1));
+                queue.push_back((right, depth + 1));
+            }
+        }
+    }
+
+    /// The nodes grouped by depth, starting with the root.
+    fn levels(&self) -> Levels<'_, Self> {
+        Levels {
+            current: vec![self],
+        }
+    }
+
+    /// Like `reduce`, but nodes are combined level by level from the deepest level up, and within
+    /// a level from right to left.
+    fn reduce_bfs<S, F: FnMut(&Self, S, S) -> S, L: FnMut(&Self) -> S>(
+        &self,
+        reduction: &mut F,
+        labeler: &mut L,
+    ) -> S {
+        // in level order the children of a node come after it, so iterating backwards sees them
+        // first
+        let mut nodes = vec![(self, None)];
+        let mut next = 0;
+        while next < nodes.len() {
+            if let Some((left, right)) = nodes[next].0.children() {
+                let first_child = nodes.len();
+                nodes[next].1 = Some(first_child);
+                nodes.push((left, None));
+                nodes.push((right, None));
             }
+            next += 1;
+        }
+
+        let mut results: Vec<Option<S>> = (0..nodes.len()).map(|_| None).collect();
+        for (index, (node, first_child)) in nodes.iter().enumerate().rev() {
+            results[index] = Some(match first_child {
+                None => labeler(node),
+                Some(left) => {
+                    let left_result = results[*left].take().unwrap();
+                    let right_result = results[*left + 1].take().unwrap();
+                    reduction(node, left_result, right_result)
+                }
+            });
         }
+
+        results[0].take().unwrap()
     }
 
     fn walk_leaves<F: FnMut(&Self)>(&self, visitor: &mut F) {
@@ -49,13 +257,16 @@ pub trait BinaryChildren {
 
     // cannot be reduced to reduce, because would need to have double mut borrow to visior
     fn walk<F: FnMut(&Self)>(&self, visitor: &mut F) {
-        match self.children() {
-            None => visitor(self),
-            Some((left, right)) => {
-                left.walk(visitor);
-                right.walk(visitor);
+        let mut stack = vec![(self, false)];
 
-                visitor(self)
+        while let Some((node, expanded)) = stack.pop() {
+            match node.children() {
+                Some((left, right)) if !expanded => {
+                    stack.push((node, true));
+                    stack.push((right, false));
+                    stack.push((left, false));
+                }
+                _ => visitor(node),
             }
         }
     }
@@ -92,17 +303,27 @@ pub trait BinaryChildren {
         &self,
         transformer: &mut F,
     ) -> Option<R> {
-        match self.children() {
-            None => transformer(self).map(R::from_leaf),
-            Some((left, right)) => {
-                if let Some(left_result) = left.try_map(transformer)
-                    && let Some(right_result) = right.try_map(transformer)
-                {
-                    return Some(R::from_children(left_result, right_result));
+        let mut results = Vec::new();
+        let mut stack = vec![(self, false)];
+
+        while let Some((node, expanded)) = stack.pop() {
+            match node.children() {
+                None => results.push(R::from_leaf(transformer(node)?)),
+                Some((left, right)) => {
+                    if expanded {
+                        let right_result = results.pop().unwrap();
+                        let left_result = results.pop().unwrap();
+                        results.push(R::from_children(left_result, right_result));
+                    } else {
+                        stack.push((node, true));
+                        stack.push((right, false));
+                        stack.push((left, false));
+                    }
                 }
-                None
             }
         }
+
+        results.pop()
     }
 
     fn map<S, R: FromChildren<S>, F: FnMut(&Self) -> S>(&self, transformer: &mut F) -> R {
@@ -114,6 +335,19 @@ pub trait BinaryChildren {
         )
     }
 
+    /// Like `map`, but shared subterms stay shared in the result and are transformed once.
+    fn map_shared<S, R: FromChildren<S> + Clone, F: FnMut(&Self) -> S>(
+        &self,
+        transformer: &mut F,
+    ) -> R {
+        self.reduce_shared(
+            &mut #[inline(always)]
+            |_, left, right| R::from_children(left, right),
+            &mut #[inline(always)]
+            |leaf| R::from_leaf(transformer(leaf)),
+        )
+    }
+
     fn display_helper<
         T,
         E,
@@ -130,26 +364,67 @@ pub trait BinaryChildren {
         combine: &mut FC,
         leaf: &mut L,
     ) -> Result<T, E> {
-        match self.children() {
-            None => leaf(self, state),
-            Some((left, right)) => {
-                if !left.is_leaf() {
-                    enter(left, state)?;
-                    left.display_helper(state, enter, leave, combine, leaf)?;
-                    leave(left, state)?;
-                } else {
-                    leaf(left, state)?;
-                }
-                combine(self, state)?;
-                if !right.is_leaf() {
-                    enter(right, state)?;
-                    right.display_helper(state, enter, leave, combine, leaf)?;
-                    leave(right, state)
-                } else {
-                    leaf(right, state)
-                }
+        enum Step<'n, N: ?Sized> {
+            Visit(&'n N),
+            Enter(&'n N),
+            Leave(&'n N),
+            Combine(&'n N),
+            Leaf(&'n N),
+        }
+
+        fn push_child<'n, N: BinaryChildren + ?Sized>(stack: &mut Vec<Step<'n, N>>, child: &'n N) {
+            if child.is_leaf() {
+                stack.push(Step::Leaf(child));
+            } else {
+                stack.push(Step::Leave(child));
+                stack.push(Step::Visit(child));
+                stack.push(Step::Enter(child));
             }
         }
+
+        let mut stack = vec![Step::Visit(self)];
+        let mut result = None;
+
+        while let Some(step) = stack.pop() {
+            result = Some(match step {
+                Step::Visit(node) => match node.children() {
+                    None => leaf(node, state)?,
+                    Some((left, right)) => {
+                        push_child(&mut stack, right);
+                        stack.push(Step::Combine(node));
+                        push_child(&mut stack, left);
+                        continue;
+                    }
+                },
+                Step::Enter(node) => enter(node, state)?,
+                Step::Leave(node) => leave(node, state)?,
+                Step::Combine(node) => combine(node, state)?,
+                Step::Leaf(node) => leaf(node, state)?,
+            });
+        }
+
+        Ok(result.unwrap())
+    }
+}
+
+pub struct Levels<'n, N: ?Sized> {
+    current: Vec<&'n N>,
+}
+
+impl<'n, N: BinaryChildren + ?Sized> Iterator for Levels<'n, N> {
+    type Item = Vec<&'n N>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.current.is_empty() {
+            return None;
+        }
+        let next = self
+            .current
+            .iter()
+            .filter_map(|node| node.children())
+            .flat_map(|(left, right)| [left, right])
+            .collect();
+        Some(std::mem::replace(&mut self.current, next))
     }
 }
 
@@ -157,3 +432,52 @@ pub trait FromChildren<T>: BinaryChildren {
     fn from_leaf(value: T) -> Self;
     fn from_children(left: Self, right: Self) -> Self;
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::{arena::TermArena, lterm, sync::SyncTerm, term::Term};
+
+    #[test]
+    fn level_order() {
+        let term = lterm!((a * (b * c)) * d);
+
+        let mut visited = Vec::new();
+        term.walk_bfs(&mut |node, depth| {
+            visited.push(format!("{}{}", node.label().map_or("*", |l| l), depth))
+        });
+        assert_eq!(visited, ["*0", "*1", "d1", "a2", "*2", "b3", "c3"]);
+
+        let widths: Vec<_> = term.levels().map(|level| level.len()).collect();
+        assert_eq!(widths, [1, 2, 2, 2]);
+
+        let mut labels = String::new();
+        let shown = term.reduce_bfs(
+            &mut |_, left, right| format!("({} {})", left, right),
+            &mut |leaf| {
+                labels.push_str(leaf.label().unwrap());
+                leaf.label().unwrap().clone()
+            },
+        );
+        assert_eq!(shown, "((a (b c)) d)");
+        assert_eq!(labels, "cbad");
+    }
+
+    #[test]
+    fn parallel_reduce() {
+        let term = Term::random(100_000, &mut StdRng::seed_from_u64(1882));
+        let depth = |_: &_, left: usize, right: usize| left.max(right) + 1;
+
+        let sync = SyncTerm::from_term(&term);
+        assert_eq!(sync.reduce_par(&depth, &|_| 0), term.depth());
+
+        let mut arena = TermArena::new();
+        let root = arena.insert(&term);
+        assert_eq!(
+            arena.reduce_par(root, &|_, left, right| left + right, &|_| 1),
+            100_000
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::term::{Term, TermRef};
+
+/// Stack machine for building terms bottom-up. Nodes are hash-consed: all leaves are one node,
+/// and combining the same two children twice returns the same node, so every distinct subterm
+/// built with one builder is allocated once.
+pub struct TermBuilder {
+    stack: Vec<TermRef>,
+    leaf: TermRef,
+    interned: HashMap<(*const Term, *const Term), TermRef>,
+}
+
+impl Default for TermBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TermBuilder {
+    pub fn new() -> Self {
+        TermBuilder {
+            stack: Vec::new(),
+            leaf: Rc::new(Term::Variable),
+            interned: HashMap::new(),
+        }
+    }
+
+    /// The shared operation node with these children.
+    pub fn operation(&mut self, left: TermRef, right: TermRef) -> TermRef {
+        self.interned
+            .entry((Rc::as_ptr(&left), Rc::as_ptr(&right)))
+            .or_insert_with(|| Term::operation(left, right))
+            .clone()
+    }
+
+    pub fn push_leaf(&mut self) -> &mut Self {
+        self.stack.push(self.leaf.clone());
+        self
+    }
+
+    pub fn push_leaves(&mut self, count: usize) -> &mut Self {
+        for _ in 0..count {
+            self.push_leaf();
+        }
+        self
+    }
+
+    /// Pushes an existing term. Its nodes are not interned.
+    pub fn push(&mut self, term: TermRef) -> &mut Self {
+        self.stack.push(term);
+        self
+    }
+
+    /// Replaces the top two terms by their operation, the top one becoming the right child.
+    pub fn combine(&mut self) -> &mut Self {
+        assert!(
+            self.stack.len() >= 2,
+            "combine needs two terms on the stack"
+        );
+        let right = self.stack.pop().unwrap();
+        let left = self.stack.pop().unwrap();
+        let operation = self.operation(left, right);
+        self.stack.push(operation);
+        self
+    }
+
+    /// Combines the whole stack into one term, nested to the right.
+    pub fn combine_all(&mut self) -> &mut Self {
+        while self.stack.len() > 1 {
+            self.combine();
+        }
+        self
+    }
+
+    pub fn pop(&mut self) -> Option<TermRef> {
+        self.stack.pop()
+    }
+
+    pub fn len(&self) -> usize {
+        self.stack.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+
+    /// Takes the single term on the stack. Panics if there is not exactly one.
+    pub fn finish(&mut self) -> TermRef {
+        assert_eq!(
+            self.stack.len(),
+            1,
+            "the stack does not hold exactly one term"
+        );
+        self.stack.pop().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::term;
+
+    #[test]
+    fn build() {
+        let mut builder = TermBuilder::new();
+        let term = builder
+            .push_leaf()
+            .push_leaf()
+            .combine()
+            .push_leaf()
+            .push_leaves(2)
+            .combine()
+            .combine()
+            .combine()
+            .finish();
+        assert!(term == term!((a * b) * (c * (d * e))));
+
+        let comb = builder.push_leaves(10_000).combine_all().finish();
+        assert_eq!(comb.leaves(), 10_000);
+
+        // pushed terms are kept as they are
+        builder.push(comb.clone()).push_leaf();
+        assert_eq!(builder.len(), 2);
+        assert_eq!(builder.pop().map(|leaf| leaf.leaves()), Some(1));
+        assert!(Rc::ptr_eq(&builder.pop().unwrap(), &comb));
+
+        // both children are built separately, but end up as the same node
+        let left = builder.push_leaves(3).combine_all().finish();
+        let right = builder.push_leaves(3).combine_all().finish();
+        assert!(Rc::ptr_eq(&left, &right));
+        assert!(builder.is_empty());
+    }
+}
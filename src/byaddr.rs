@@ -16,13 +16,13 @@ impl AsRef<Term> for TermByAddress<'_> {
     }
 }
 
-impl<'a> PartialEq for &TermByAddress<'a> {
+impl<'a> PartialEq for TermByAddress<'a> {
     fn eq(&self, other: &Self) -> bool {
         std::ptr::eq(self.0 as *const Term, other.0 as *const Term)
     }
 }
 
-impl<'a> Eq for &TermByAddress<'a> {}
+impl<'a> Eq for TermByAddress<'a> {}
 
 impl<'a> From<&'a Term> for TermByAddress<'a> {
     fn from(value: &'a Term) -> Self {
@@ -0,0 +1,194 @@
+use std::fmt::Display;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    InvalidTag(u8),
+    TrailingBytes(usize),
+    InvalidShape,
+    InvalidUtf8,
+    InvalidIndex(u32),
+    NotPermutation,
+    TooDeep,
+    InvalidTree,
+    InvalidGroup,
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnexpectedEnd => write!(f, "unexpected end of input"),
+            Self::InvalidTag(tag) => write!(f, "invalid tag byte {}", tag),
+            Self::TrailingBytes(count) => write!(f, "{} trailing bytes after value", count),
+            Self::InvalidShape => write!(f, "bits do not describe a binary term"),
+            Self::InvalidUtf8 => write!(f, "label is not valid utf-8"),
+            Self::InvalidIndex(index) => write!(f, "index {} out of range", index),
+            Self::NotPermutation => write!(f, "values are not a permutation"),
+            Self::TooDeep => write!(f, "nesting too deep"),
+            Self::InvalidTree => write!(f, "substitution tree is malformed"),
+            Self::InvalidGroup => write!(f, "stabilizer chain is inconsistent"),
+        }
+    }
+}
+
+/// Little endian byte sink for the compact binary formats.
+#[derive(Default)]
+pub struct ByteWriter {
+    bytes: Vec<u8>,
+}
+
+impl ByteWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u16_slice(&mut self, values: &[u16]) {
+        self.u32(values.len() as u32);
+        values.iter().for_each(|v| self.u16(*v));
+    }
+
+    pub fn byte_slice(&mut self, values: &[u8]) {
+        self.u32(values.len() as u32);
+        self.bytes.extend_from_slice(values);
+    }
+
+    /// Packs the bits eight to a byte, least significant bit first.
+    pub fn bits(&mut self, bits: &[bool]) {
+        self.u32(bits.len() as u32);
+        self.bytes.extend(bits.chunks(8).map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0, |byte, (i, bit)| byte | ((*bit as u8) << i))
+        }));
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+pub struct ByteReader<'b> {
+    bytes: &'b [u8],
+}
+
+impl<'b> ByteReader<'b> {
+    pub fn new(bytes: &'b [u8]) -> Self {
+        ByteReader { bytes }
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let (head, tail) = self
+            .bytes
+            .split_first_chunk::<N>()
+            .ok_or(DecodeError::UnexpectedEnd)?;
+        self.bytes = tail;
+        Ok(*head)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    pub fn bool(&mut self) -> Result<bool, DecodeError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+
+    pub fn u16_vec(&mut self) -> Result<Vec<u16>, DecodeError> {
+        let len = self.u32()? as usize;
+        if self.bytes.len() < 2 * len {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        (0..len).map(|_| self.u16()).collect()
+    }
+
+    pub fn byte_vec(&mut self) -> Result<Vec<u8>, DecodeError> {
+        let len = self.u32()? as usize;
+        let (head, tail) = self
+            .bytes
+            .split_at_checked(len)
+            .ok_or(DecodeError::UnexpectedEnd)?;
+        self.bytes = tail;
+        Ok(head.to_vec())
+    }
+
+    pub fn bits(&mut self) -> Result<Vec<bool>, DecodeError> {
+        let len = self.u32()? as usize;
+        let (head, tail) = self
+            .bytes
+            .split_at_checked(len.div_ceil(8))
+            .ok_or(DecodeError::UnexpectedEnd)?;
+        self.bytes = tail;
+        Ok((0..len)
+            .map(|i| head[i / 8] & (1 << (i % 8)) != 0)
+            .collect())
+    }
+
+    pub fn finish(self) -> Result<(), DecodeError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(DecodeError::TrailingBytes(self.bytes.len()))
+        }
+    }
+}
+
+/// Leaf labels that can be stored in the label table of an encoded `LabeledTerm`.
+pub trait LabelCodec: Sized {
+    fn encode_label(&self, writer: &mut ByteWriter);
+    fn decode_label(reader: &mut ByteReader) -> Result<Self, DecodeError>;
+}
+
+impl LabelCodec for String {
+    fn encode_label(&self, writer: &mut ByteWriter) {
+        writer.byte_slice(self.as_bytes());
+    }
+
+    fn decode_label(reader: &mut ByteReader) -> Result<Self, DecodeError> {
+        String::from_utf8(reader.byte_vec()?).map_err(|_| DecodeError::InvalidUtf8)
+    }
+}
+
+impl LabelCodec for u16 {
+    fn encode_label(&self, writer: &mut ByteWriter) {
+        writer.u16(*self);
+    }
+
+    fn decode_label(reader: &mut ByteReader) -> Result<Self, DecodeError> {
+        reader.u16()
+    }
+}
+
+impl LabelCodec for u32 {
+    fn encode_label(&self, writer: &mut ByteWriter) {
+        writer.u32(*self);
+    }
+
+    fn decode_label(reader: &mut ByteReader) -> Result<Self, DecodeError> {
+        reader.u32()
+    }
+}
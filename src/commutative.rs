@@ -0,0 +1,192 @@
+use std::{cmp::Ordering, iter, rc::Rc};
+
+use crate::{
+    bidag::{BinaryChildren, FromChildren},
+    iter::LeafCounts,
+    labeled::LabeledTermRef,
+    maps::{NodeIndex, TermMap},
+    term::{Term, TermRef},
+};
+
+impl Term {
+    /// Total order on shapes: by number of leaves, then by the pre-order sequence of nodes, with
+    /// leaves before operations.
+    pub fn shape_cmp(&self, other: &Term) -> Ordering {
+        let ordering = self.leaves().cmp(&other.leaves());
+        if ordering.is_ne() || self.shape_hash() == other.shape_hash() && self.shape_eq(other) {
+            return ordering;
+        }
+
+        let mut stack = vec![(self, other)];
+        while let Some((a, b)) = stack.pop() {
+            match (a.children(), b.children()) {
+                (None, None) => {}
+                (None, Some(_)) => return Ordering::Less,
+                (Some(_), None) => return Ordering::Greater,
+                (Some((a_left, a_right)), Some((b_left, b_right))) => {
+                    stack.push((a_right, b_right));
+                    stack.push((a_left, b_left));
+                }
+            }
+        }
+        Ordering::Equal
+    }
+
+    /// Orders the children of every node by `shape_cmp`, so terms that are equal modulo
+    /// commutativity get the same canonical term. Returns the map from `self` to it.
+    pub fn canonicalize_commutative(self: &TermRef) -> TermMap<'static> {
+        // the labels are the leaf indices in `self`
+        let labeled: LabeledTermRef<NodeIndex> = self.label_with(|i| i as NodeIndex);
+        let (canonical, canonical_labeled) = labeled.reduce(
+            &mut |_,
+                  (left, left_labeled): (TermRef, LabeledTermRef<NodeIndex>),
+                  (right, right_labeled)| {
+                if left.shape_cmp(&right).is_gt() {
+                    (
+                        Term::operation(right, left),
+                        LabeledTermRef::from_children(right_labeled, left_labeled),
+                    )
+                } else {
+                    (
+                        Term::operation(left, right),
+                        LabeledTermRef::from_children(left_labeled, right_labeled),
+                    )
+                }
+            },
+            &mut |leaf| (Rc::new(Term::Variable), leaf.clone()),
+        );
+
+        // backward[i] is the leaf of `self` that ends up at leaf i of the canonical term
+        let mut backward = Vec::with_capacity(self.leaves());
+        canonical_labeled.walk_leaves(&mut |leaf| backward.push(*leaf.label().unwrap()));
+        TermMap::new(canonical, self.clone(), backward.into()).into_backward()
+    }
+}
+
+// the terms with the children of any operation nodes swapped, `term` itself first
+fn orientations(term: &LabeledTermRef<NodeIndex>) -> Vec<LabeledTermRef<NodeIndex>> {
+    let Some((left, right)) = term.children() else {
+        return vec![term.clone()];
+    };
+    let (lefts, rights) = (orientations(left), orientations(right));
+    let mut oriented = Vec::with_capacity(2 * lefts.len() * rights.len());
+    for left in &lefts {
+        for right in &rights {
+            oriented.push(LabeledTermRef::from_children(left.clone(), right.clone()));
+        }
+    }
+    for left in &lefts {
+        for right in &rights {
+            oriented.push(LabeledTermRef::from_children(right.clone(), left.clone()));
+        }
+    }
+    oriented
+}
+
+impl TermMap<'static> {
+    /// The map with the children of any operation nodes of the source swapped, to the same
+    /// target. Matching all of them finds every instance of the source modulo commutativity,
+    /// with each way the children can be swapped to get there. `self` comes first.
+    pub fn commutative_variants(&self) -> Vec<TermMap<'static>> {
+        let (source, target) = self.labeled();
+        orientations(&source)
+            .into_iter()
+            .map(|source| source.map_to(target.clone()))
+            .collect()
+    }
+}
+
+// the terms with `leaves` leaves that `canonicalize_commutative` does not change
+fn canonical_terms(leaves: usize) -> Box<dyn Iterator<Item = TermRef>> {
+    if leaves == 1 {
+        return Box::new(iter::once(Rc::new(Term::Variable)));
+    }
+    // the left child is never bigger than the right one
+    Box::new((1..=leaves / 2).flat_map(move |left_leaves| {
+        canonical_terms(leaves - left_leaves).flat_map(move |right| {
+            canonical_terms(left_leaves).filter_map(move |left| {
+                let ordered = left.shape_cmp(&right).is_le();
+                ordered.then(|| Term::operation(left, right.clone()))
+            })
+        })
+    }))
+}
+
+/// Iterator over one term per class of terms that are equal modulo commutativity, namely the
+/// canonical one of `Term::canonicalize_commutative`, by increasing number of leaves. Built from
+/// canonical subterms instead of canonicalizing every term.
+pub struct CommutativeTermIterator {
+    terms: Box<dyn Iterator<Item = TermRef>>,
+}
+
+impl CommutativeTermIterator {
+    pub fn new(leaves: impl LeafCounts) -> Self {
+        let (min_leaves, max_leaves) = leaves.bounds();
+        assert!(min_leaves > 0, "terms have at least one leaf");
+        CommutativeTermIterator {
+            terms: Box::new((min_leaves..=max_leaves).flat_map(canonical_terms)),
+        }
+    }
+}
+
+impl Iterator for CommutativeTermIterator {
+    type Item = TermRef;
+
+    fn next(&mut self) -> Option<TermRef> {
+        self.terms.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::{iter::TermIterator, lterm, term};
+
+    #[test]
+    fn canonicalize_commutative() {
+        // Wedderburn–Etherington numbers
+        for (leaves, shapes) in [(5, 3), (7, 11)] {
+            let canonical: HashSet<_> = TermIterator::new(leaves)
+                .map(|term| term.canonicalize_commutative().target().clone())
+                .collect();
+            assert_eq!(canonical.len(), shapes);
+        }
+
+        let term = term!((a * (b * c)) * d);
+        let map = term.canonicalize_commutative();
+        let expected = lterm!((a * (b * c)) * d).map_to(lterm!(d * (a * (b * c))));
+        assert!(map.target() == expected.target());
+        assert_eq!(map.perm(), expected.perm());
+
+        let again = map.target().canonicalize_commutative();
+        assert!(again.target() == map.target());
+        assert!(again.perm().is_identity());
+    }
+
+    #[test]
+    fn commutative_variants() {
+        let equiv = lterm!(a * (b * c)).map_to(lterm!((a * c) * b));
+        let variants: Vec<_> = equiv
+            .commutative_variants()
+            .iter()
+            .map(|variant| variant.to_string())
+            .collect();
+        assert_eq!(variants.len(), 4);
+        assert_eq!(variants[0], equiv.to_string());
+        let swapped = lterm!((b * c) * a).map_to(lterm!((a * c) * b));
+        assert!(variants.contains(&swapped.to_string()));
+    }
+
+    #[test]
+    fn commutative_term_iterator() {
+        let terms: Vec<_> = CommutativeTermIterator::new(1..=9).collect();
+        let canonical: HashSet<_> = TermIterator::new(1..=9)
+            .map(|term| term.canonicalize_commutative().target().clone())
+            .collect();
+        assert_eq!(terms.len(), canonical.len());
+        assert_eq!(terms.iter().cloned().collect::<HashSet<_>>(), canonical);
+        assert_eq!(CommutativeTermIterator::new(9).count(), 46);
+    }
+}
@@ -0,0 +1,609 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, HashSet},
+    fmt::Display,
+};
+
+use crate::{
+    bidag::BinaryChildren,
+    iter::TermIterator,
+    maps::TermMap,
+    ordering::TermOrdering,
+    position::Position,
+    rewrite::{RewriteSystem, Strategy, leaf_places},
+    term::{Term, TermPointer, TermRef},
+};
+
+#[derive(Debug)]
+pub enum CompletionError {
+    /// An equation between different terms, neither of which is greater than the other.
+    Unorientable(TermMap<'static>),
+    /// The number of rules exceeded the budget.
+    TooManyRules(usize),
+    /// Normalizing a term took more steps than the budget, e.g. with rules from a heuristic
+    /// that don't terminate.
+    TooManySteps(usize),
+}
+
+impl Display for CompletionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unorientable(equation) => write!(f, "cannot orient {}", equation),
+            Self::TooManyRules(budget) => write!(f, "more than {} rules needed", budget),
+            Self::TooManySteps(budget) => {
+                write!(f, "normalization took more than {} steps", budget)
+            }
+        }
+    }
+}
+
+/// The most general unifier of two linear terms without common variables, applied to them. The
+/// leaves of each term are bound to the subterms of the other one at the same positions.
+pub fn merge(a: &TermRef, b: &TermRef) -> TermRef {
+    match (a.children(), b.children()) {
+        (None, _) => b.clone(),
+        (_, None) => a.clone(),
+        (Some((a_left, a_right)), Some((b_left, b_right))) => {
+            Term::operation(merge(a_left, b_left), merge(a_right, b_right))
+        }
+    }
+}
+
+/// An overlap of the sources of two rules, and the equation between its two rewrites.
+#[derive(Clone, Debug)]
+pub struct CriticalPair {
+    /// Where the source of the inner rule is in the source of the outer rule.
+    #[allow(dead_code)]
+    pub position: Position,
+    /// The most general term both rules apply to, the outer one at the root.
+    pub overlap: TermRef,
+    /// From the overlap rewritten by the inner rule to the overlap rewritten by the outer rule.
+    pub equation: TermMap<'static>,
+}
+
+impl CriticalPair {
+    /// Whether both rewrites give the same term with the leaves in the same places.
+    pub fn is_trivial(&self) -> bool {
+        self.equation.source() == self.equation.target() && self.equation.perm().is_identity()
+    }
+}
+
+/// The critical pairs from unifying the source of `inner` with the subterms of the source of
+/// `outer` at all operation positions, in pre-order. Trivial pairs, like the one from
+/// overlapping a rule with itself at the root, are left out.
+pub fn critical_pairs(outer: &TermMap<'_>, inner: &TermMap<'_>) -> Vec<CriticalPair> {
+    let mut pairs = Vec::new();
+    outer.source().walk_with_positions(&mut |position, node| {
+        if node.is_leaf() {
+            return;
+        }
+        let overlap = outer.source().graft(position, merge(node, inner.source()));
+        let inner_side = overlap.substitute_at(position, inner);
+        let outer_side = overlap.substitute_at(&Position::root(), outer);
+        let pair = CriticalPair {
+            position: position.clone(),
+            overlap,
+            equation: &inner_side.into_backward() * outer_side,
+        };
+        if !pair.is_trivial() {
+            pairs.push(pair);
+        }
+    });
+    pairs
+}
+
+/// Budgets of `complete`, and how it picks the next equation to process.
+#[derive(Clone, Copy, Debug)]
+pub struct CompletionLimits {
+    /// Completion fails once more rules would be needed.
+    pub max_rules: usize,
+    /// Critical pairs with a side of more leaves are dropped.
+    pub max_leaves: usize,
+    /// Completion fails once normalizing a term takes more rewrite steps. Rules from an
+    /// `OrientationHeuristic` need not terminate, so this is what stops rewriting with them.
+    pub max_steps: usize,
+    /// Out of every `weight_ratio + 1` picks, one is the oldest equation and the others are the
+    /// lightest ones, i.e. with the fewest leaves. Picking by age alone is fair but slow, picking
+    /// by weight alone may starve a heavy equation forever.
+    pub weight_ratio: usize,
+}
+
+impl Default for CompletionLimits {
+    fn default() -> Self {
+        CompletionLimits {
+            max_rules: 100,
+            max_leaves: usize::MAX,
+            max_steps: 10_000,
+            weight_ratio: 4,
+        }
+    }
+}
+
+// equations waiting to be processed, retrievable both by age and by weight
+#[derive(Default)]
+struct Pending {
+    // by age, taken ones are `None`
+    equations: Vec<Option<TermMap<'static>>>,
+    oldest: usize,
+    by_weight: BinaryHeap<Reverse<(usize, usize)>>,
+}
+
+impl Pending {
+    fn push(&mut self, equation: TermMap<'static>) {
+        let weight = equation.source().leaves();
+        self.by_weight.push(Reverse((weight, self.equations.len())));
+        self.equations.push(Some(equation));
+    }
+
+    fn pop_oldest(&mut self) -> Option<TermMap<'static>> {
+        while self.oldest < self.equations.len() {
+            self.oldest += 1;
+            if let Some(equation) = self.equations[self.oldest - 1].take() {
+                return Some(equation);
+            }
+        }
+        None
+    }
+
+    fn pop_lightest(&mut self) -> Option<TermMap<'static>> {
+        while let Some(Reverse((_, age))) = self.by_weight.pop() {
+            if let Some(equation) = self.equations[age].take() {
+                return Some(equation);
+            }
+        }
+        None
+    }
+}
+
+/// Orients equations the ordering of `complete_with` can't. The rules need not decrease in the
+/// ordering, so it's up to the heuristic that rewriting with them terminates.
+pub trait OrientationHeuristic {
+    fn orient(&self, equation: &TermMap<'static>) -> Option<TermMap<'static>>;
+}
+
+impl<F: Fn(&TermMap<'static>) -> Option<TermMap<'static>>> OrientationHeuristic for F {
+    fn orient(&self, equation: &TermMap<'static>) -> Option<TermMap<'static>> {
+        self(equation)
+    }
+}
+
+/// Rewrites the deeper side to the shallower one. Both sides of an equation have the same
+/// leaves and hence the same size, so their depth is what is left to compare. This is not a
+/// reduction ordering: with deep subterms at the variables, an instance of the deeper side can
+/// be shallower than the instance of the other side. Rewriting with the rules can then fail to
+/// terminate, which completion reports as `CompletionError::TooManySteps`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Depth;
+
+impl OrientationHeuristic for Depth {
+    fn orient(&self, equation: &TermMap<'static>) -> Option<TermMap<'static>> {
+        match equation.source().depth().cmp(&equation.target().depth()) {
+            Ordering::Greater => Some(equation.clone()),
+            Ordering::Less => Some(equation.backward()),
+            Ordering::Equal => None,
+        }
+    }
+}
+
+/// The result of `complete_with`.
+pub struct Completion {
+    pub system: RewriteSystem,
+    /// The equations that could not be oriented, in normal form. They hold in both directions
+    /// and are not used for rewriting, so normal forms only decide equivalence modulo them.
+    pub equations: Vec<TermMap<'static>>,
+}
+
+// the critical pairs between `a` and `b` in both roles
+fn overlaps(a: &TermMap<'static>, b: &TermMap<'static>) -> Vec<CriticalPair> {
+    let mut pairs = critical_pairs(a, b);
+    if !std::ptr::eq(a, b) {
+        pairs.extend(critical_pairs(b, a));
+    }
+    pairs
+}
+
+/// Knuth-Bendix completion of `equations`, which orients them into rules and adds the critical
+/// pairs that don't rewrite to the same normal form, until all of them do. The result is a
+/// terminating and confluent system for the equations, so normal forms decide equivalence. Rules
+/// whose source a new rule rewrites are turned back into equations and the targets are kept in
+/// normal form, so the result is interreduced. If critical pairs were dropped for their size, the
+/// result need not be confluent.
+#[allow(dead_code)]
+pub fn complete(
+    equations: Vec<TermMap<'static>>,
+    ordering: &impl TermOrdering,
+    limits: &CompletionLimits,
+) -> Result<RewriteSystem, CompletionError> {
+    complete_inner(equations, ordering, &[], false, limits).map(|completion| completion.system)
+}
+
+/// Like `complete`, but equations the ordering can't orient are given to the heuristics in
+/// order, and those none of them orients are kept as two-way equations instead of failing. These
+/// are overlapped with the rules in both directions, so completion goes on around them.
+pub fn complete_with(
+    equations: Vec<TermMap<'static>>,
+    ordering: &impl TermOrdering,
+    heuristics: &[&dyn OrientationHeuristic],
+    limits: &CompletionLimits,
+) -> Result<Completion, CompletionError> {
+    complete_inner(equations, ordering, heuristics, true, limits)
+}
+
+fn complete_inner(
+    equations: Vec<TermMap<'static>>,
+    ordering: &impl TermOrdering,
+    heuristics: &[&dyn OrientationHeuristic],
+    keep_unorientable: bool,
+    limits: &CompletionLimits,
+) -> Result<Completion, CompletionError> {
+    let mut system = RewriteSystem::new(Vec::new());
+    // the two-way equations, with both directions of each
+    let mut two_way: Vec<[TermMap<'static>; 2]> = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pending = Pending::default();
+    for equation in equations {
+        pending.push(equation);
+    }
+    let normalize = |system: &RewriteSystem, term: &TermRef| {
+        system
+            .normalize_within(term, Strategy::Innermost, limits.max_steps)
+            .map(|normalization| normalization.map)
+            .ok_or(CompletionError::TooManySteps(limits.max_steps))
+    };
+
+    for pick in 0.. {
+        let next = if pick % (limits.weight_ratio + 1) == 0 {
+            pending.pop_oldest()
+        } else {
+            pending.pop_lightest()
+        };
+        let Some(equation) = next else {
+            break;
+        };
+        let source = normalize(&system, equation.source())?;
+        let target = normalize(&system, equation.target())?;
+        let equation = &(&source.into_backward() * equation) * target;
+        if equation.source() == equation.target() && equation.perm().is_identity() {
+            continue;
+        }
+
+        let rule = ordering.orient(equation.clone()).or_else(|| {
+            heuristics
+                .iter()
+                .find_map(|heuristic| heuristic.orient(&equation))
+        });
+        if rule.is_none() && !keep_unorientable {
+            return Err(CompletionError::Unorientable(equation));
+        }
+        if system.len() + two_way.len() == limits.max_rules {
+            return Err(CompletionError::TooManyRules(limits.max_rules));
+        }
+
+        let mut pairs = Vec::new();
+        match rule {
+            Some(rule) => {
+                // the rules the new one rewrites the source of go back to the equations, the
+                // targets of the others are kept in normal form
+                let collapsing = RewriteSystem::new(vec![rule.clone()]);
+                let mut rules = Vec::new();
+                for old in system.rules() {
+                    if collapsing.all_redexes(old.source()).is_empty() {
+                        rules.push(old.clone());
+                    } else {
+                        pending.push(old.clone());
+                    }
+                }
+                rules.push(rule);
+                let simplifying = RewriteSystem::new(rules);
+                system = RewriteSystem::new(
+                    simplifying
+                        .rules()
+                        .iter()
+                        .map(|rule| Ok(rule * normalize(&simplifying, rule.target())?))
+                        .collect::<Result<_, _>>()?,
+                );
+                let new = system.rule(system.len() - 1);
+                for other in system.rules().iter().chain(two_way.iter().flatten()) {
+                    pairs.extend(overlaps(other, new));
+                }
+            }
+            None => {
+                let backward = equation.backward();
+                // commuting equations are their own backward direction
+                let unseen = seen.insert(leaf_places(&equation));
+                seen.insert(leaf_places(&backward));
+                if !unseen {
+                    continue;
+                }
+                for new in [&equation, &backward] {
+                    for other in system.rules() {
+                        pairs.extend(overlaps(other, new));
+                    }
+                }
+                two_way.push([equation, backward]);
+            }
+        }
+        for pair in pairs {
+            if pair.overlap.leaves() <= limits.max_leaves {
+                pending.push(pair.equation);
+            }
+        }
+    }
+
+    Ok(Completion {
+        system,
+        equations: two_way.into_iter().map(|[equation, _]| equation).collect(),
+    })
+}
+
+// The terms equivalent to `term` by applying the equivalences at any position in either
+// direction, with maps from `term` to them, in the order they are found.
+fn class_of(term: &TermRef, equivs: &[TermMap<'static>]) -> Vec<TermMap<'static>> {
+    let mut class = vec![term.identity_map()];
+    let mut seen = HashSet::from([term.clone()]);
+    let mut next = 0;
+    while let Some(map) = class.get(next).cloned() {
+        next += 1;
+        let current = map.target();
+        for equiv in equivs {
+            for matched in equiv.source().match_top_down(current) {
+                let step = &map * current.substitute_match(&matched, equiv);
+                if seen.insert(step.target().clone()) {
+                    class.push(step);
+                }
+            }
+        }
+    }
+    class
+}
+
+/// Ground completion on the terms with up to `leaves` leaves: every term rewrites to the least
+/// term of its class under `ordering`. Since the ordering is closed under contexts, the
+/// subterms of least terms are least as well, so rules are only needed for terms whose proper
+/// subterms are already normal forms. Normal forms are unique for the shapes, the leaves may end
+/// up in places that differ by automorphisms of the normal form. Rules for instances of other
+/// rules are dropped in the end, which fails if a target takes more than `max_steps` steps to
+/// normalize, as it can if `ordering` is not closed under contexts after all.
+pub fn ground_complete(
+    equivs: &[TermMap<'static>],
+    leaves: usize,
+    ordering: &impl TermOrdering,
+    max_steps: usize,
+) -> Result<RewriteSystem, CompletionError> {
+    let equivs: Vec<_> = equivs
+        .iter()
+        .flat_map(|equiv| [equiv.clone(), equiv.backward()])
+        .collect();
+    let mut rules = Vec::new();
+    let mut normal_forms: HashSet<TermRef> = HashSet::new();
+
+    for leaves in 1..=leaves {
+        let mut representatives: HashMap<TermRef, TermMap<'static>> = HashMap::new();
+        for term in TermIterator::new(leaves) {
+            if representatives.contains_key(&term) {
+                continue;
+            }
+            let class = class_of(&term, &equivs);
+            let least = ordering
+                .least(class.iter().map(|map| map.target()))
+                .unwrap()
+                .clone();
+            let to_least = class.iter().find(|map| *map.target() == least).unwrap();
+            for map in &class {
+                representatives.insert(map.target().clone(), &map.backward() * to_least);
+            }
+            normal_forms.insert(least);
+        }
+
+        for term in TermIterator::new(leaves) {
+            let Some((left, right)) = term.children() else {
+                continue;
+            };
+            if !normal_forms.contains(&term)
+                && normal_forms.contains(left)
+                && normal_forms.contains(right)
+            {
+                rules.push(representatives.remove(&term).unwrap());
+            }
+        }
+    }
+    RewriteSystem::new(rules)
+        .interreduce(max_steps)
+        .ok_or(CompletionError::TooManySteps(max_steps))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        iter::TermIterator,
+        labeled::LabeledTermRef,
+        lterm,
+        maps::NodeIndex,
+        ordering::{Kbo, Lpo},
+        rewrite::Strategy,
+        term,
+    };
+
+    #[test]
+    fn complete() {
+        let associativity = lterm!((a * b) * c).map_to(lterm!(a * (b * c)));
+        let system = super::complete(
+            vec![associativity.backward()],
+            &Kbo::default(),
+            &CompletionLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(system.len(), 1);
+        assert_eq!(system.rule(0).to_string(), associativity.to_string());
+
+        // the normal forms decide equivalence modulo associativity
+        let right_comb = system.normalize(&term!(a * (b * (c * (d * e)))), Strategy::Innermost);
+        for term in TermIterator::new(5) {
+            let normalized = system.normalize(&term, Strategy::Innermost);
+            assert_eq!(normalized.normal_form(), right_comb.normal_form());
+            assert!(normalized.map.perm().is_identity());
+        }
+
+        // the rule for associativity, picked second, rewrites the source of the first one
+        let pairs = lterm!((a * b) * (c * d)).map_to(lterm!(a * (b * (c * d))));
+        let system = super::complete(
+            vec![pairs.clone(), associativity.clone()],
+            &Kbo::default(),
+            &CompletionLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(system.len(), 1);
+        assert_eq!(system.rule(0).to_string(), associativity.to_string());
+        // the collapsed rule is normalized as an equation again, which takes a step
+        let limits = CompletionLimits {
+            max_steps: 0,
+            ..CompletionLimits::default()
+        };
+        assert!(matches!(
+            super::complete(vec![pairs, associativity.clone()], &Kbo::default(), &limits),
+            Err(CompletionError::TooManySteps(0))
+        ));
+
+        // every rule is a specialization of the previous one with one leaf more
+        let diverging = lterm!(((a * b) * c) * d).map_to(lterm!((a * b) * (c * d)));
+        let limits = CompletionLimits {
+            max_rules: 4,
+            ..CompletionLimits::default()
+        };
+        assert!(matches!(
+            super::complete(vec![diverging.clone()], &Kbo::default(), &limits),
+            Err(CompletionError::TooManyRules(4))
+        ));
+        // unless the larger critical pairs are dropped, at the cost of confluence
+        let limits = CompletionLimits {
+            max_leaves: 6,
+            ..limits
+        };
+        let system = super::complete(vec![diverging], &Kbo::default(), &limits).unwrap();
+        assert!(system.len() <= 4);
+        assert!(!system.is_locally_confluent(2));
+
+        let commutativity = lterm!((a * b) * c).map_to(lterm!(b * (a * c)));
+        let Err(CompletionError::Unorientable(equation)) = super::complete(
+            vec![commutativity],
+            &Lpo::default(),
+            &CompletionLimits::default(),
+        ) else {
+            panic!("commuting leaves is not orientable");
+        };
+        assert_eq!(equation.source(), equation.target());
+    }
+
+    #[test]
+    fn complete_with() {
+        let associativity = lterm!((a * b) * c).map_to(lterm!(a * (b * c)));
+        let commutativity = lterm!(a * b).map_to(lterm!(b * a));
+        let limits = CompletionLimits {
+            max_leaves: 4,
+            ..CompletionLimits::default()
+        };
+        let completion = super::complete_with(
+            vec![associativity.clone(), commutativity.clone()],
+            &Kbo::default(),
+            &[&Depth],
+            &limits,
+        )
+        .unwrap();
+        assert_eq!(completion.system.len(), 1);
+        assert_eq!(
+            completion.system.rule(0).to_string(),
+            associativity.to_string()
+        );
+        // commuting the right comb anywhere is left as an equation
+        assert!(completion.equations.iter().any(|equation| {
+            equation.source() == commutativity.source() && !equation.perm().is_identity()
+        }));
+        assert!(
+            completion
+                .equations
+                .iter()
+                .all(|equation| equation.source() == equation.target())
+        );
+
+        // an ordering that orients nothing leaves everything to the heuristics
+        struct Never;
+        impl TermOrdering for Never {
+            fn greater(
+                &self,
+                _: &LabeledTermRef<NodeIndex>,
+                _: &LabeledTermRef<NodeIndex>,
+            ) -> bool {
+                false
+            }
+        }
+        let balancing = lterm!(((a * b) * c) * d).map_to(lterm!((a * b) * (c * d)));
+        let by_depth =
+            super::complete_with(vec![balancing.backward()], &Never, &[&Depth], &limits).unwrap();
+        assert_eq!(by_depth.system.rule(0).to_string(), balancing.to_string());
+        let reversed = |equation: &TermMap<'static>| Some(equation.backward());
+        let by_callback =
+            super::complete_with(vec![associativity.clone()], &Never, &[&reversed], &limits)
+                .unwrap();
+        assert_eq!(by_callback.system.rule(0).source(), associativity.target());
+    }
+
+    #[test]
+    fn critical_pairs() {
+        let associativity = lterm!((a * b) * c).map_to(lterm!(a * (b * c)));
+        let pairs = super::critical_pairs(&associativity, &associativity);
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0].position.to_string(), "L");
+        assert_eq!(pairs[0].overlap, term!(((a * b) * c) * d));
+        assert_eq!(
+            pairs[0].equation.to_string(),
+            "(0 * (1 * 2)) * 3 -> (0 * 1) * (2 * 3)"
+        );
+
+        // the overlaps at the root and at the left child
+        let rotation = lterm!((a * b) * c).map_to(lterm!(c * (a * b)));
+        let medial = lterm!((a * b) * (c * d)).map_to(lterm!((a * c) * (b * d)));
+        let pairs = super::critical_pairs(&rotation, &medial);
+        let positions: Vec<_> = pairs.iter().map(|pair| pair.position.to_string()).collect();
+        assert_eq!(positions, ["ε", "L"]);
+        assert_eq!(pairs[0].overlap, term!((a * b) * (c * d)));
+        for pair in &pairs {
+            assert_eq!(pair.equation.source().leaves(), pair.overlap.leaves());
+        }
+    }
+
+    #[test]
+    fn ground_complete() {
+        let equivs = [
+            lterm!((a * b) * c).map_to(lterm!(a * (b * c))),
+            lterm!((a * b) * (c * d)).map_to(lterm!((c * a) * (b * d))),
+        ];
+        let associativity = super::ground_complete(&equivs[..1], 4, &Kbo::default(), 100).unwrap();
+        let sources: Vec<_> = associativity
+            .rules()
+            .iter()
+            .map(|rule| rule.source().to_string())
+            .collect();
+        assert_eq!(sources, ["(0 * 1) * 2"]);
+
+        // the normal forms classify the terms like the equivalence classes do
+        let system = super::ground_complete(&equivs, 6, &Kbo::default(), 100).unwrap();
+        let terms: Vec<_> = TermIterator::new(6).collect();
+        let normal_forms: Vec<_> = terms
+            .iter()
+            .map(|term| system.normalize(term, Strategy::Outermost).map)
+            .collect();
+        let both_ways: Vec<_> = equivs
+            .iter()
+            .flat_map(|equiv| [equiv.clone(), equiv.backward()])
+            .collect();
+        for (term, normal_form) in terms.iter().zip(&normal_forms) {
+            let class = class_of(term, &both_ways);
+            assert!(class.iter().any(|map| map.target() == normal_form.target()));
+            assert_eq!(
+                Kbo::default().least(class.iter().map(|map| map.target())),
+                Some(normal_form.target())
+            );
+        }
+    }
+}
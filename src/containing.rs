@@ -0,0 +1,344 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    iter,
+    rc::Rc,
+};
+
+use crate::{
+    bidag::BinaryChildren,
+    indexing::Match,
+    iter::LeafCounts,
+    patindex::PatternId,
+    position::Position,
+    term::{Term, TermPointer, TermRef},
+};
+
+// the distinct operation subterms of some patterns, so the ones a term is an instance of follow
+// from those of its children
+struct Subpatterns {
+    patterns: Vec<TermRef>,
+    // the children of every operation subpattern, `None` for a variable
+    children: Vec<(Option<usize>, Option<usize>)>,
+    // the subpattern of every pattern, `None` for a variable
+    roots: Vec<Option<usize>>,
+}
+
+impl Subpatterns {
+    fn new(patterns: &[TermRef]) -> Self {
+        let mut subpatterns = Subpatterns {
+            patterns: patterns.to_vec(),
+            children: Vec::new(),
+            roots: Vec::new(),
+        };
+        let mut indices = HashMap::new();
+        for pattern in patterns {
+            let root = subpatterns.index(pattern, &mut indices);
+            subpatterns.roots.push(root);
+        }
+        subpatterns
+    }
+
+    // the index of the operation subpattern, after adding it and its own if they are new
+    fn index(&mut self, pattern: &TermRef, indices: &mut HashMap<TermRef, usize>) -> Option<usize> {
+        let (left, right) = pattern.children()?;
+        if let Some(&index) = indices.get(pattern) {
+            return Some(index);
+        }
+        let children = (self.index(left, indices), self.index(right, indices));
+        assert!(self.children.len() < 64, "too many distinct subpatterns");
+        self.children.push(children);
+        indices.insert(pattern.clone(), self.children.len() - 1);
+        Some(self.children.len() - 1)
+    }
+
+    // the operation subpatterns an operation is an instance of, from those of its children, as
+    // bits by index
+    fn instances(&self, left: u64, right: u64) -> u64 {
+        let instance = |child: Option<usize>, instances: u64| {
+            child.is_none_or(|index| instances >> index & 1 == 1)
+        };
+        let mut instances = 0;
+        for (index, &(left_child, right_child)) in self.children.iter().enumerate() {
+            if instance(left_child, left) && instance(right_child, right) {
+                instances |= 1 << index;
+            }
+        }
+        instances
+    }
+
+    // whether pattern `id` matches at the root of a term that is an instance of `instances`
+    fn matches(&self, id: PatternId, instances: u64) -> bool {
+        self.roots[id].is_none_or(|index| instances >> index & 1 == 1)
+    }
+}
+
+// what matters about a term for which patterns its parents contain: either it already contains
+// one, or the operation subpatterns it is an instance of
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum State {
+    Instances(u64),
+    Contains,
+}
+
+// the number of leaves on the left and the states of both children
+type Split = (usize, State, State);
+
+// the ways to build terms of each state from two smaller ones, by number of leaves
+struct Table {
+    subpatterns: Subpatterns,
+    splits: Vec<BTreeMap<State, Vec<Split>>>,
+}
+
+impl Table {
+    fn new(patterns: &[TermRef], max_leaves: usize) -> Self {
+        let mut table = Table {
+            subpatterns: Subpatterns::new(patterns),
+            splits: vec![BTreeMap::new()],
+        };
+        let variable = (0..patterns.len()).any(|id| table.subpatterns.matches(id, 0));
+        let leaf = if variable {
+            State::Contains
+        } else {
+            State::Instances(0)
+        };
+        table.splits.push(BTreeMap::from([(leaf, Vec::new())]));
+        for leaves in 2..=max_leaves {
+            let mut splits: BTreeMap<_, Vec<_>> = BTreeMap::new();
+            for left_leaves in 1..leaves {
+                for &left in table.splits[left_leaves].keys() {
+                    for &right in table.splits[leaves - left_leaves].keys() {
+                        splits.entry(table.combine(left, right)).or_default().push((
+                            left_leaves,
+                            left,
+                            right,
+                        ));
+                    }
+                }
+            }
+            table.splits.push(splits);
+        }
+        table
+    }
+
+    // the state of the operation on terms in these states
+    fn combine(&self, left: State, right: State) -> State {
+        let (State::Instances(left), State::Instances(right)) = (left, right) else {
+            return State::Contains;
+        };
+        let instances = self.subpatterns.instances(left, right);
+        let patterns = self.subpatterns.patterns.len();
+        if (0..patterns).any(|id| self.subpatterns.matches(id, instances)) {
+            State::Contains
+        } else {
+            State::Instances(instances)
+        }
+    }
+}
+
+// all terms with `leaves` leaves in `state`
+fn terms(table: &Rc<Table>, leaves: usize, state: State) -> Box<dyn Iterator<Item = TermRef>> {
+    if leaves == 1 {
+        return Box::new(iter::once(Rc::new(Term::Variable)));
+    }
+    let table = table.clone();
+    let splits = table.splits[leaves][&state].clone();
+    Box::new(
+        splits
+            .into_iter()
+            .flat_map(move |(left_leaves, left, right)| {
+                let table = table.clone();
+                terms(&table, leaves - left_leaves, right).flat_map(move |right| {
+                    terms(&table, left_leaves, left)
+                        .map(move |left| Term::operation(left, right.clone()))
+                })
+            }),
+    )
+}
+
+/// Iterator over the terms that contain an instance of at least one of the patterns, by
+/// increasing number of leaves. The terms are built from smaller ones that either contain a
+/// pattern or can still become part of an instance, so the others are never constructed.
+pub struct ContainingTermIterator {
+    terms: Box<dyn Iterator<Item = TermRef>>,
+}
+
+impl ContainingTermIterator {
+    pub fn new(leaves: impl LeafCounts, patterns: &[TermRef]) -> Self {
+        let (min_leaves, max_leaves) = leaves.bounds();
+        assert!(min_leaves > 0, "terms have at least one leaf");
+        let table = Rc::new(Table::new(patterns, max_leaves));
+        let terms = (min_leaves..=max_leaves).flat_map(move |leaves| {
+            let contained = table.splits[leaves].contains_key(&State::Contains);
+            contained
+                .then(|| terms(&table, leaves, State::Contains))
+                .into_iter()
+                .flatten()
+        });
+        ContainingTermIterator {
+            terms: Box::new(terms),
+        }
+    }
+}
+
+impl Iterator for ContainingTermIterator {
+    type Item = TermRef;
+
+    fn next(&mut self) -> Option<TermRef> {
+        self.terms.next()
+    }
+}
+
+// a term with the operation subpatterns it is an instance of and all matches in it
+struct Matched {
+    term: TermRef,
+    instances: u64,
+    matches: Vec<(PatternId, Match)>,
+}
+
+impl Matched {
+    fn leaf(subpatterns: &Subpatterns) -> Self {
+        let term = Rc::new(Term::Variable);
+        let matches = (0..subpatterns.patterns.len())
+            .filter(|&id| subpatterns.matches(id, 0))
+            .map(|id| (id, root_match(&term, &subpatterns.patterns[id])))
+            .collect();
+        Matched {
+            term,
+            instances: 0,
+            matches,
+        }
+    }
+
+    // the matches at the root, then those in the children in pre-order
+    fn operation(subpatterns: &Subpatterns, left: &Matched, right: &Matched) -> Self {
+        let term = Term::operation(left.term.clone(), right.term.clone());
+        let instances = subpatterns.instances(left.instances, right.instances);
+        let mut matches: Vec<_> = (0..subpatterns.patterns.len())
+            .filter(|&id| subpatterns.matches(id, instances))
+            .map(|id| (id, root_match(&term, &subpatterns.patterns[id])))
+            .collect();
+        for (child, prefix) in [
+            (left, Position::root().left()),
+            (right, Position::root().right()),
+        ] {
+            matches.extend(child.matches.iter().map(|(id, matched)| {
+                let matched = Match {
+                    position: prefix.concat(&matched.position),
+                    ..matched.clone()
+                };
+                (*id, matched)
+            }));
+        }
+        Matched {
+            term,
+            instances,
+            matches,
+        }
+    }
+}
+
+fn root_match(term: &TermRef, pattern: &TermRef) -> Match {
+    Match {
+        position: Position::root(),
+        root: term.clone(),
+        bindings: pattern.instance_bindings(term).unwrap(),
+    }
+}
+
+// all terms with `leaves` leaves with their matches, in the order of `TermIterator`
+fn matched_terms(
+    subpatterns: &Rc<Subpatterns>,
+    leaves: usize,
+) -> Box<dyn Iterator<Item = Rc<Matched>>> {
+    if leaves == 1 {
+        return Box::new(iter::once(Rc::new(Matched::leaf(subpatterns))));
+    }
+    let subpatterns = subpatterns.clone();
+    Box::new((1..leaves).flat_map(move |left_leaves| {
+        let subpatterns = subpatterns.clone();
+        matched_terms(&subpatterns, leaves - left_leaves).flat_map(move |right| {
+            let subpatterns = subpatterns.clone();
+            matched_terms(&subpatterns, left_leaves)
+                .map(move |left| Rc::new(Matched::operation(&subpatterns, &left, &right)))
+        })
+    }))
+}
+
+/// Iterator over the terms of `TermIterator` together with all matches of the patterns in them,
+/// like those of `PatternAutomaton::matches` for linear patterns. The matches of a term are put
+/// together from those of its subterms, which are enumerated along with them, instead of matching
+/// every term from scratch.
+pub struct MatchingTermIterator {
+    terms: Box<dyn Iterator<Item = Rc<Matched>>>,
+}
+
+impl MatchingTermIterator {
+    pub fn new(leaves: impl LeafCounts, patterns: &[TermRef]) -> Self {
+        let (min_leaves, max_leaves) = leaves.bounds();
+        assert!(min_leaves > 0, "terms have at least one leaf");
+        let subpatterns = Rc::new(Subpatterns::new(patterns));
+        MatchingTermIterator {
+            terms: Box::new(
+                (min_leaves..=max_leaves)
+                    .flat_map(move |leaves| matched_terms(&subpatterns, leaves)),
+            ),
+        }
+    }
+}
+
+impl Iterator for MatchingTermIterator {
+    type Item = (TermRef, Vec<(PatternId, Match)>);
+
+    fn next(&mut self) -> Option<(TermRef, Vec<(PatternId, Match)>)> {
+        let matched = self.terms.next()?;
+        Some((matched.term.clone(), matched.matches.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::{indexing::PatternAutomaton, iter::TermIterator, term};
+
+    #[test]
+    fn containing_term_iterator() {
+        let pattern_sets = [
+            vec![term!((a * b) * c)],
+            vec![term!(a * ((b * c) * d)), term!((a * b) * (c * d))],
+            vec![term!(a)],
+        ];
+        for patterns in pattern_sets {
+            let automaton = PatternAutomaton::new(patterns.clone());
+            let expected: Vec<_> = TermIterator::new(1..=7)
+                .filter(|term| !automaton.matches(term).is_empty())
+                .collect();
+            let terms: Vec<_> = ContainingTermIterator::new(1..=7, &patterns).collect();
+            assert_eq!(terms.len(), expected.len());
+            assert_eq!(
+                terms.iter().collect::<HashSet<_>>(),
+                expected.iter().collect::<HashSet<_>>()
+            );
+            assert!(terms.is_sorted_by_key(|term| term.leaves()));
+        }
+    }
+
+    #[test]
+    fn matching_term_iterator() {
+        let patterns = vec![term!((a * b) * c), term!(a * b), term!((a * b) * (c * d))];
+        let automaton = PatternAutomaton::new(patterns.clone());
+        let matched = MatchingTermIterator::new(1..=7, &patterns);
+        for ((term, matches), expected) in matched.zip(TermIterator::new(1..=7)) {
+            assert_eq!(term, expected);
+            let expected = automaton.matches(&term);
+            assert_eq!(matches.len(), expected.len());
+            for ((id, matched), (expected_id, expected)) in matches.iter().zip(&expected) {
+                assert_eq!(id, expected_id);
+                assert_eq!(matched.position, expected.position);
+                assert!(Rc::ptr_eq(&matched.root, &expected.root));
+                assert_eq!(matched.bindings.len(), expected.bindings.len());
+            }
+        }
+    }
+}
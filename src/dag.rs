@@ -0,0 +1,232 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::labeled::{LabeledTerm, LabeledTermRef};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Node {
+    Variable(usize),
+    Operation(usize, usize),
+}
+
+// a distinct labeled term, made of the ones before it
+struct Entry {
+    node: Node,
+    // the distinct subterms including itself, sorted
+    subterms: Vec<usize>,
+    variables: usize,
+}
+
+/// Iterator over the labeled terms with at most a number of distinct subterms, which is the
+/// number of nodes when equal subterms are shared. The variables are numbered in the order they
+/// first occur from left to right, so every term appears once up to renaming its variables, and
+/// equal subterms are the same node. The terms come by increasing number of nodes, and their
+/// numbers of leaves can be exponential in it, like that of `((x * x) * (x * x)) * ...`.
+pub struct DagIterator {
+    entries: Vec<Entry>,
+    ids: HashMap<Node, usize>,
+    // the terms by number of nodes, as far as they are known
+    sizes: Vec<Vec<usize>>,
+    max_nodes: usize,
+    // the number of nodes and the index of the next term
+    next: (usize, usize),
+    built: Vec<Option<LabeledTermRef<usize>>>,
+}
+
+impl DagIterator {
+    pub fn new(max_nodes: usize) -> Self {
+        let mut iter = DagIterator {
+            entries: Vec::new(),
+            ids: HashMap::new(),
+            sizes: vec![Vec::new(); max_nodes + 1],
+            max_nodes,
+            next: (1, 0),
+            built: Vec::new(),
+        };
+        if max_nodes > 0 {
+            let variable = iter.intern(Node::Variable(0));
+            iter.sizes[1].push(variable);
+        }
+        iter
+    }
+
+    fn intern(&mut self, node: Node) -> usize {
+        if let Some(&id) = self.ids.get(&node) {
+            return id;
+        }
+        let id = self.entries.len();
+        let (mut subterms, variables) = match node {
+            Node::Variable(variable) => (Vec::new(), variable + 1),
+            Node::Operation(left, right) => {
+                let (left, right) = (&self.entries[left], &self.entries[right]);
+                let mut subterms = left.subterms.clone();
+                subterms.extend(&right.subterms);
+                subterms.sort_unstable();
+                subterms.dedup();
+                (subterms, left.variables.max(right.variables))
+            }
+        };
+        subterms.push(id);
+        self.entries.push(Entry {
+            node,
+            subterms,
+            variables,
+        });
+        self.ids.insert(node, id);
+        id
+    }
+
+    // the term with its variables renamed by `renaming`
+    fn rename(&mut self, id: usize, renaming: &[usize]) -> usize {
+        let node = match self.entries[id].node {
+            Node::Variable(variable) => Node::Variable(renaming[variable]),
+            Node::Operation(left, right) => {
+                Node::Operation(self.rename(left, renaming), self.rename(right, renaming))
+            }
+        };
+        self.intern(node)
+    }
+
+    // the renamings of `variables` variables of a right child that keep the numbering in the
+    // order of first occurrence, with `used` variables on the left: each is one of those on the
+    // left that no other takes, or the next new one
+    fn renamings(variables: usize, used: usize) -> Vec<Vec<usize>> {
+        let mut renamings = vec![Vec::new()];
+        for _ in 0..variables {
+            let mut extended = Vec::new();
+            for renaming in renamings {
+                let fresh = used
+                    + renaming
+                        .iter()
+                        .filter(|&&variable| variable >= used)
+                        .count();
+                for variable in (0..used).chain([fresh]) {
+                    if !renaming.contains(&variable) {
+                        let mut renaming = renaming.clone();
+                        renaming.push(variable);
+                        extended.push(renaming);
+                    }
+                }
+            }
+            renamings = extended;
+        }
+        renamings
+    }
+
+    // builds the operations whose bigger child has `nodes` nodes, once all terms with up to that
+    // many nodes are known
+    fn combine(&mut self, nodes: usize) {
+        let known: Vec<usize> = (1..=nodes)
+            .flat_map(|size| self.sizes[size].clone())
+            .collect();
+        for &left in &known {
+            for &right in &known {
+                let bigger = self.entries[left]
+                    .subterms
+                    .len()
+                    .max(self.entries[right].subterms.len());
+                if bigger != nodes {
+                    continue;
+                }
+                let (used, variables) =
+                    (self.entries[left].variables, self.entries[right].variables);
+                for renaming in Self::renamings(variables, used) {
+                    let right = self.rename(right, &renaming);
+                    let id = self.intern(Node::Operation(left, right));
+                    let size = self.entries[id].subterms.len();
+                    if size <= self.max_nodes {
+                        self.sizes[size].push(id);
+                    }
+                }
+            }
+        }
+    }
+
+    // the term with its equal subterms shared
+    fn build(&mut self, id: usize) -> LabeledTermRef<usize> {
+        if self.built.len() <= id {
+            self.built.resize(id + 1, None);
+        }
+        if let Some(term) = &self.built[id] {
+            return term.clone();
+        }
+        let term = match self.entries[id].node {
+            Node::Variable(variable) => Rc::new(LabeledTerm::Variable(variable)),
+            Node::Operation(left, right) => {
+                Rc::new(LabeledTerm::Operation(self.build(left), self.build(right)))
+            }
+        };
+        self.built[id] = Some(term.clone());
+        term
+    }
+}
+
+impl Iterator for DagIterator {
+    type Item = LabeledTermRef<usize>;
+
+    fn next(&mut self) -> Option<LabeledTermRef<usize>> {
+        let (nodes, index) = self.next;
+        if nodes > self.max_nodes {
+            return None;
+        }
+        if let Some(&id) = self.sizes[nodes].get(index) {
+            self.next = (nodes, index + 1);
+            return Some(self.build(id));
+        }
+        // all terms with `nodes` nodes are known by now
+        if nodes < self.max_nodes {
+            self.combine(nodes);
+        }
+        self.next = (nodes + 1, 0);
+        self.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::iter::{LabeledTermIterator, Labeling};
+
+    fn subterms(term: &LabeledTermRef<usize>, found: &mut HashSet<String>) {
+        found.insert(term.to_string());
+        if let LabeledTerm::Operation(left, right) = term.as_ref() {
+            subterms(left, found);
+            subterms(right, found);
+        }
+    }
+
+    fn nodes(term: &LabeledTermRef<usize>) -> usize {
+        let mut distinct = HashSet::new();
+        subterms(term, &mut distinct);
+        distinct.len()
+    }
+
+    #[test]
+    fn dag_iterator() {
+        let terms: Vec<_> = DagIterator::new(3).map(|term| term.to_string()).collect();
+        assert_eq!(terms.len(), 1 + 1 + 4);
+        assert_eq!(terms[..2], ["0", "0 * 0"]);
+
+        // equal subterms are shared
+        let square = DagIterator::new(3)
+            .find(|term| term.to_string() == "(0 * 0) * (0 * 0)")
+            .unwrap();
+        let LabeledTerm::Operation(left, right) = square.as_ref() else {
+            panic!("not an operation");
+        };
+        assert!(Rc::ptr_eq(left, right));
+
+        // terms with at most four nodes have at most eight leaves and two variables
+        let dags: Vec<_> = DagIterator::new(4).collect();
+        assert!(dags.is_sorted_by_key(nodes));
+        let expected: HashSet<_> =
+            LabeledTermIterator::new(1..=8, vec![0, 1], Labeling::UpToRenaming)
+                .filter(|term| nodes(term) <= 4)
+                .map(|term| term.to_string())
+                .collect();
+        let strings: HashSet<_> = dags.iter().map(|term| term.to_string()).collect();
+        assert_eq!(strings.len(), dags.len());
+        assert_eq!(strings, expected);
+    }
+}
@@ -0,0 +1,64 @@
+use std::{fmt::Display, rc::Rc};
+
+use crate::{
+    bidag::BinaryChildren,
+    position::Position,
+    term::{Term, TermRef},
+};
+
+/// Replacing `left` by `right` at `position` is one step from one term to the other.
+#[derive(Clone, Debug)]
+pub struct Difference {
+    pub position: Position,
+    pub left: TermRef,
+    pub right: TermRef,
+}
+
+impl Term {
+    /// The positions where the two terms differ, in pre-order. Descends as long as both terms
+    /// have an operation, so every difference has a leaf on at least one side and replacing all
+    /// of them turns `self` into `other`.
+    pub fn diff(self: &TermRef, other: &TermRef) -> Vec<Difference> {
+        let mut differences = Vec::new();
+        let mut stack = vec![(Position::root(), self, other)];
+
+        while let Some((position, left, right)) = stack.pop() {
+            if Rc::ptr_eq(left, right) || left == right {
+                continue;
+            }
+            match (left.children(), right.children()) {
+                (Some((left_left, left_right)), Some((right_left, right_right))) => {
+                    stack.push((position.right(), left_right, right_right));
+                    stack.push((position.left(), left_left, right_left));
+                }
+                _ => differences.push(Difference {
+                    position,
+                    left: left.clone(),
+                    right: right.clone(),
+                }),
+            }
+        }
+        differences
+    }
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} -> {}", self.position, self.left, self.right)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::term;
+
+    #[test]
+    fn diff() {
+        let a = term!((a * b) * ((c * d) * e));
+        let b = term!((a * b) * (c * (d * (e * f))));
+        let differences: Vec<_> = a.diff(&b).iter().map(|d| d.to_string()).collect();
+        assert_eq!(differences, ["RL: 0 * 1 -> 0", "RR: 0 -> 0 * (1 * 2)"]);
+        assert!(a.diff(&a.counted_clone().0).is_empty());
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+
+use crate::{bidag::BinaryChildren, maps::TermMap, term::TermRef};
+
+pub type ClassId = usize;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum ENode {
+    Leaf,
+    Operation(ClassId, ClassId),
+}
+
+/// Classes of equivalent terms, closed under congruence, where every class is a set of nodes whose
+/// children are classes. Terms with a common subterm share its class, so an equivalence applied
+/// to a class applies to all of them at once. Only tracks which shapes are equivalent, not how
+/// their leaves correspond.
+#[derive(Default)]
+pub struct EGraph {
+    // union-find forest, roots are their own parent
+    parents: Vec<ClassId>,
+    // the nodes of every root class, empty for the others
+    nodes: Vec<Vec<ENode>>,
+    memo: HashMap<ENode, ClassId>,
+}
+
+impl EGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of classes.
+    pub fn len(&self) -> usize {
+        self.classes().count()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
+
+    pub fn classes(&self) -> impl Iterator<Item = ClassId> + '_ {
+        (0..self.parents.len()).filter(|&class| self.parents[class] == class)
+    }
+
+    pub fn find(&self, mut class: ClassId) -> ClassId {
+        while self.parents[class] != class {
+            class = self.parents[class];
+        }
+        class
+    }
+
+    fn canonical(&self, node: ENode) -> ENode {
+        match node {
+            ENode::Leaf => ENode::Leaf,
+            ENode::Operation(left, right) => ENode::Operation(self.find(left), self.find(right)),
+        }
+    }
+
+    fn add_node(&mut self, node: ENode) -> ClassId {
+        let node = self.canonical(node);
+        if let Some(&class) = self.memo.get(&node) {
+            return self.find(class);
+        }
+        let class = self.parents.len();
+        self.parents.push(class);
+        self.nodes.push(vec![node]);
+        self.memo.insert(node, class);
+        class
+    }
+
+    /// Adds `term` and all its subterms, returns the class of `term`.
+    pub fn add(&mut self, term: &TermRef) -> ClassId {
+        let leaf = self.add_node(ENode::Leaf);
+        term.reduce_shared(
+            &mut |_, left, right| self.add_node(ENode::Operation(left, right)),
+            &mut |_| leaf,
+        )
+    }
+
+    /// The class of `term`, `None` if it is not in the graph.
+    pub fn lookup(&self, term: &TermRef) -> Option<ClassId> {
+        term.reduce(
+            &mut |_, left: Option<ClassId>, right| {
+                let node = self.canonical(ENode::Operation(left?, right?));
+                self.memo.get(&node).map(|&class| self.find(class))
+            },
+            &mut |_| self.memo.get(&ENode::Leaf).copied(),
+        )
+    }
+
+    /// Merges the classes. Until the next `rebuild`, congruent nodes may still be in different
+    /// classes. Returns whether the classes were different.
+    pub fn union(&mut self, a: ClassId, b: ClassId) -> bool {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return false;
+        }
+        if self.nodes[a].len() < self.nodes[b].len() {
+            std::mem::swap(&mut a, &mut b);
+        }
+        self.parents[b] = a;
+        let nodes = std::mem::take(&mut self.nodes[b]);
+        self.nodes[a].extend(nodes);
+        true
+    }
+
+    /// Restores congruence closure, i.e. merges the classes of nodes whose children have been
+    /// merged, until no such nodes are left.
+    pub fn rebuild(&mut self) {
+        loop {
+            let mut memo = HashMap::new();
+            let mut merges = Vec::new();
+            for class in 0..self.nodes.len() {
+                let nodes = std::mem::take(&mut self.nodes[class]);
+                let mut canonical: Vec<_> =
+                    nodes.into_iter().map(|node| self.canonical(node)).collect();
+                canonical.sort_unstable_by_key(|node| match node {
+                    ENode::Leaf => (0, 0, 0),
+                    ENode::Operation(left, right) => (1, *left, *right),
+                });
+                canonical.dedup();
+                for &node in &canonical {
+                    let other = *memo.entry(node).or_insert(class);
+                    if other != class {
+                        merges.push((other, class));
+                    }
+                }
+                self.nodes[class] = canonical;
+            }
+            self.memo = memo;
+
+            let mut changed = false;
+            for (a, b) in merges {
+                changed |= self.union(a, b);
+            }
+            if !changed {
+                return;
+            }
+        }
+    }
+
+    // the bindings of the leaves of `pattern` for every way it matches a term in `class`
+    fn ematch(&self, pattern: &TermRef, class: ClassId) -> Vec<Vec<ClassId>> {
+        let Some((pattern_left, pattern_right)) = pattern.children() else {
+            return vec![vec![class]];
+        };
+        let mut found = Vec::new();
+        for node in &self.nodes[self.find(class)] {
+            let ENode::Operation(left, right) = *node else {
+                continue;
+            };
+            let right_matches = self.ematch(pattern_right, right);
+            for left_bindings in self.ematch(pattern_left, left) {
+                for right_bindings in &right_matches {
+                    let mut bindings = left_bindings.clone();
+                    bindings.extend(right_bindings);
+                    found.push(bindings);
+                }
+            }
+        }
+        found
+    }
+
+    // adds `term` with its leaves replaced by the classes in `bindings`, in order
+    fn instantiate(&mut self, term: &TermRef, bindings: &[ClassId]) -> ClassId {
+        let mut leaves = bindings.iter();
+        term.reduce(
+            &mut |_, left, right| self.add_node(ENode::Operation(left, right)),
+            &mut |_| *leaves.next().unwrap(),
+        )
+    }
+
+    /// Applies the equivalences to all classes in both directions until no classes are merged
+    /// anymore. Returns the number of rounds. Since the equivalences keep the number of leaves,
+    /// there are finitely many terms to be added and this terminates.
+    pub fn saturate(&mut self, equivs: &[TermMap<'_>]) -> usize {
+        let backwards: Vec<_> = equivs.iter().map(|equiv| equiv.backward()).collect();
+        let mut rounds = 0;
+        loop {
+            rounds += 1;
+            let mut matches = Vec::new();
+            for class in self.classes() {
+                for equiv in equivs.iter().chain(&backwards) {
+                    for bindings in self.ematch(equiv.source(), class) {
+                        matches.push((class, equiv, bindings));
+                    }
+                }
+            }
+
+            let mut changed = false;
+            for (class, equiv, bindings) in matches {
+                // every leaf of the target is bound like the leaf of the source it comes from
+                let backward = equiv.perm().inverse();
+                let target_bindings: Vec<_> = (0..bindings.len())
+                    .map(|leaf| bindings[backward.get(leaf as _) as usize])
+                    .collect();
+                let target = self.instantiate(equiv.target(), &target_bindings);
+                changed |= self.union(class, target);
+            }
+            self.rebuild();
+            if !changed {
+                return rounds;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{eqclass::EquivalenceClasses, iter::TermIterator, lterm, term, term::TermPointer};
+
+    #[test]
+    fn egraph() {
+        let mut egraph = EGraph::new();
+        let term = egraph.add(&term!((a * b) * (c * d)));
+        // the leaf, a * b and (a * b) * (c * d), since a * b and c * d have the same shape
+        assert_eq!(egraph.len(), 3);
+        assert_eq!(egraph.lookup(&term!((a * b) * (c * d))), Some(term));
+        assert_eq!(egraph.lookup(&term!(a * (b * c))), None);
+
+        // merging the leaf with a * b makes all terms of the graph congruent to each other
+        let leaf = egraph.lookup(&term!(a)).unwrap();
+        let pair = egraph.lookup(&term!(a * b)).unwrap();
+        egraph.union(leaf, pair);
+        egraph.rebuild();
+        assert_eq!(egraph.len(), 1);
+    }
+
+    #[test]
+    fn saturate_like_equivalence_classes() {
+        let equivs = [
+            lterm!((a * b) * c).map_to(lterm!(a * (b * c))),
+            lterm!((a * b) * (c * d)).map_to(lterm!((c * a) * (b * d))),
+        ];
+        for leaves in [4, 6] {
+            let terms: Vec<_> = TermIterator::new(leaves).collect();
+            let mut egraph = EGraph::new();
+            let mut eqclasses = EquivalenceClasses::new();
+            for term in &terms {
+                egraph.add(term);
+                for equiv in &equivs {
+                    for matched in equiv.source().match_top_down(term) {
+                        eqclasses.add_equiv(term.substitute_match(&matched, equiv));
+                    }
+                }
+            }
+            egraph.saturate(&equivs);
+
+            for a in &terms {
+                for b in terms.iter().filter(|&b| b != a) {
+                    assert_eq!(
+                        egraph.lookup(a) == egraph.lookup(b),
+                        eqclasses.equivalent(a, b),
+                        "{} and {}",
+                        a,
+                        b
+                    );
+                }
+            }
+        }
+    }
+}
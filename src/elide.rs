@@ -0,0 +1,129 @@
+use std::fmt::Display;
+
+use crate::{
+    bidag::BinaryChildren,
+    position::Position,
+    term::{Term, TermRef},
+};
+
+/// Display of a term that elides subterms below a depth, or after a number of leaves, as
+/// `…[n]` with `n` the number of leaves left out. Leaves keep their index in the whole term.
+#[derive(Clone, Copy)]
+pub struct Elided<'t> {
+    term: &'t Term,
+    first_leaf: usize,
+    max_depth: Option<usize>,
+    max_leaves: Option<usize>,
+}
+
+impl<'t> Elided<'t> {
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    pub fn max_leaves(mut self, max_leaves: usize) -> Self {
+        self.max_leaves = Some(max_leaves);
+        self
+    }
+}
+
+impl Term {
+    pub fn elided(&self) -> Elided<'_> {
+        Elided {
+            term: self,
+            first_leaf: 0,
+            max_depth: None,
+            max_leaves: None,
+        }
+    }
+
+    /// Displays only the subterm at `position`, numbering its leaves as in `self`.
+    pub fn elided_at<'t>(self: &'t TermRef, position: &Position) -> Option<Elided<'t>> {
+        let subterm = self.subterm_at(position)?;
+        let first_leaf = self
+            .subterms()
+            .take_while(|(other, _)| other != position)
+            .filter(|(_, node)| node.is_leaf())
+            .count();
+        Some(Elided {
+            first_leaf,
+            ..subterm.elided()
+        })
+    }
+}
+
+impl Display for Elided<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        enum Step<'n> {
+            Visit(&'n Term, usize),
+            Text(&'static str),
+        }
+
+        let mut leaf_index = self.first_leaf;
+        let mut printed_leaves = 0;
+        let mut stack = vec![Step::Visit(self.term, 0)];
+
+        while let Some(step) = stack.pop() {
+            let (node, depth) = match step {
+                Step::Text(text) => {
+                    write!(f, "{}", text)?;
+                    continue;
+                }
+                Step::Visit(node, depth) => (node, depth),
+            };
+
+            let out_of_leaves = self.max_leaves.is_some_and(|max| printed_leaves >= max);
+            let too_deep = !node.is_leaf() && self.max_depth.is_some_and(|max| depth >= max);
+            if out_of_leaves || too_deep {
+                write!(f, "…[{}]", node.leaves())?;
+                leaf_index += node.leaves();
+                continue;
+            }
+
+            match node.children() {
+                None => {
+                    write!(f, "{}", leaf_index)?;
+                    leaf_index += 1;
+                    printed_leaves += 1;
+                }
+                Some((left, right)) => {
+                    if depth > 0 {
+                        stack.push(Step::Text(")"));
+                    }
+                    stack.push(Step::Visit(right, depth + 1));
+                    stack.push(Step::Text(" * "));
+                    stack.push(Step::Visit(left, depth + 1));
+                    if depth > 0 {
+                        stack.push(Step::Text("("));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::term;
+
+    #[test]
+    fn elided() {
+        let term = term!((a * (b * c)) * ((d * e) * f));
+        assert_eq!(term.elided().to_string(), term.to_string());
+        assert_eq!(
+            term.elided().max_depth(2).to_string(),
+            "(0 * …[2]) * (…[2] * 5)"
+        );
+        assert_eq!(
+            term.elided().max_leaves(2).to_string(),
+            "(0 * (1 * …[1])) * …[3]"
+        );
+
+        let subterm = term.elided_at(&"RL".parse().unwrap()).unwrap();
+        assert_eq!(subterm.to_string(), "3 * 4");
+        assert!(term.elided_at(&"LL".parse().unwrap()).is_some());
+        assert!(term.elided_at(&"LLL".parse().unwrap()).is_none());
+    }
+}
@@ -1,21 +1,38 @@
-use std::{collections::HashMap, fmt::Debug};
-
-use crate::{indexing::IndexedTerm, maps::TermMap, perm::group::PermutationGroup, term::TermRef};
+use std::{collections::HashMap, fmt::Debug, hash::Hash, sync::OnceLock};
+
+use crate::{
+    bidag::BinaryChildren,
+    indexing::{IndexedTerm, bindings_agree, repeated_leaves},
+    labeled::LabeledTermRef,
+    maps::TermMap,
+    perm::{group::PermutationGroup, perms::Permutation},
+    rewrite::{RewriteSystem, Strategy},
+    substtree::SubstitutionTree,
+    term::{TermPointer, TermRef},
+};
 
 type EqClassEntryIndex = usize;
 
-struct EqClassRootEntry {
-    term: IndexedTerm,
+struct EqClassRootEntry<P: TermPointer> {
+    term: IndexedTerm<P>,
     rank: usize,
     automorphisms: Option<PermutationGroup<'static>>,
 }
 
-impl EqClassRootEntry {
+impl<P: TermPointer> EqClassRootEntry<P> {
+    fn add_automorphism(&mut self, perm: Permutation<'static>) {
+        if let Some(non_fixpoint) = perm.nonfix_index() {
+            self.automorphisms
+                .get_or_insert_with(|| PermutationGroup::new(non_fixpoint))
+                .extend(perm);
+        }
+    }
+
     pub fn into_child(
         self,
         parent: EqClassEntryIndex,
-        parent_map: TermMap<'static>,
-    ) -> EqClassEntry {
+        parent_map: TermMap<'static, P>,
+    ) -> EqClassEntry<P> {
         EqClassEntry::Child(EqClassChildEntry {
             parent,
             parent_map,
@@ -24,19 +41,19 @@ impl EqClassRootEntry {
     }
 }
 
-struct EqClassChildEntry {
-    term: IndexedTerm,
+struct EqClassChildEntry<P: TermPointer> {
+    term: IndexedTerm<P>,
     parent: EqClassEntryIndex,
-    parent_map: TermMap<'static>,
+    parent_map: TermMap<'static, P>,
 }
 
-enum EqClassEntry {
-    Root(EqClassRootEntry),
-    Child(EqClassChildEntry),
+enum EqClassEntry<P: TermPointer> {
+    Root(EqClassRootEntry<P>),
+    Child(EqClassChildEntry<P>),
 }
 
-impl EqClassEntry {
-    pub fn new_root(term: &TermRef) -> Self {
+impl<P: TermPointer> EqClassEntry<P> {
+    pub fn new_root(term: &P) -> Self {
         EqClassEntry::Root(EqClassRootEntry {
             term: IndexedTerm::from(term.clone()),
             rank: 0,
@@ -44,32 +61,34 @@ impl EqClassEntry {
         })
     }
 
+    #[allow(dead_code)]
     pub fn is_root(&self) -> bool {
         matches!(self, Self::Root(_))
     }
 
-    pub fn as_mut_root(&mut self) -> &mut EqClassRootEntry {
+    pub fn as_mut_root(&mut self) -> &mut EqClassRootEntry<P> {
         match self {
             EqClassEntry::Root(root) => root,
             EqClassEntry::Child(_) => panic!("as_root called on non-child entry"),
         }
     }
 
-    pub fn as_mut_child(&mut self) -> &mut EqClassChildEntry {
+    pub fn as_mut_child(&mut self) -> &mut EqClassChildEntry<P> {
         match self {
             EqClassEntry::Child(child) => child,
             EqClassEntry::Root(_) => panic!("as_mut_child called on non-child entry"),
         }
     }
 
-    pub fn as_root(&self) -> &EqClassRootEntry {
+    pub fn as_root(&self) -> &EqClassRootEntry<P> {
         match self {
             EqClassEntry::Root(root) => root,
             EqClassEntry::Child(_) => panic!("as_root called on non-child entry"),
         }
     }
 
-    pub fn as_child(&self) -> &EqClassChildEntry {
+    #[allow(dead_code)]
+    pub fn as_child(&self) -> &EqClassChildEntry<P> {
         match self {
             EqClassEntry::Child(child) => child,
             EqClassEntry::Root(_) => panic!("as_child called on non-child entry"),
@@ -77,16 +96,19 @@ impl EqClassEntry {
     }
 }
 
-pub struct EquivalenceClasses {
-    entries: Vec<EqClassEntry>,
-    by_shape: HashMap<TermRef, EqClassEntryIndex>,
+pub struct EquivalenceClasses<P: TermPointer = TermRef> {
+    entries: Vec<EqClassEntry<P>>,
+    by_shape: HashMap<P, EqClassEntryIndex>,
+    // built on the first query, then kept up to date
+    stored: OnceLock<SubstitutionTree<EqClassEntryIndex>>,
 }
 
-impl EquivalenceClasses {
+impl<P: TermPointer> EquivalenceClasses<P> {
     pub fn new() -> Self {
         EquivalenceClasses {
             entries: Vec::new(),
             by_shape: HashMap::new(),
+            stored: OnceLock::new(),
         }
     }
 
@@ -97,15 +119,123 @@ impl EquivalenceClasses {
         }
     }
 
-    fn entry_for_term(&mut self, term: &TermRef) -> EqClassEntryIndex {
+    fn entry_for_term(&mut self, term: &P) -> EqClassEntryIndex {
         *self.by_shape.entry(term.clone()).or_insert_with(|| {
             let entry = EqClassEntry::new_root(term);
             self.entries.push(entry);
+            if let Some(stored) = self.stored.get_mut() {
+                stored.insert(term, self.entries.len() - 1);
+            }
             self.entries.len() - 1
         })
     }
 
-    pub fn add_equiv(&mut self, map: TermMap) {
+    fn term(&self, index: EqClassEntryIndex) -> &P {
+        match &self.entries[index] {
+            EqClassEntry::Root(root) => root.term.term(),
+            EqClassEntry::Child(child) => child.term.term(),
+        }
+    }
+
+    fn stored(&self) -> &SubstitutionTree<EqClassEntryIndex> {
+        self.stored.get_or_init(|| {
+            let mut stored = SubstitutionTree::new();
+            for index in 0..self.entries.len() {
+                stored.insert(self.term(index), index);
+            }
+            stored
+        })
+    }
+
+    fn terms<'a>(&'a self, indices: Vec<&EqClassEntryIndex>) -> Vec<&'a P> {
+        indices.into_iter().map(|&index| self.term(index)).collect()
+    }
+
+    /// The stored terms that unify with `term`.
+    #[allow(dead_code)]
+    pub fn unifiable(&self, term: &P) -> Vec<&P> {
+        self.terms(self.stored().unifiable(term))
+    }
+
+    /// The stored terms that are instances of `term`.
+    #[allow(dead_code)]
+    pub fn instances(&self, term: &P) -> Vec<&P> {
+        self.terms(self.stored().instances(term))
+    }
+
+    /// The stored terms that `term` is an instance of.
+    #[allow(dead_code)]
+    pub fn generalizations(&self, term: &P) -> Vec<&P> {
+        self.terms(self.stored().generalizations(term))
+    }
+
+    /// The stored terms that are instances of `pattern`, whose leaves are variables identified by
+    /// their labels. Leaves with the same label must be matched by equal subterms.
+    pub fn instances_of<T: Hash + Eq>(&self, pattern: &LabeledTermRef<T>) -> Vec<&P> {
+        let skeleton: P = pattern.map_shared(&mut |_| ());
+        let repeated = repeated_leaves(&pattern.variable_indices());
+        let mut found = self.terms(self.stored().instances(pattern));
+        found.retain(|term| {
+            skeleton
+                .instance_bindings(term)
+                .is_some_and(|bindings| bindings_agree(&repeated, &bindings))
+        });
+        found
+    }
+
+    /// Whether `term` is in some class, i.e. appeared in an added equivalence.
+    pub fn contains(&self, term: &P) -> bool {
+        self.by_shape.contains_key(term)
+    }
+
+    /// Whether both terms have been added and are in the same class.
+    #[allow(dead_code)]
+    pub fn equivalent(&self, a: &P, b: &P) -> bool {
+        match (self.by_shape.get(a), self.by_shape.get(b)) {
+            (Some(&a), Some(&b)) => self.root(a) == self.root(b),
+            _ => false,
+        }
+    }
+
+    // the map from `term` to the root of its class, as stored
+    fn stored_map_to_root(&self, term: &P) -> Option<(EqClassEntryIndex, TermMap<'static, P>)> {
+        let &index = self.by_shape.get(term)?;
+        let mut map = TermMap::identity(term.clone());
+        let root = self.find_immut(index, &mut map);
+        Some((root, map))
+    }
+
+    // the canonical one of the maps that differ from `map` by an automorphism of the root
+    fn canonical(&self, root: EqClassEntryIndex, map: TermMap<'_, P>) -> TermMap<'static, P> {
+        let perm = match &self.entries[root].as_root().automorphisms {
+            Some(group) => group.canonical_coset_repr(map.perm()),
+            None => map.perm().clone().into_owned(),
+        };
+        TermMap::new(map.source().clone(), map.target().clone(), perm)
+    }
+
+    /// The map from `term` to the root of its class, `None` if it has not been added. Maps that
+    /// differ by an automorphism of the root are the same modulo the symmetries of the class, so
+    /// this is a canonical one of them.
+    #[allow(dead_code)]
+    pub fn map_to_root(&self, term: &P) -> Option<TermMap<'static, P>> {
+        let (root, map) = self.stored_map_to_root(term)?;
+        Some(self.canonical(root, map))
+    }
+
+    /// Whether the maps, which go between the same terms, differ only by an automorphism of the
+    /// class of their target. If the target has not been added, whether they are the same.
+    #[allow(dead_code)]
+    pub fn equivalent_maps(&self, a: &TermMap<'_, P>, b: &TermMap<'_, P>) -> bool {
+        let Some((root, to_root)) = self.stored_map_to_root(a.target()) else {
+            return a.perm() == b.perm();
+        };
+        let a = self.canonical(root, a * &to_root);
+        let b = self.canonical(root, b * &to_root);
+        a.perm() == b.perm()
+    }
+
+    pub fn add_equiv(&mut self, map: TermMap<'_, P>) {
         let target = self.entry_for_term(map.target());
         let source = self.entry_for_term(map.source());
         let mut source_to_target_root = map;
@@ -114,16 +244,9 @@ impl EquivalenceClasses {
         let mut source_root = self.find(source, Some(&mut target_root_to_source_root));
 
         if target_root == source_root {
-            let root_entry = self.entries[target_root].as_mut_root();
-            let perm = target_root_to_source_root.into_perm();
-
-            if let Some(non_fixpoint) = perm.nonfix_index() {
-                root_entry
-                    .automorphisms
-                    .get_or_insert_with(|| PermutationGroup::new(non_fixpoint))
-                    .extend(perm);
-            }
-
+            self.entries[target_root]
+                .as_mut_root()
+                .add_automorphism(target_root_to_source_root.into_perm());
             return;
         }
 
@@ -142,6 +265,15 @@ impl EquivalenceClasses {
             source_entry.rank += 1;
         }
 
+        // the automorphisms of the class being merged in are conjugated onto the new root's leaves
+        if let Some(child_automorphisms) = target_entry.automorphisms.take() {
+            let to_root = target_root_to_source_root.perm();
+            let from_root = to_root.inverse();
+            for generator in child_automorphisms.generators() {
+                source_entry.add_automorphism(&from_root * generator * to_root);
+            }
+        }
+
         // FIXME: Is there really no better way to do this?
         if let EqClassEntry::Root(target_owned) = self.entries.swap_remove(target_root) {
             let last_index = self.entries.len();
@@ -153,10 +285,17 @@ impl EquivalenceClasses {
         }
     }
 
+    fn root(&self, mut index: EqClassEntryIndex) -> EqClassEntryIndex {
+        while let Some(parent) = self.parent_of(index) {
+            index = parent;
+        }
+        index
+    }
+
     fn find(
         &mut self,
         mut index: EqClassEntryIndex,
-        mut tracking_map: Option<&mut TermMap>,
+        mut tracking_map: Option<&mut TermMap<'_, P>>,
     ) -> EqClassEntryIndex {
         loop {
             match self.parent_of(index) {
@@ -188,7 +327,7 @@ impl EquivalenceClasses {
     fn find_immut(
         &self,
         mut index: EqClassEntryIndex,
-        mut tracking_map: &mut TermMap,
+        mut tracking_map: &mut TermMap<'_, P>,
     ) -> EqClassEntryIndex {
         loop {
             match &self.entries[index] {
@@ -204,22 +343,39 @@ impl EquivalenceClasses {
     }
 }
 
-impl Debug for EquivalenceClasses {
+impl EquivalenceClasses<TermRef> {
+    /// Adds `map` with both sides rewritten to normal form by `system`, which has to terminate.
+    /// Only normal forms end up in the classes then. Returns false if the sides and their leaves
+    /// became the same, in which case nothing is added.
+    pub fn add_demodulated(&mut self, map: TermMap<'static>, system: &RewriteSystem) -> bool {
+        let source = system.normalize(map.source(), Strategy::Innermost).map;
+        let target = system.normalize(map.target(), Strategy::Innermost).map;
+        let map = &(&source.into_backward() * map) * target;
+        if map.source() == map.target() && map.perm().is_identity() {
+            return false;
+        }
+        self.add_equiv(map);
+        true
+    }
+}
+
+impl<P: TermPointer> Debug for EquivalenceClasses<P> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut classes = HashMap::new();
 
         for (i, entry) in self.entries.iter().enumerate() {
             match entry {
+                // children with a lower index may have created the class already
                 EqClassEntry::Root(_) => {
-                    classes.insert(i, Vec::new());
+                    classes.entry(i).or_insert_with(Vec::new);
                 }
                 EqClassEntry::Child(child) => {
-                    let mut map_to_root = child.term.term().identity_map();
+                    let mut map_to_root = TermMap::identity(child.term.term().clone());
                     let root = self.find_immut(i, &mut map_to_root);
 
                     classes
                         .entry(root)
-                        .or_insert_with(|| Vec::new())
+                        .or_insert_with(Vec::new)
                         .push(map_to_root);
                 }
             }
@@ -234,7 +390,7 @@ impl Debug for EquivalenceClasses {
                 writeln!(f, "\tMorphs : {:?}", morphs)?;
             }
 
-            if maps.len() > 0 {
+            if !maps.is_empty() {
                 writeln!(f, "\tChildren ({}):", maps.len())?;
                 for map in maps {
                     writeln!(f, "\t\t{}", map.backward())?;
@@ -244,3 +400,184 @@ impl Debug for EquivalenceClasses {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{iter::TermIterator, lterm, term};
+
+    // the term with the same constant at every leaf, which `match_onto` treats like the leaves
+    // of a term
+    fn labeled(term: &TermRef) -> LabeledTermRef<String> {
+        term.map(&mut |_| "c".to_string())
+    }
+
+    #[test]
+    fn instances_of() {
+        let assoc = lterm!((a * b) * c).map_to(lterm!(a * (b * c)));
+        let pattern = IndexedTerm::from(assoc.source().clone());
+        let mut eqclasses = EquivalenceClasses::new();
+        for term in TermIterator::new(3..=6) {
+            for matched in pattern.match_with_bindings(&term) {
+                eqclasses.add_equiv(term.substitute_match(&matched, &assoc));
+            }
+        }
+
+        for query in [lterm!((x * x) * y), lterm!(x * (y * x)), lterm!(x * y)] {
+            let mut found: Vec<_> = eqclasses
+                .instances_of(&query)
+                .iter()
+                .map(|term| term.to_string())
+                .collect();
+            found.sort();
+            let mut expected: Vec<_> = eqclasses
+                .by_shape
+                .keys()
+                .filter(|term| query.match_onto(&labeled(term)).is_some())
+                .map(|term| term.to_string())
+                .collect();
+            expected.sort();
+            assert!(!expected.is_empty());
+            assert_eq!(found, expected);
+        }
+    }
+
+    fn sorted(terms: Vec<&TermRef>) -> Vec<String> {
+        let mut terms: Vec<_> = terms.into_iter().map(|term| term.to_string()).collect();
+        terms.sort();
+        terms
+    }
+
+    fn check_stored_queries(eqclasses: &EquivalenceClasses) {
+        let stored: Vec<_> = eqclasses.by_shape.keys().collect();
+        for query in [term!(a * b), term!((a * b) * c), term!(a * ((b * c) * d))] {
+            let instances = stored
+                .iter()
+                .copied()
+                .filter(|term| query.instance_bindings(term).is_some());
+            let generalizations = stored
+                .iter()
+                .copied()
+                .filter(|term| term.instance_bindings(&query).is_some());
+            assert_eq!(
+                sorted(eqclasses.instances(&query)),
+                sorted(instances.collect())
+            );
+            assert_eq!(
+                sorted(eqclasses.generalizations(&query)),
+                sorted(generalizations.collect())
+            );
+            // linear terms over one operation all unify
+            assert_eq!(sorted(eqclasses.unifiable(&query)), sorted(stored.clone()));
+        }
+    }
+
+    #[test]
+    fn stored_queries() {
+        let assoc = lterm!((a * b) * c).map_to(lterm!(a * (b * c)));
+        let add = |eqclasses: &mut EquivalenceClasses, leaves| {
+            for term in TermIterator::new(leaves) {
+                for matched in assoc.source().match_top_down(&term) {
+                    eqclasses.add_equiv(term.substitute_match(&matched, &assoc));
+                }
+            }
+        };
+        let mut eqclasses = EquivalenceClasses::new();
+        add(&mut eqclasses, 3..=4);
+        assert!(eqclasses.stored.get().is_none());
+        check_stored_queries(&eqclasses);
+        // the terms added after the first query are found as well
+        add(&mut eqclasses, 5..=5);
+        check_stored_queries(&eqclasses);
+    }
+
+    #[test]
+    fn equivalent_maps() {
+        let assoc = lterm!((a * b) * c).map_to(lterm!(a * (b * c)));
+        let commute = lterm!(a * b).map_to(lterm!(b * a));
+        let mut associative = EquivalenceClasses::new();
+        let mut commutative = EquivalenceClasses::new();
+        for term in TermIterator::new(3) {
+            for matched in assoc.source().match_top_down(&term) {
+                let map = term.substitute_match(&matched, &assoc);
+                associative.add_equiv(map.clone());
+                commutative.add_equiv(map);
+            }
+            for matched in commute.source().match_top_down(&term) {
+                commutative.add_equiv(term.substitute_match(&matched, &commute));
+            }
+        }
+
+        // relabeling the leaves of a class is only a symmetry with commutativity
+        let term = term!((a * b) * c);
+        assert!(associative.contains(&term));
+        assert!(!associative.contains(&term!(a * b)));
+        let identity = term.identity_map();
+        let swapped = term.substitute_at(&"L".parse().unwrap(), &commute);
+        assert!(!associative.equivalent_maps(&identity, &swapped));
+        assert!(commutative.equivalent_maps(&identity, &swapped));
+
+        // every term has a single map to the root modulo the symmetries
+        let a = commutative.map_to_root(&term).unwrap();
+        let b = commutative.map_to_root(swapped.target()).unwrap();
+        assert_eq!(a.target(), b.target());
+        assert_eq!(a.perm(), commutative.map_to_root(&term).unwrap().perm());
+        assert!(commutative.equivalent_maps(&(&swapped * &b), &a));
+    }
+
+    #[test]
+    fn merge_automorphisms() {
+        let (left, right) = (term!((a * b) * c), term!(a * (b * c)));
+        let mut eqclasses = EquivalenceClasses::new();
+        // swapping the first two leaves, and the last two leaves
+        eqclasses.add_equiv(TermMap::new(left.clone(), left.clone(), vec![1, 0].into()));
+        eqclasses.add_equiv(TermMap::new(
+            right.clone(),
+            right.clone(),
+            vec![0, 2, 1].into(),
+        ));
+        // `(a * b) * c` to `c * (a * b)`, where the swap of the last two leaves is that of `a` and `b`
+        eqclasses.add_equiv(TermMap::new(
+            left.clone(),
+            right.clone(),
+            vec![1, 2, 0].into(),
+        ));
+
+        let root = eqclasses.root(*eqclasses.by_shape.get(&left).unwrap());
+        assert_eq!(eqclasses.term(root), &left);
+        let group = eqclasses.entries[root]
+            .as_root()
+            .automorphisms
+            .as_ref()
+            .unwrap();
+        assert_eq!(group.order(), 2);
+        assert!(group.contains(&vec![1, 0].into()));
+    }
+
+    #[test]
+    fn add_demodulated() {
+        let assoc = lterm!((a * b) * c).map_to(lterm!(a * (b * c)));
+        let swap = lterm!((a * b) * (c * d)).map_to(lterm!((c * d) * (a * b)));
+        let system = RewriteSystem::new(vec![assoc.clone()]);
+
+        let mut plain = EquivalenceClasses::new();
+        let mut demodulated = EquivalenceClasses::new();
+        let mut trivial = 0;
+        let terms: Vec<_> = TermIterator::new(5).collect();
+        for term in &terms {
+            for equiv in [&assoc, &swap] {
+                for matched in equiv.source().match_top_down(term) {
+                    let map = term.substitute_match(&matched, equiv);
+                    plain.add_equiv(map.clone());
+                    trivial += !demodulated.add_demodulated(map, &system) as usize;
+                }
+            }
+        }
+
+        // every associativity step is trivial, and only the right comb is left
+        assert!(trivial > 0);
+        let right_comb = term!(a * (b * (c * (d * e))));
+        assert!(demodulated.by_shape.keys().all(|term| *term == right_comb));
+        assert!(terms.iter().all(|a| plain.equivalent(a, &right_comb)));
+    }
+}
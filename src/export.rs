@@ -0,0 +1,91 @@
+use std::fmt::Write;
+
+use crate::{
+    bidag::BinaryChildren, labeled::LabeledTermRef, maps::NodeIndex, rewrite::RewriteSystem,
+};
+
+// prefix notation with `f` for the operation and `x<label>` for the variables
+fn prefix(term: &LabeledTermRef<NodeIndex>) -> String {
+    term.reduce(
+        &mut |_, left, right| format!("f({}, {})", left, right),
+        &mut |leaf| format!("x{}", leaf.label().unwrap()),
+    )
+}
+
+impl RewriteSystem {
+    fn variables(&self) -> Vec<String> {
+        let leaves = self
+            .rules()
+            .iter()
+            .map(|rule| rule.source().leaves())
+            .max()
+            .unwrap_or(0);
+        (0..leaves).map(|leaf| format!("x{}", leaf)).collect()
+    }
+
+    /// The rules in the TPDB `.trs` format of the termination competition, with the operation
+    /// as the binary symbol `f`.
+    pub fn to_trs(&self) -> String {
+        let mut trs = format!("(VAR {})\n(RULES\n", self.variables().join(" "));
+        for rule in self.rules() {
+            let (source, target) = rule.labeled();
+            writeln!(trs, "  {} -> {}", prefix(&source), prefix(&target)).unwrap();
+        }
+        trs.push_str(")\n");
+        trs
+    }
+
+    /// The rules as a Maude system module `name` over a single sort `T`, labeled by their ids.
+    pub fn to_maude(&self, name: &str) -> String {
+        let mut maude = format!("mod {} is\n  sort T .\n  op f : T T -> T .\n", name);
+        let variables = self.variables();
+        if !variables.is_empty() {
+            writeln!(maude, "  vars {} : T .", variables.join(" ")).unwrap();
+        }
+        for (id, rule) in self.rules().iter().enumerate() {
+            let (source, target) = rule.labeled();
+            writeln!(
+                maude,
+                "  rl [r{}] : {} => {} .",
+                id,
+                prefix(&source),
+                prefix(&target)
+            )
+            .unwrap();
+        }
+        maude.push_str("endm\n");
+        maude
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lterm;
+
+    #[test]
+    fn export() {
+        let system = RewriteSystem::new(vec![
+            lterm!((a * b) * c).map_to(lterm!(a * (b * c))),
+            lterm!(a * (b * (c * d))).map_to(lterm!(b * (a * (c * d)))),
+        ]);
+        assert_eq!(
+            system.to_trs(),
+            "(VAR x0 x1 x2 x3)\n\
+             (RULES\n  \
+             f(f(x0, x1), x2) -> f(x0, f(x1, x2))\n  \
+             f(x0, f(x1, f(x2, x3))) -> f(x1, f(x0, f(x2, x3)))\n\
+             )\n"
+        );
+        assert_eq!(
+            system.to_maude("ASSOC"),
+            "mod ASSOC is\n  \
+             sort T .\n  \
+             op f : T T -> T .\n  \
+             vars x0 x1 x2 x3 : T .\n  \
+             rl [r0] : f(f(x0, x1), x2) => f(x0, f(x1, x2)) .\n  \
+             rl [r1] : f(x0, f(x1, f(x2, x3))) => f(x1, f(x0, f(x2, x3))) .\n\
+             endm\n"
+        );
+    }
+}
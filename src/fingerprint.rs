@@ -0,0 +1,144 @@
+use crate::bidag::BinaryChildren;
+
+// the positions of depth at most 2, numbered as in a complete binary tree: ε, L, R, LL, LR, RL, RR
+const SAMPLED: usize = 7;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Feature {
+    Operation,
+    Leaf,
+    BelowLeaf,
+}
+
+use Feature::*;
+
+const FEATURES: [Feature; 3] = [Operation, Leaf, BelowLeaf];
+
+/// What a term has at a few fixed positions near the root. Comparing fingerprints rules out most
+/// pairs that do not match, without looking at the terms.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Fingerprint([Feature; SAMPLED]);
+
+impl Fingerprint {
+    pub fn of<P: BinaryChildren>(term: &P) -> Self {
+        let mut nodes: [Option<&P>; SAMPLED] = [None; SAMPLED];
+        nodes[0] = Some(term);
+        for i in 0..SAMPLED / 2 {
+            if let Some((left, right)) = nodes[i].and_then(|node| node.children()) {
+                nodes[2 * i + 1] = Some(left);
+                nodes[2 * i + 2] = Some(right);
+            }
+        }
+        Fingerprint(nodes.map(|node| match node {
+            None => BelowLeaf,
+            Some(node) if node.is_leaf() => Leaf,
+            Some(_) => Operation,
+        }))
+    }
+
+    // Below a leaf of a pattern is anything, but below a leaf of a term is nothing.
+    fn features_match(pattern: Feature, term: Feature) -> bool {
+        match pattern {
+            BelowLeaf => true,
+            Leaf => term != BelowLeaf,
+            Operation => term == Operation,
+        }
+    }
+}
+
+struct TrieNode<V> {
+    children: [Option<usize>; 3],
+    values: Vec<V>,
+}
+
+impl<V> TrieNode<V> {
+    fn new() -> Self {
+        TrieNode {
+            children: [None; 3],
+            values: Vec::new(),
+        }
+    }
+}
+
+/// Values of patterns stored in a trie over the fingerprints of the patterns. Retrieval only
+/// compares fingerprints, so it finds a superset of the patterns that match, which the caller
+/// still has to verify.
+pub struct FingerprintIndex<V> {
+    trie: Vec<TrieNode<V>>,
+}
+
+impl<V> Default for FingerprintIndex<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> FingerprintIndex<V> {
+    pub fn new() -> Self {
+        FingerprintIndex {
+            trie: vec![TrieNode::new()],
+        }
+    }
+
+    pub fn insert<P: BinaryChildren>(&mut self, pattern: &P, value: V) {
+        let mut current = 0;
+        for feature in Fingerprint::of(pattern).0 {
+            let next = self.trie.len();
+            current = *self.trie[current].children[feature as usize].get_or_insert(next);
+            if current == next {
+                self.trie.push(TrieNode::new());
+            }
+        }
+        self.trie[current].values.push(value);
+    }
+
+    /// The values of the patterns whose fingerprints allow them to match `term` at the root.
+    pub fn candidates<P: BinaryChildren>(&self, term: &P) -> Vec<&V> {
+        let query = Fingerprint::of(term);
+        let mut found = Vec::new();
+        let mut stack = vec![(0, 0)];
+        while let Some((node, depth)) = stack.pop() {
+            if depth == SAMPLED {
+                found.extend(&self.trie[node].values);
+                continue;
+            }
+            for feature in FEATURES {
+                if let Some(child) = self.trie[node].children[feature as usize]
+                    && Fingerprint::features_match(feature, query.0[depth])
+                {
+                    stack.push((child, depth + 1));
+                }
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{iter::TermIterator, term::TermPointer};
+
+    #[test]
+    fn fingerprint_index() {
+        let terms: Vec<_> = (1..=6).flat_map(TermIterator::new).collect();
+        let mut index = FingerprintIndex::new();
+        for (i, term) in terms.iter().enumerate() {
+            index.insert(term, i);
+        }
+
+        let mut passing = 0;
+        for term in &terms {
+            let candidates: Vec<_> = index.candidates(term).into_iter().copied().collect();
+            passing += candidates.len();
+            // no generalization is ruled out
+            for (i, pattern) in terms.iter().enumerate() {
+                if pattern.instance_bindings(term).is_some() {
+                    assert!(candidates.contains(&i));
+                }
+            }
+        }
+        // but most pairs that do not match are
+        assert!(passing * 3 < terms.len() * terms.len());
+    }
+}
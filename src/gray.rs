@@ -0,0 +1,168 @@
+use std::{iter::FusedIterator, rc::Rc};
+
+use crate::{
+    bidag::BinaryChildren,
+    maps::TermMap,
+    position::{Direction, Position},
+    term::{Term, TermRef},
+};
+
+/// A single rotation, as done by `Term::rotate_left_at` or `Term::rotate_right_at`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Rotation {
+    Left(Position),
+    Right(Position),
+}
+
+impl Rotation {
+    /// The map that rotates `term`, `None` if it cannot be rotated there.
+    pub fn apply(&self, term: &TermRef) -> Option<TermMap<'static>> {
+        match self {
+            Rotation::Left(position) => term.rotate_left_at(position),
+            Rotation::Right(position) => term.rotate_right_at(position),
+        }
+    }
+
+    // the same rotation in a term that contains the rotated one at `prefix`
+    fn below(self, prefix: &Position) -> Self {
+        match self {
+            Rotation::Left(position) => Rotation::Left(prefix.concat(&position)),
+            Rotation::Right(position) => Rotation::Right(prefix.concat(&position)),
+        }
+    }
+}
+
+// the number of operations on the path from the root to the rightmost leaf
+fn right_depth(term: &TermRef) -> usize {
+    let mut depth = 0;
+    let mut node = term;
+    while let Some((_, right)) = node.children() {
+        node = right;
+        depth += 1;
+    }
+    depth
+}
+
+// the term with one leaf more than `term`, that is the right child of a new node replacing the
+// subterm `depth` steps down the right edge of `term`
+fn attach_leaf(term: &TermRef, depth: usize) -> TermRef {
+    let position = Position::from(vec![Direction::Right; depth]);
+    let subterm = term.subterm_at(&position).unwrap().clone();
+    term.graft(&position, Term::operation(subterm, Rc::new(Term::Variable)))
+}
+
+// where on the right edge of a term the new leaf is attached, and which way that moves next
+struct Level {
+    depth: usize,
+    down: bool,
+}
+
+/// Iterator over all terms with a number of leaves, each differing from the one before by a
+/// single rotation, which is returned with it. Rotations keep the order of the leaves, so every
+/// term is the previous one with the returned rotation applied.
+///
+/// The terms with one more leaf are made by attaching a new rightmost leaf at every depth of the
+/// right edge of a smaller term. Attaching it one step deeper is a rotation to the right, so the
+/// depths are swept down and up again for consecutive smaller terms.
+pub struct GrayTermIterator {
+    // `terms[i]` is the current term with `i + 1` leaves
+    terms: Vec<TermRef>,
+    // `levels[i]` builds `terms[i + 1]` from `terms[i]`
+    levels: Vec<Level>,
+    started: bool,
+}
+
+impl GrayTermIterator {
+    pub fn new(leaves: usize) -> Self {
+        assert!(leaves > 0, "terms have at least one leaf");
+        let mut terms = vec![Rc::new(Term::Variable)];
+        let mut levels = Vec::new();
+        for _ in 1..leaves {
+            terms.push(attach_leaf(terms.last().unwrap(), 0));
+            levels.push(Level {
+                depth: 0,
+                down: true,
+            });
+        }
+        GrayTermIterator {
+            terms,
+            levels,
+            started: false,
+        }
+    }
+
+    // moves `terms[index]` on, returning the rotation, `None` if it was the last
+    fn advance(&mut self, index: usize) -> Option<Rotation> {
+        let smaller = index.checked_sub(1)?;
+        let bottom = right_depth(&self.terms[smaller]);
+        let level = &mut self.levels[smaller];
+        let edge = Position::from(vec![Direction::Right; level.depth]);
+        let rotation = if level.down && level.depth < bottom {
+            level.depth += 1;
+            Rotation::Right(edge)
+        } else if !level.down && level.depth > 0 {
+            level.depth -= 1;
+            Rotation::Left(edge.parent().unwrap())
+        } else {
+            let rotation = self.advance(smaller)?;
+            let level = &mut self.levels[smaller];
+            level.down = !level.down;
+            if level.depth == 0 {
+                // the smaller term is the left child
+                rotation.below(&Position::root().left())
+            } else {
+                // the new leaf hangs below the rightmost leaf of the smaller term, where the
+                // rotation does not reach
+                level.depth = right_depth(&self.terms[smaller]);
+                rotation
+            }
+        };
+        let depth = self.levels[smaller].depth;
+        self.terms[index] = attach_leaf(&self.terms[smaller], depth);
+        Some(rotation)
+    }
+}
+
+impl Iterator for GrayTermIterator {
+    type Item = (TermRef, Option<Rotation>);
+
+    fn next(&mut self) -> Option<(TermRef, Option<Rotation>)> {
+        let rotation = if self.started {
+            Some(self.advance(self.terms.len() - 1)?)
+        } else {
+            self.started = true;
+            None
+        };
+        Some((self.terms.last().unwrap().clone(), rotation))
+    }
+}
+
+impl FusedIterator for GrayTermIterator {}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn gray_term_iterator() {
+        for leaves in 1..=9 {
+            let mut previous: Option<TermRef> = None;
+            let mut seen = HashSet::new();
+            for (term, rotation) in GrayTermIterator::new(leaves) {
+                match (&previous, rotation) {
+                    (None, None) => {}
+                    (Some(previous), Some(rotation)) => {
+                        let map = rotation.apply(previous).unwrap();
+                        assert_eq!(map.target(), &term);
+                    }
+                    _ => panic!("only the first term has no rotation"),
+                }
+                assert!(seen.insert(term.clone()));
+                previous = Some(term);
+            }
+            assert_eq!(seen.len() as u128, Term::shape_count(leaves));
+        }
+    }
+}
@@ -1,81 +1,773 @@
-use crate::bidag::BinaryChildren;
 use std::{
     collections::{HashMap, HashSet},
-    fmt::Debug,
+    fmt::{Debug, Display},
+    hash::Hash,
+    rc::Rc,
+    sync::OnceLock,
+    time::{Duration, Instant},
 };
 
-use crate::term::TermRef;
+use crate::{
+    bidag::BinaryChildren,
+    labeled::LabeledTermRef,
+    patindex::PatternId,
+    position::{Occurrences, Position},
+    term::{TermPointer, TermRef},
+};
 
-pub struct TermIndexing(HashMap<(usize, usize), usize>);
+/// Labels of the subterms of patterns, numbered from 1 in the order they are found. The table is
+/// a `Vec` by the label of the left child, and every row holds the pairs `(right, label)` sorted
+/// by the label of the right child.
+pub struct TermIndexing {
+    by_left: Vec<Vec<(usize, usize)>>,
+}
 
-impl From<&TermRef> for TermIndexing {
-    fn from(value: &TermRef) -> Self {
-        let mut table = HashMap::new();
+impl TermIndexing {
+    fn new() -> Self {
+        TermIndexing {
+            by_left: vec![Vec::new()],
+        }
+    }
 
-        value.reduce(
-            &mut |_node, left_label, right_label| {
-                if let Some(label) = table.get(&(left_label, right_label)) {
-                    *label
-                } else {
-                    table.insert((left_label, right_label), table.len() + 1);
-                    table.len()
+    /// The number of labels besides the leaf label 0.
+    fn len(&self) -> usize {
+        self.by_left.len() - 1
+    }
+
+    // the labels of the children of every label, with a dummy entry for the leaf label
+    fn children(&self) -> Vec<(usize, usize)> {
+        let mut children = vec![(0, 0); self.by_left.len()];
+        for (left, row) in self.by_left.iter().enumerate() {
+            for &(right, label) in row {
+                children[label] = (left, right);
+            }
+        }
+        children
+    }
+
+    /// Labels the subterms of `pattern` that have no label yet, and returns the label of
+    /// `pattern`. Leaves have label 0.
+    fn insert<P: TermPointer>(&mut self, pattern: &P) -> usize {
+        pattern.reduce_shared(
+            &mut |_node, left_label: usize, right_label: usize| {
+                let next = self.by_left.len();
+                let row = &mut self.by_left[left_label];
+                match row.binary_search_by_key(&right_label, |&(right, _)| right) {
+                    Ok(found) => row[found].1,
+                    Err(at) => {
+                        row.insert(at, (right_label, next));
+                        self.by_left.push(Vec::new());
+                        next
+                    }
                 }
             },
             &mut |_| 0,
-        );
-        TermIndexing(table)
+        )
+    }
+}
+
+impl<P: TermPointer> From<&P> for TermIndexing {
+    fn from(value: &P) -> Self {
+        let mut indexing = TermIndexing::new();
+        indexing.insert(value);
+        indexing
+    }
+}
+
+/// An occurrence of a pattern together with the subterms matched by the pattern leaves.
+#[derive(Clone, Debug)]
+pub struct Match<P: TermPointer = TermRef> {
+    pub position: Position,
+    pub root: P,
+    pub bindings: Vec<P>,
+}
+
+type StateId = u32;
+
+/// Deterministic bottom-up tree automaton for the labels of a `TermIndexing`. A state is the set
+/// of labels, i.e. subterms of the pattern, that a node matches, so every node of a term gets a
+/// single state from the states of its children. The states reachable from leaves are found up
+/// front, and state 0 is the one of leaves.
+struct Automaton {
+    // transitions[left][right]
+    transitions: Vec<Vec<StateId>>,
+    // accepting[state] are the indices of the roots in the state
+    accepting: Vec<Vec<usize>>,
+    // labels[state] are the labels in the state, sorted
+    labels: Vec<Vec<usize>>,
+}
+
+impl Automaton {
+    fn new(index: &TermIndexing, roots: &[usize], commutative: bool) -> Self {
+        let mut by_left = index.by_left.clone();
+        if commutative {
+            for (left, row) in index.by_left.iter().enumerate() {
+                for &(right, label) in row {
+                    by_left[right].push((left, label));
+                }
+            }
+            for row in &mut by_left {
+                row.sort_unstable();
+                row.dedup();
+            }
+        }
+
+        // the label sets are kept sorted
+        let mut states = vec![vec![0]];
+        let mut ids = HashMap::from([(vec![0], 0)]);
+        let mut table = HashMap::new();
+        let mut next = 0;
+        while next < states.len() {
+            // all pairs of earlier states are done, so pair the next one with those and itself
+            for other in 0..=next {
+                for (left, right) in [(next, other), (other, next)] {
+                    let mut labels = vec![0];
+                    let max_right = *states[right].last().unwrap();
+                    for &left_label in &states[left] {
+                        for &(right_label, label) in &by_left[left_label] {
+                            if right_label > max_right {
+                                break;
+                            }
+                            if states[right].binary_search(&right_label).is_ok() {
+                                labels.push(label);
+                            }
+                        }
+                    }
+                    labels.sort_unstable();
+                    labels.dedup();
+                    let state = *ids.entry(labels).or_insert_with_key(|labels| {
+                        states.push(labels.clone());
+                        states.len() - 1
+                    });
+                    table.insert((left, right), state as StateId);
+                }
+            }
+            next += 1;
+        }
+
+        Automaton {
+            transitions: (0..states.len())
+                .map(|left| {
+                    (0..states.len())
+                        .map(|right| table[&(left, right)])
+                        .collect()
+                })
+                .collect(),
+            accepting: states
+                .iter()
+                .map(|labels| {
+                    (0..roots.len())
+                        .filter(|&i| labels.binary_search(&roots[i]).is_ok())
+                        .collect()
+                })
+                .collect(),
+            labels: states,
+        }
     }
 }
 
-pub struct IndexedTerm {
-    term: TermRef,
+/// The groups of leaves with the same variable, for the groups of at least two leaves.
+pub fn repeated_leaves(variables: &[usize]) -> Vec<Vec<usize>> {
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (leaf, variable) in variables.iter().enumerate() {
+        groups.entry(*variable).or_default().push(leaf);
+    }
+    let mut repeated: Vec<_> = groups
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .collect();
+    repeated.sort_unstable();
+    repeated
+}
+
+/// Whether the bindings of the leaves in each group of `repeated` are equal.
+pub fn bindings_agree<P: Eq>(repeated: &[Vec<usize>], bindings: &[P]) -> bool {
+    repeated.iter().all(|group| {
+        group
+            .iter()
+            .all(|&leaf| bindings[leaf] == bindings[group[0]])
+    })
+}
+
+// The pre-order sequence of the shape with the children of every node ordered as by
+// `Term::shape_cmp`, which is the same for terms that are equal modulo commutativity.
+fn commutative_form<P: BinaryChildren>(term: &P) -> Vec<bool> {
+    term.reduce(
+        &mut |_, left: Vec<bool>, right: Vec<bool>| {
+            let (first, second) = if (left.len(), &left) <= (right.len(), &right) {
+                (left, right)
+            } else {
+                (right, left)
+            };
+            let mut form = Vec::with_capacity(first.len() + second.len() + 1);
+            form.push(true);
+            form.extend(first);
+            form.extend(second);
+            form
+        },
+        &mut |_| vec![false],
+    )
+}
+
+/// A pattern prepared for bottom-up matching. Its leaves are variables; if some of them are the
+/// same variable, matching also requires equal subterms at those leaves.
+pub struct IndexedTerm<P: TermPointer = TermRef> {
+    term: P,
     index: TermIndexing,
+    repeated: Vec<Vec<usize>>,
+    automaton: OnceLock<Automaton>,
+    commutative_automaton: OnceLock<Automaton>,
 }
 
-impl IndexedTerm {
-    pub fn term(&self) -> &TermRef {
+impl<P: TermPointer> IndexedTerm<P> {
+    pub fn term(&self) -> &P {
         &self.term
     }
 }
 
-impl From<TermRef> for IndexedTerm {
-    fn from(value: TermRef) -> Self {
+impl<P: TermPointer> From<P> for IndexedTerm<P> {
+    fn from(value: P) -> Self {
         Self {
             index: TermIndexing::from(&value),
             term: value,
+            repeated: Vec::new(),
+            automaton: OnceLock::new(),
+            commutative_automaton: OnceLock::new(),
         }
     }
 }
 
-impl IndexedTerm {
-    // there is room for optimization here: Use BTreeSet instead of HashSet and
-    // use max/min values to abort loop over `index' early. also `index' could
-    // be stored in Vec instead (we only lookup in term index creation).
-    pub fn matches(&self, term: &TermRef) -> Vec<TermRef> {
-        let mut matched = Vec::new();
+impl<T: Hash + Eq> From<&LabeledTermRef<T>> for IndexedTerm {
+    /// The skeleton of a labeled term as pattern, with leaves of equal labels as one variable.
+    fn from(value: &LabeledTermRef<T>) -> Self {
+        IndexedTerm::with_variables(value.skeleton(), &value.variable_indices())
+    }
+}
+
+impl<P: TermPointer> IndexedTerm<P> {
+    /// A pattern where `variables[i]` is the variable at leaf `i`.
+    pub fn with_variables(term: P, variables: &[usize]) -> Self {
+        assert_eq!(
+            variables.len(),
+            term.leaf_count() as usize,
+            "there must be one variable per leaf"
+        );
+        IndexedTerm {
+            repeated: repeated_leaves(variables),
+            ..IndexedTerm::from(term)
+        }
+    }
 
-        term.reduce(
-            &mut |node, left_labels, right_labels| -> HashSet<usize> {
-                let mut labels = HashSet::<usize>::from([0]);
-                for ((left_label, right_label), label) in &self.index.0 {
-                    if left_labels.contains(left_label) && right_labels.contains(right_label) {
-                        if *label == self.index.0.len() {
-                            matched.push(node.clone());
-                        }
-                        labels.insert(*label);
+    #[allow(dead_code)]
+    pub fn is_linear(&self) -> bool {
+        self.repeated.is_empty()
+    }
+
+    // whether the subterms at the leaves of a repeated variable are equal, in at least one
+    // orientation of the children with `commutative`
+    fn bindings_agree(&self, node: &P, commutative: bool) -> bool {
+        if self.repeated.is_empty() {
+            return true;
+        }
+        let mut stack = vec![(vec![(&self.term, node)], Vec::new())];
+        while let Some((mut pending, mut bindings)) = stack.pop() {
+            let Some((pattern, node)) = pending.pop() else {
+                let agree = if commutative {
+                    let forms: Vec<_> = bindings.into_iter().map(commutative_form).collect();
+                    bindings_agree(&self.repeated, &forms)
+                } else {
+                    bindings_agree(&self.repeated, &bindings)
+                };
+                if agree {
+                    return true;
+                }
+                continue;
+            };
+            match (pattern.children(), node.children()) {
+                (None, _) => {
+                    bindings.push(node);
+                    stack.push((pending, bindings));
+                }
+                (Some(_), None) => {}
+                (Some((pattern_left, pattern_right)), Some((left, right))) => {
+                    if commutative {
+                        let mut swapped = pending.clone();
+                        swapped.extend([(pattern_right, left), (pattern_left, right)]);
+                        stack.push((swapped, bindings.clone()));
                     }
+                    pending.extend([(pattern_right, right), (pattern_left, left)]);
+                    stack.push((pending, bindings));
+                }
+            }
+        }
+        false
+    }
+
+    fn automaton(&self, commutative: bool) -> &Automaton {
+        // a single leaf as pattern has the leaf label 0, which every node has, so it matches
+        // everywhere like in `PatternAutomaton`
+        let roots = vec![self.index.len()];
+        if commutative {
+            self.commutative_automaton
+                .get_or_init(|| Automaton::new(&self.index, &roots, true))
+        } else {
+            self.automaton
+                .get_or_init(|| Automaton::new(&self.index, &roots, false))
+        }
+    }
+
+    fn nodes<'a>(&'a self, term: &'a P, commutative: bool) -> Matches<'a, P> {
+        Matches {
+            pattern: self,
+            commutative,
+            automaton: self.automaton(commutative),
+            states: HashMap::new(),
+            results: Vec::new(),
+            stack: vec![(term, false)],
+        }
+    }
+
+    /// The physical nodes of `term` that match, each once even if it is shared, found lazily in
+    /// post-order.
+    #[allow(dead_code)]
+    pub fn matches<'a>(&'a self, term: &'a P) -> Matches<'a, P> {
+        self.nodes(term, false)
+    }
+
+    /// Like `matches`, but returns the positions of all occurrences. Unlike the matched nodes,
+    /// positions stay meaningful when subterms are shared.
+    #[allow(dead_code)]
+    pub fn occurrences(&self, term: &P) -> Occurrences {
+        self.occurrences_inner(term, false)
+    }
+
+    /// The occurrences of the pattern with the operation commutative, i.e. where it matches
+    /// after swapping the children of some nodes.
+    #[allow(dead_code)]
+    pub fn occurrences_modulo_commutativity(&self, term: &P) -> Occurrences {
+        self.occurrences_inner(term, true)
+    }
+
+    fn occurrences_inner(&self, term: &P, commutative: bool) -> Occurrences {
+        let matched: HashSet<_> = self
+            .nodes(term, commutative)
+            .map(|node| node.address())
+            .collect();
+
+        let mut positions = Vec::new();
+        term.walk_with_positions(&mut |position, node| {
+            if matched.contains(&node.address()) {
+                positions.push(position.clone());
+            }
+        });
+        positions.into_iter().collect()
+    }
+
+    /// All matches in pre-order, with the subterm bound to every leaf of the pattern. The
+    /// bindings are put together during the bottom-up run from those of the children.
+    #[allow(dead_code)]
+    pub fn match_with_bindings(&self, term: &P) -> Vec<Match<P>> {
+        let automaton = self.automaton(false);
+        let children = self.index.children();
+        let root = self.index.len();
+        // the subterms bound by `label` in `child`, whose bindings by operation label are `bound`
+        let bindings = |child: &P, bound: &HashMap<usize, Vec<P>>, label: usize| match label {
+            0 => vec![child.clone()],
+            label => bound[&label].clone(),
+        };
+
+        let mut matched = HashMap::new();
+        term.reduce_shared(
+            &mut |node, (left_state, left): Bound<P>, (right_state, right): Bound<P>| {
+                let state = automaton.transitions[left_state as usize][right_state as usize];
+                let (left_child, right_child) = node.children().unwrap();
+                let bound: HashMap<_, _> = automaton.labels[state as usize][1..]
+                    .iter()
+                    .map(|&label| {
+                        let (left_label, right_label) = children[label];
+                        let mut bound = bindings(left_child, &left, left_label);
+                        bound.extend(bindings(right_child, &right, right_label));
+                        (label, bound)
+                    })
+                    .collect();
+                if let Some(bound) = bound.get(&root)
+                    && bindings_agree(&self.repeated, bound)
+                {
+                    matched.insert(node.address(), bound.clone());
+                }
+                (state, Rc::new(bound))
+            },
+            &mut |_| (0, Rc::new(HashMap::new())),
+        );
+        if root == 0 {
+            term.walk(&mut |node| {
+                matched.insert(node.address(), vec![node.clone()]);
+            });
+        }
+
+        let mut matches = Vec::new();
+        term.walk_with_positions(&mut |position, node| {
+            if let Some(bindings) = matched.get(&node.address()) {
+                matches.push(Match {
+                    position: position.clone(),
+                    root: node.clone(),
+                    bindings: bindings.clone(),
+                });
+            }
+        });
+        matches
+    }
+}
+
+// the state of a node, and the subterms bound by every operation label in it
+type Bound<P> = (StateId, Rc<HashMap<usize, Vec<P>>>);
+
+/// Iterator over the nodes of a term that match the pattern of an `IndexedTerm`. Only the part
+/// of the term up to the last returned node has been looked at.
+pub struct Matches<'a, P: TermPointer> {
+    pattern: &'a IndexedTerm<P>,
+    commutative: bool,
+    automaton: &'a Automaton,
+    states: HashMap<*const (), StateId>,
+    results: Vec<StateId>,
+    stack: Vec<(&'a P, bool)>,
+}
+
+impl<P: TermPointer> Iterator for Matches<'_, P> {
+    type Item = P;
+
+    fn next(&mut self) -> Option<P> {
+        // the traversal of `reduce_shared`, paused after every match
+        while let Some((node, expanded)) = self.stack.pop() {
+            if !expanded && let Some(&state) = self.states.get(&node.address()) {
+                self.results.push(state);
+                continue;
+            }
+            let Some((left, right)) = node.children() else {
+                self.results.push(0);
+                if !self.automaton.accepting[0].is_empty() {
+                    self.states.insert(node.address(), 0);
+                    return Some(node.clone());
                 }
-                labels
+                continue;
+            };
+            if !expanded {
+                self.stack.push((node, true));
+                self.stack.push((right, false));
+                self.stack.push((left, false));
+                continue;
+            }
+
+            let right_state = self.results.pop().unwrap();
+            let left_state = self.results.pop().unwrap();
+            let state = self.automaton.transitions[left_state as usize][right_state as usize];
+            self.states.insert(node.address(), state);
+            self.results.push(state);
+            if !self.automaton.accepting[state as usize].is_empty()
+                && self.pattern.bindings_agree(node, self.commutative)
+            {
+                return Some(node.clone());
+            }
+        }
+        None
+    }
+}
+
+/// Bottom-up automaton for several patterns at once. Subterms that the patterns have in common
+/// get one label, so matching them is done once per node of the term for all patterns.
+pub struct PatternAutomaton<P: TermPointer = TermRef> {
+    patterns: Vec<P>,
+    repeated: Vec<Vec<Vec<usize>>>,
+    automaton: Automaton,
+}
+
+impl<P: TermPointer> PatternAutomaton<P> {
+    pub fn new(patterns: Vec<P>) -> Self {
+        let mut index = TermIndexing::new();
+        let roots: Vec<_> = patterns
+            .iter()
+            .map(|pattern| index.insert(pattern))
+            .collect();
+        PatternAutomaton {
+            automaton: Automaton::new(&index, &roots, false),
+            repeated: vec![Vec::new(); patterns.len()],
+            patterns,
+        }
+    }
+
+    /// Patterns given with the variable at every leaf, as for `IndexedTerm::with_variables`.
+    #[allow(dead_code)]
+    pub fn with_variables(patterns: Vec<(P, Vec<usize>)>) -> Self {
+        let (patterns, variables): (Vec<_>, Vec<_>) = patterns.into_iter().unzip();
+        PatternAutomaton {
+            repeated: variables
+                .iter()
+                .map(|variables| repeated_leaves(variables))
+                .collect(),
+            ..PatternAutomaton::new(patterns)
+        }
+    }
+
+    /// All matches of all patterns, ordered by position in pre-order and then by pattern.
+    pub fn matches(&self, term: &P) -> Vec<(PatternId, Match<P>)> {
+        self.matches_inner(term, None)
+    }
+
+    /// Like `matches`, but adds what it did to `stats`.
+    pub fn matches_with_stats(
+        &self,
+        term: &P,
+        stats: &mut MatcherStats,
+    ) -> Vec<(PatternId, Match<P>)> {
+        self.matches_inner(term, Some(stats))
+    }
+
+    fn matches_inner(
+        &self,
+        term: &P,
+        mut stats: Option<&mut MatcherStats>,
+    ) -> Vec<(PatternId, Match<P>)> {
+        let start = stats.is_some().then(Instant::now);
+        let mut states = HashMap::new();
+        term.reduce_shared(
+            &mut |node, left, right| {
+                let state = self.automaton.transitions[left as usize][right as usize];
+                states.insert(node.address(), state);
+                state
             },
-            &mut |_| [0].into(),
+            &mut |_| 0,
         );
 
-        matched
+        let mut matches = Vec::new();
+        let mut nodes = 0;
+        term.walk_with_positions(&mut |position, node| {
+            nodes += 1;
+            let state = states.get(&node.address()).copied().unwrap_or(0);
+            for &id in &self.automaton.accepting[state as usize] {
+                let pattern_start = stats.is_some().then(Instant::now);
+                let bindings = self.patterns[id].instance_bindings(node).unwrap();
+                let agree = bindings_agree(&self.repeated[id], &bindings);
+                if let Some(stats) = stats.as_deref_mut() {
+                    let pattern = stats.pattern(id);
+                    pattern.candidates += 1;
+                    pattern.matches += agree as u64;
+                    pattern.time += pattern_start.unwrap().elapsed();
+                }
+                if !agree {
+                    continue;
+                }
+                matches.push((
+                    id,
+                    Match {
+                        position: position.clone(),
+                        root: node.clone(),
+                        bindings,
+                    },
+                ));
+            }
+        });
+
+        if let Some(stats) = stats {
+            stats.terms += 1;
+            stats.nodes += nodes;
+            stats.transitions += states.len() as u64;
+            stats.time += start.unwrap().elapsed();
+        }
+        matches
     }
 }
 
-impl Debug for IndexedTerm {
+#[derive(Clone, Default, Debug)]
+pub struct PatternStats {
+    /// Nodes where the automaton accepted the linear pattern.
+    pub candidates: u64,
+    /// Candidates where the repeated variables agree as well.
+    pub matches: u64,
+    /// Time spent on computing and checking the bindings of the candidates.
+    pub time: Duration,
+}
+
+/// Counters of a `PatternAutomaton`, summed over all terms matched with `matches_with_stats`.
+#[derive(Clone, Default, Debug)]
+pub struct MatcherStats {
+    pub terms: u64,
+    pub nodes: u64,
+    /// Lookups in the transition table, one per physical operation node.
+    pub transitions: u64,
+    pub time: Duration,
+    pub patterns: Vec<PatternStats>,
+}
+
+impl MatcherStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn pattern(&mut self, id: PatternId) -> &mut PatternStats {
+        if self.patterns.len() <= id {
+            self.patterns.resize(id + 1, PatternStats::default());
+        }
+        &mut self.patterns[id]
+    }
+}
+
+impl Display for MatcherStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} terms, {} nodes, {} transitions in {:?}",
+            self.terms, self.nodes, self.transitions, self.time
+        )?;
+        for (id, pattern) in self.patterns.iter().enumerate() {
+            writeln!(
+                f,
+                "pattern {}: {} matches of {} candidates in {:?}",
+                id, pattern.matches, pattern.candidates, pattern.time
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<P: TermPointer> Debug for IndexedTerm<P> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "IndexedTerm[{}]", self.term)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{iter::TermIterator, lterm, patindex::PatternIndex, term};
+
+    #[test]
+    fn pattern_automaton() {
+        let patterns = vec![
+            term!((a * b) * c),
+            term!(a * (b * c)),
+            term!((a * b) * (c * d)),
+            term!(a),
+            term!((a * b) * c),
+        ];
+        let automaton = PatternAutomaton::new(patterns.clone());
+        let mut index = PatternIndex::new();
+        for pattern in patterns {
+            index.insert(pattern);
+        }
+
+        for term in TermIterator::new(6) {
+            let found: Vec<_> = automaton
+                .matches(&term)
+                .into_iter()
+                .map(|(id, matched)| (id, matched.position, matched.bindings))
+                .collect();
+            let expected: Vec<_> = index
+                .matches(&term)
+                .into_iter()
+                .map(|(id, matched)| (id, matched.position, matched.bindings))
+                .collect();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn single_leaf_pattern() {
+        let leaf = term!(x);
+        let term = term!((a * b) * (c * (d * e)));
+        let nodes = term.node_count();
+
+        let indexed = IndexedTerm::from(leaf.clone());
+        assert_eq!(indexed.matches(&term).count(), nodes);
+        assert_eq!(indexed.occurrences(&term).len(), nodes);
+        let with_bindings = indexed.match_with_bindings(&term);
+        assert_eq!(with_bindings.len(), nodes);
+        assert!(
+            with_bindings
+                .iter()
+                .all(|matched| matched.bindings == [matched.root.clone()])
+        );
+
+        assert_eq!(
+            PatternAutomaton::new(vec![leaf.clone()])
+                .matches(&term)
+                .len(),
+            nodes
+        );
+        let mut index = PatternIndex::new();
+        index.insert(leaf.clone());
+        assert_eq!(index.matches(&term).len(), nodes);
+        assert_eq!(leaf.match_top_down(&term).len(), nodes);
+    }
+
+    #[test]
+    fn lazy_matches() {
+        let pattern = IndexedTerm::from(term!((a * b) * c));
+        let term = term!(((a * b) * c) * ((d * e) * f));
+        let found: Vec<_> = pattern.matches(&term).collect();
+        assert_eq!(found.len(), 3);
+        assert!(found[0] == term!((a * b) * c));
+
+        // the first match is found before the right subterm is looked at
+        let mut matches = pattern.matches(&term);
+        matches.next();
+        assert_eq!(matches.states.len(), 2);
+    }
+
+    #[test]
+    fn non_linear() {
+        let pattern = IndexedTerm::from(&lterm!(x * x));
+        assert!(!pattern.is_linear());
+        let term = term!((a * b) * ((c * d) * (e * (f * g))));
+        assert_eq!(
+            pattern.occurrences(&term).positions(),
+            ["L", "RL", "RRR"].map(|p| p.parse().unwrap())
+        );
+        assert_eq!(pattern.matches(&term).count(), 3);
+
+        // the children of the right child of the root are only equal modulo commutativity
+        let term = term!((a * b) * ((c * (d * e)) * ((f * g) * h)));
+        assert_eq!(pattern.occurrences(&term).len(), 3);
+        assert_eq!(
+            pattern.occurrences_modulo_commutativity(&term).positions(),
+            ["L", "R", "RLR", "RRL"].map(|p| p.parse().unwrap())
+        );
+
+        let labeled = [lterm!(x * (y * x)), lterm!((x * y) * z)];
+        let automaton = PatternAutomaton::with_variables(
+            labeled
+                .iter()
+                .map(|pattern| (pattern.skeleton(), pattern.variable_indices()))
+                .collect(),
+        );
+        let term = term!((a * b) * (c * (d * e)));
+        let found: Vec<_> = automaton
+            .matches(&term)
+            .into_iter()
+            .map(|(id, matched)| (id, matched.position.to_string()))
+            .collect();
+        assert_eq!(found, [(0, "ε".into()), (1, "ε".into()), (0, "R".into())]);
+    }
+
+    #[test]
+    fn matcher_stats() {
+        let labeled = [lterm!((x * y) * z), lterm!(x * x)];
+        let automaton = PatternAutomaton::with_variables(
+            labeled
+                .iter()
+                .map(|pattern| (pattern.skeleton(), pattern.variable_indices()))
+                .collect(),
+        );
+        let mut stats = MatcherStats::new();
+        let mut found = 0;
+        for term in TermIterator::new(5) {
+            found += automaton.matches_with_stats(&term, &mut stats).len();
+        }
+        assert_eq!(stats.terms, 14);
+        assert_eq!(stats.nodes, 14 * 9);
+        let matches: u64 = stats.patterns.iter().map(|pattern| pattern.matches).sum();
+        assert_eq!(matches, found as u64);
+        // every operation node is a candidate for x * x
+        assert_eq!(stats.patterns[1].candidates, 14 * 4);
+        assert!(stats.patterns[1].matches < stats.patterns[1].candidates);
+    }
+}
@@ -1,28 +1,421 @@
-use std::rc::Rc;
+use std::{
+    fmt::Display,
+    iter::FusedIterator,
+    ops::RangeInclusive,
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
-use crate::term::{Term, TermRef};
+use serde::{Deserialize, Serialize};
 
-pub enum TermIterator {
-    InnerIterator(bool, Box<Self>, Box<Self>, usize, usize, TermRef),
-    LeafIterator(bool),
+use crate::{
+    bidag::BinaryChildren,
+    labeled::LabeledTermRef,
+    term::{Term, TermRef},
+};
+
+// a node of the current term, whose subterm is `leaves` big with `split` leaves on the left
+#[derive(Clone)]
+struct Frame {
+    leaves: usize,
+    split: usize,
+    // the index of the subterm in the pool, only kept if it is small enough to be in there
+    rank: usize,
+    term: TermRef,
+}
+
+/// Numbers of leaves for `TermIterator::new`, either one or an inclusive range.
+pub trait LeafCounts {
+    fn bounds(self) -> (usize, usize);
+}
+
+impl LeafCounts for usize {
+    fn bounds(self) -> (usize, usize) {
+        (self, self)
+    }
+}
+
+impl LeafCounts for RangeInclusive<usize> {
+    fn bounds(self) -> (usize, usize) {
+        (*self.start(), *self.end())
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct SizeStats {
+    pub terms: u64,
+    /// Operation nodes built for the terms.
+    pub allocations: u64,
+    pub time: Duration,
+}
+
+/// Counters of a `TermIterator` made with `with_stats`, by number of leaves.
+#[derive(Clone, Default, Debug)]
+pub struct EnumerationStats {
+    pub sizes: Vec<SizeStats>,
+}
+
+impl EnumerationStats {
+    fn size(&mut self, leaves: usize) -> &mut SizeStats {
+        if self.sizes.len() <= leaves {
+            self.sizes.resize(leaves + 1, SizeStats::default());
+        }
+        &mut self.sizes[leaves]
+    }
+}
+
+impl Display for EnumerationStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (leaves, size) in self.sizes.iter().enumerate() {
+            if size.terms == 0 {
+                continue;
+            }
+            writeln!(
+                f,
+                "{} leaves: {} terms, {} allocations in {:?}",
+                leaves, size.terms, size.allocations, size.time
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Where a `TermIterator` is, from which `TermIterator::resume` goes on.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Cursor {
+    /// Number of leaves of the next term.
+    pub leaves: usize,
+    /// Rank of the next term among those with `leaves` leaves.
+    pub rank: u128,
+    pub max_leaves: usize,
+    /// How many more terms to return, if fewer than are left.
+    pub limit: Option<u128>,
+}
+
+/// Iterator over all terms with a number of leaves, by increasing number of leaves if there are
+/// several. For every split into a number of leaves on the left and on the right, the right
+/// subterm changes slower than the left one.
+///
+/// The current term is kept as its nodes in pre-order, so the subterm of a node with `split`
+/// leaves on the left starts right after it, and the right one `2 * split` nodes after it. Each
+/// step only rebuilds the nodes above the subterm that changed and shares all others.
+pub struct TermIterator {
+    frames: Vec<Frame>,
+    // the right combs by number of leaves, which are the first terms
+    combs: Vec<TermRef>,
+    // all terms with up to `pool.len() - 1` leaves by number of leaves, in order
+    pool: Rc<Vec<Vec<TermRef>>>,
+    leaves: usize,
+    max_leaves: usize,
+    started: bool,
+    // how many more terms to return, if fewer than are left
+    limit: Option<u128>,
+    // the operations built while stepping
+    allocations: u64,
+    stats: Option<EnumerationStats>,
 }
 
 impl TermIterator {
-    pub fn new(leaves: usize) -> Self {
-        if leaves == 1 {
-            Self::LeafIterator(false)
+    pub fn new(leaves: impl LeafCounts) -> Self {
+        TermIterator::with_pool(leaves, 0)
+    }
+
+    /// Like `new`, but every subterm with at most `pool_leaves` leaves is taken from a pool of all
+    /// of them that is built once. Equal small subterms of all terms are then the same node, which
+    /// helps analyses that are memoized by address.
+    pub fn with_pool(leaves: impl LeafCounts, pool_leaves: usize) -> Self {
+        let (min_leaves, max_leaves) = leaves.bounds();
+        assert!(min_leaves > 0, "terms have at least one leaf");
+        let pool = shape_pool(pool_leaves.min(max_leaves));
+        // `combs` is indexed from one leaf on
+        let mut combs = vec![Rc::new(Term::Variable)];
+        for leaves in 1..=max_leaves {
+            let comb = match pool.get(leaves) {
+                Some(shapes) => shapes[0].clone(),
+                None if leaves == 1 => combs[0].clone(),
+                None => Term::operation(combs[1].clone(), combs[leaves - 1].clone()),
+            };
+            combs.push(comb);
+        }
+
+        let mut iter = TermIterator {
+            frames: Vec::new(),
+            combs,
+            pool: Rc::new(pool),
+            leaves: min_leaves,
+            max_leaves,
+            started: false,
+            limit: None,
+            allocations: 0,
+            stats: None,
+        };
+        iter.start();
+        iter
+    }
+
+    // the number of leaves and the rank of the next term, `self.max_leaves + 1` leaves if there
+    // is none
+    fn next_position(&self) -> (usize, u128) {
+        if self.leaves > self.max_leaves {
+            return (self.leaves, 0);
+        }
+        let rank = self.frames[0].term.rank() + self.started as u128;
+        if rank == Term::shape_count(self.leaves) {
+            (self.leaves + 1, 0)
         } else {
-            let mut right = TermIterator::new(leaves - 1);
-            let right_subtree = right.next().unwrap();
-            Self::InnerIterator(
-                false,
-                Box::new(TermIterator::LeafIterator(false)),
-                Box::new(right),
-                1,
-                leaves - 1,
-                right_subtree,
-            )
+            (self.leaves, rank)
+        }
+    }
+
+    // the number of leaves and the rank of the term `rank` terms into those with `leaves` leaves,
+    // going on with more leaves past the last of them
+    fn locate(&self, mut leaves: usize, mut rank: u128) -> (usize, u128) {
+        while leaves <= self.max_leaves && rank >= Term::shape_count(leaves) {
+            rank -= Term::shape_count(leaves);
+            leaves += 1;
+        }
+        (leaves, rank)
+    }
+
+    // an iterator over the same terms from the one `locate` finds on
+    fn seek(&self, leaves: usize, rank: u128) -> Self {
+        let (leaves, rank) = self.locate(leaves, rank);
+        let mut iter = TermIterator {
+            frames: Vec::new(),
+            combs: self.combs.clone(),
+            pool: self.pool.clone(),
+            leaves,
+            max_leaves: self.max_leaves,
+            started: false,
+            limit: None,
+            allocations: 0,
+            stats: None,
+        };
+        if leaves <= self.max_leaves {
+            let mut frames = Vec::with_capacity(2 * leaves - 1);
+            Term::unrank(leaves, rank).walk_preorder(&mut |node| {
+                let split = node.children().map_or(0, |(left, _)| left.leaves());
+                frames.push(Frame {
+                    leaves: node.leaves(),
+                    split,
+                    rank: 0,
+                    term: node.clone(),
+                });
+            });
+            // children come after their parents
+            for index in (0..frames.len()).rev() {
+                let Frame { leaves, split, .. } = frames[index];
+                if let Some(shapes) = self.pool.get(leaves) {
+                    frames[index].rank = frames[index].term.rank() as usize;
+                    frames[index].term = shapes[frames[index].rank].clone();
+                } else if leaves > 1 {
+                    frames[index].term = Term::operation(
+                        frames[index + 1].term.clone(),
+                        frames[index + 2 * split].term.clone(),
+                    );
+                }
+            }
+            iter.frames = frames;
+        }
+        iter
+    }
+
+    /// Counts what the iterator does from now on, see `stats`.
+    pub fn with_stats(mut self) -> Self {
+        self.stats = Some(EnumerationStats::default());
+        self
+    }
+
+    pub fn stats(&self) -> Option<&EnumerationStats> {
+        self.stats.as_ref()
+    }
+
+    /// The position of the iterator, to go on from later or in another process.
+    #[allow(dead_code)]
+    pub fn cursor(&self) -> Cursor {
+        let (leaves, rank) = self.next_position();
+        Cursor {
+            leaves,
+            rank,
+            max_leaves: self.max_leaves,
+            limit: self.limit,
+        }
+    }
+
+    /// An iterator over the terms that were left when `cursor` was taken.
+    #[allow(dead_code)]
+    pub fn resume(cursor: Cursor) -> Self {
+        let first = cursor.leaves.min(cursor.max_leaves);
+        let mut iter =
+            TermIterator::new(first..=cursor.max_leaves).seek(cursor.leaves, cursor.rank);
+        iter.limit = cursor.limit;
+        iter
+    }
+
+    /// The term `index` terms after the next one, like `nth` but without moving the iterator.
+    /// Found by unranking, so it takes time linear in the size of the term, not in `index`.
+    #[allow(dead_code)]
+    pub fn nth_term(&self, index: u128) -> Option<TermRef> {
+        if self.limit.is_some_and(|limit| limit <= index) {
+            return None;
+        }
+        let (leaves, rank) = self.next_position();
+        let (leaves, rank) = self.locate(leaves, rank + index);
+        (leaves <= self.max_leaves).then(|| Term::unrank(leaves, rank))
+    }
+
+    /// The number of terms left, e.g. for reporting progress. `None` if it does not fit into
+    /// `u128`, which happens past about 70 leaves.
+    pub fn checked_remaining(&self) -> Option<u128> {
+        let total = (self.leaves..=self.max_leaves)
+            .map(Term::checked_shape_count)
+            .try_fold(0u128, |sum, count| sum.checked_add(count?));
+        let left = match total {
+            Some(_) => {
+                let (leaves, rank) = self.next_position();
+                (leaves..=self.max_leaves)
+                    .map(Term::shape_count)
+                    .sum::<u128>()
+                    .saturating_sub(rank)
+            }
+            // too many to count, but then also more than any limit
+            None if self.limit.is_some() => u128::MAX,
+            None => return None,
+        };
+        Some(self.limit.map_or(left, |limit| limit.min(left)))
+    }
+
+    // the number of terms left
+    fn remaining(&self) -> u128 {
+        self.checked_remaining()
+            .expect("number of terms does not fit into u128")
+    }
+
+    /// The next `index` terms, and the ones after them. Both can be enumerated independently,
+    /// e.g. on different threads.
+    #[allow(dead_code)]
+    pub fn split_at(self, index: u128) -> (Self, Self) {
+        let (leaves, rank) = self.next_position();
+        let mut rest = self.seek(leaves, rank + index);
+        rest.limit = self.limit.map(|limit| limit.saturating_sub(index));
+        let mut first = self.seek(leaves, rank);
+        first.limit = Some(self.limit.map_or(index, |limit| limit.min(index)));
+        (first, rest)
+    }
+
+    /// Splits the terms left into `n` consecutive parts whose sizes differ by at most one.
+    #[allow(dead_code)]
+    pub fn chunks(self, n: u128) -> Vec<Self> {
+        assert!(n > 0, "no chunks to split into");
+        let remaining = self.remaining();
+        let mut chunks = Vec::new();
+        let mut rest = self;
+        for chunk in 0..n - 1 {
+            let size = remaining / n + (chunk < remaining % n) as u128;
+            let (first, next) = rest.split_at(size);
+            chunks.push(first);
+            rest = next;
         }
+        chunks.push(rest);
+        chunks
+    }
+
+    // the next term, without counting it
+    fn step(&mut self) -> Option<TermRef> {
+        if self.leaves > self.max_leaves || self.limit == Some(0) {
+            return None;
+        }
+        if self.started && !self.advance(0) {
+            self.leaves += 1;
+            self.start();
+            return self.step();
+        }
+        self.started = true;
+        if let Some(limit) = &mut self.limit {
+            *limit -= 1;
+        }
+        Some(self.frames[0].term.clone())
+    }
+
+    // makes the current term the first one with `self.leaves` leaves, if there are any left
+    fn start(&mut self) {
+        if self.leaves > self.max_leaves {
+            return;
+        }
+        let frame = Frame {
+            leaves: 1,
+            split: 0,
+            rank: 0,
+            term: self.combs[1].clone(),
+        };
+        self.frames = vec![frame; 2 * self.leaves - 1];
+        self.reset(0, self.leaves);
+        self.started = false;
+    }
+
+    // makes the subterm at `index` the right comb with `leaves` leaves
+    fn reset(&mut self, index: usize, leaves: usize) {
+        for offset in 0..leaves - 1 {
+            self.frames[index + 2 * offset] = Frame {
+                leaves: leaves - offset,
+                split: 1,
+                rank: 0,
+                term: self.combs[leaves - offset].clone(),
+            };
+            self.frames[index + 2 * offset + 1] = Frame {
+                leaves: 1,
+                split: 0,
+                rank: 0,
+                term: self.combs[1].clone(),
+            };
+        }
+        self.frames[index + 2 * (leaves - 1)] = Frame {
+            leaves: 1,
+            split: 0,
+            rank: 0,
+            term: self.combs[1].clone(),
+        };
+    }
+
+    // moves the subterm at `index` to the next one of its size, false if it was the last
+    fn advance(&mut self, index: usize) -> bool {
+        let Frame {
+            leaves,
+            split,
+            rank,
+            ..
+        } = self.frames[index];
+        if leaves == 1 {
+            return false;
+        }
+        if let Some(shapes) = self.pool.get(leaves) {
+            let Some(term) = shapes.get(rank + 1) else {
+                return false;
+            };
+            self.frames[index].rank = rank + 1;
+            self.frames[index].term = term.clone();
+            return true;
+        }
+        let (left, right) = (index + 1, index + 2 * split);
+        if !self.advance(left) {
+            if self.advance(right) {
+                self.reset(left, split);
+            } else if split + 1 < leaves {
+                self.frames[index].split = split + 1;
+                self.reset(left, split + 1);
+                self.reset(index + 2 * (split + 1), leaves - split - 1);
+            } else {
+                return false;
+            }
+        }
+
+        let right = index + 2 * self.frames[index].split;
+        self.allocations += 1;
+        self.frames[index].term = Term::operation(
+            self.frames[left].term.clone(),
+            self.frames[right].term.clone(),
+        );
+        true
     }
 }
 
@@ -30,42 +423,630 @@ impl Iterator for TermIterator {
     type Item = TermRef;
 
     fn next(&mut self) -> Option<TermRef> {
-        match self {
-            Self::LeafIterator(done) => {
-                if !*done {
-                    *done = true;
-                    Some(Rc::new(Term::Variable))
-                } else {
-                    None
+        if self.stats.is_none() {
+            return self.step();
+        }
+        let (start, allocations) = (Instant::now(), self.allocations);
+        let term = self.step()?;
+        let size = self.stats.as_mut().unwrap().size(term.leaves());
+        size.terms += 1;
+        size.allocations += self.allocations - allocations;
+        size.time += start.elapsed();
+        Some(term)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.checked_remaining().map(usize::try_from) {
+            Some(Ok(remaining)) => (remaining, Some(remaining)),
+            _ => (usize::MAX, None),
+        }
+    }
+
+    // jumps there by unranking instead of stepping
+    fn nth(&mut self, n: usize) -> Option<TermRef> {
+        let n = n as u128;
+        if self.limit.is_some_and(|limit| limit <= n) {
+            self.limit = Some(0);
+            return None;
+        }
+        let (leaves, rank) = self.next_position();
+        let limit = self.limit.map(|limit| limit - n);
+        let stats = self.stats.take();
+        *self = self.seek(leaves, rank + n);
+        (self.limit, self.stats) = (limit, stats);
+        self.next()
+    }
+}
+
+impl FusedIterator for TermIterator {}
+
+// all terms with up to `max_leaves` leaves, by number of leaves and in the order of
+// `TermIterator`, built from each other
+fn shape_pool(max_leaves: usize) -> Vec<Vec<TermRef>> {
+    let mut pool = vec![Vec::new()];
+    if max_leaves == 0 {
+        return pool;
+    }
+    pool.push(vec![Rc::new(Term::Variable)]);
+    for leaves in 2..=max_leaves {
+        let mut shapes = Vec::new();
+        for left_leaves in 1..leaves {
+            for right in &pool[leaves - left_leaves] {
+                for left in &pool[left_leaves] {
+                    shapes.push(Term::operation(left.clone(), right.clone()));
                 }
             }
-            Self::InnerIterator(done, left, right, left_leaves, right_leaves, right_subtree) => {
-                if let Some(left_subtree) = left.next() {
-                    return Some(Rc::new(Term::Operation(
-                        left_subtree,
-                        right_subtree.clone(),
-                    )));
-                };
-
-                if let Some(subtree) = right.next() {
-                    *right_subtree = subtree;
-                    *left = Box::new(TermIterator::new(*left_leaves));
-                    return self.next();
-                };
-
-                *left_leaves += 1;
-                *right_leaves -= 1;
-
-                if *right_leaves == 0 {
-                    *done = true;
+        }
+        pool.push(shapes);
+    }
+    pool
+}
+
+/// Iterator over the terms of `TermIterator`, but taking turns between the numbers of leaves
+/// instead of going through them one after the other. Every round starts on the next number of
+/// leaves and takes one term of each started one, so a run cut short has terms of all sizes up
+/// to some point and not only small ones.
+pub struct DovetailTermIterator {
+    sizes: Vec<TermIterator>,
+    // the next number of leaves to start
+    leaves: usize,
+    max_leaves: usize,
+    // the size to take the next term from
+    turn: usize,
+}
+
+impl DovetailTermIterator {
+    pub fn new(leaves: impl LeafCounts) -> Self {
+        let (min_leaves, max_leaves) = leaves.bounds();
+        assert!(min_leaves > 0, "terms have at least one leaf");
+        DovetailTermIterator {
+            sizes: Vec::new(),
+            leaves: min_leaves,
+            max_leaves,
+            turn: 0,
+        }
+    }
+}
+
+impl Iterator for DovetailTermIterator {
+    type Item = TermRef;
+
+    fn next(&mut self) -> Option<TermRef> {
+        loop {
+            if self.turn == self.sizes.len() {
+                if self.leaves <= self.max_leaves {
+                    self.sizes.push(TermIterator::new(self.leaves));
+                    self.leaves += 1;
+                } else if self.sizes.is_empty() {
                     return None;
                 }
+                self.turn = 0;
+            }
+            match self.sizes[self.turn].next() {
+                Some(term) => {
+                    self.turn += 1;
+                    return Some(term);
+                }
+                None => {
+                    self.sizes.remove(self.turn);
+                }
+            }
+        }
+    }
+}
+
+impl FusedIterator for DovetailTermIterator {}
 
-                *left = Box::new(TermIterator::new(*left_leaves));
-                *right = Box::new(TermIterator::new(*right_leaves));
-                *right_subtree = right.next().unwrap();
-                self.next()
+/// Which labels `LabeledTermIterator` puts on the leaves of every shape, and which operators
+/// `OperatorTermIterator` puts on its operations.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Labeling {
+    /// Every assignment of labels to the leaves.
+    #[default]
+    All,
+    /// One assignment out of those that only differ by renaming the labels, namely the one that
+    /// uses them in the order of the alphabet from left to right. Enough if the labels are
+    /// variables, but not if they are constants.
+    UpToRenaming,
+}
+
+// moves to the next assignment of `count` labels in lexicographic order, false if it was the last
+fn next_labeling(labels: &mut [usize], count: usize, labeling: Labeling) -> bool {
+    for index in (0..labels.len()).rev() {
+        let mut bound = count - 1;
+        if labeling == Labeling::UpToRenaming {
+            let used = labels[..index].iter().max().map_or(0, |&max| max + 1);
+            bound = bound.min(used);
+        }
+        if labels[index] < bound {
+            labels[index] += 1;
+            labels[index + 1..].fill(0);
+            return true;
+        }
+    }
+    false
+}
+
+/// Iterator over the terms of `TermIterator` with labels from an alphabet on their leaves. The
+/// labelings of every shape come in lexicographic order, with the labels ordered like the
+/// alphabet.
+pub struct LabeledTermIterator<T> {
+    shapes: TermIterator,
+    alphabet: Vec<T>,
+    labeling: Labeling,
+    shape: Option<TermRef>,
+    // the label of every leaf as an index into `alphabet`
+    labels: Vec<usize>,
+}
+
+impl<T: Clone> LabeledTermIterator<T> {
+    #[allow(dead_code)]
+    pub fn new(leaves: impl LeafCounts, alphabet: Vec<T>, labeling: Labeling) -> Self {
+        LabeledTermIterator {
+            shapes: TermIterator::new(leaves),
+            alphabet,
+            labeling,
+            shape: None,
+            labels: Vec::new(),
+        }
+    }
+}
+
+impl<T: Clone> Iterator for LabeledTermIterator<T> {
+    type Item = LabeledTermRef<T>;
+
+    fn next(&mut self) -> Option<LabeledTermRef<T>> {
+        if self.alphabet.is_empty() {
+            return None;
+        }
+        let count = self.alphabet.len();
+        if self.shape.is_none() || !next_labeling(&mut self.labels, count, self.labeling) {
+            let shape = self.shapes.next()?;
+            self.labels = vec![0; shape.leaves()];
+            self.shape = Some(shape);
+        }
+        let mut labels = self.labels.iter();
+        let shape = self.shape.as_ref().unwrap();
+        Some(shape.map(&mut |_| self.alphabet[*labels.next().unwrap()].clone()))
+    }
+}
+
+/// Iterator over the terms of `TermIterator` with one of several operators at every operation.
+/// Terms only have a single operation, so the operators are returned next to the term, as indices
+/// in `0..operators` for its operations in pre-order. They come in lexicographic order for every
+/// term.
+pub struct OperatorTermIterator {
+    shapes: TermIterator,
+    operators: usize,
+    labeling: Labeling,
+    shape: Option<TermRef>,
+    assignment: Vec<usize>,
+}
+
+impl OperatorTermIterator {
+    #[allow(dead_code)]
+    pub fn new(leaves: impl LeafCounts, operators: usize, labeling: Labeling) -> Self {
+        assert!(operators > 0, "terms with operations need an operator");
+        OperatorTermIterator {
+            shapes: TermIterator::new(leaves),
+            operators,
+            labeling,
+            shape: None,
+            assignment: Vec::new(),
+        }
+    }
+}
+
+impl Iterator for OperatorTermIterator {
+    type Item = (TermRef, Vec<usize>);
+
+    fn next(&mut self) -> Option<(TermRef, Vec<usize>)> {
+        let advanced = next_labeling(&mut self.assignment, self.operators, self.labeling);
+        if self.shape.is_none() || !advanced {
+            let shape = self.shapes.next()?;
+            self.assignment = vec![0; shape.leaves() - 1];
+            self.shape = Some(shape);
+        }
+        Some((self.shape.clone().unwrap(), self.assignment.clone()))
+    }
+}
+
+// the terms with up to `max_leaves` leaves all of whose subterms are kept, by number of leaves
+// and in the order of `TermIterator`, built from each other
+fn kept_pool(max_leaves: usize, keep: &dyn Fn(&TermRef) -> bool) -> Vec<Vec<TermRef>> {
+    let mut pool = vec![Vec::new()];
+    if max_leaves == 0 {
+        return pool;
+    }
+    let leaf = Rc::new(Term::Variable);
+    pool.push(keep(&leaf).then_some(leaf).into_iter().collect());
+    for leaves in 2..=max_leaves {
+        let mut terms = Vec::new();
+        for left_leaves in 1..leaves {
+            for right in &pool[leaves - left_leaves] {
+                for left in &pool[left_leaves] {
+                    let term = Term::operation(left.clone(), right.clone());
+                    if keep(&term) {
+                        terms.push(term);
+                    }
+                }
             }
         }
+        pool.push(terms);
+    }
+    pool
+}
+
+// Like an entry of `kept_pool`, but built one term after the other from the kept terms with
+// fewer leaves, so the terms of the largest size are never all stored.
+fn kept_terms(
+    leaves: usize,
+    pool: &Rc<Vec<Vec<TermRef>>>,
+    keep: &Rc<dyn Fn(&TermRef) -> bool>,
+) -> Box<dyn Iterator<Item = TermRef>> {
+    if leaves < pool.len() {
+        return Box::new(pool[leaves].clone().into_iter());
+    }
+    let (pool, keep) = (pool.clone(), keep.clone());
+    Box::new((1..leaves).flat_map(move |left_leaves| {
+        let (pool, keep) = (pool.clone(), keep.clone());
+        (0..pool[leaves - left_leaves].len()).flat_map(move |right| {
+            let (pool, keep) = (pool.clone(), keep.clone());
+            (0..pool[left_leaves].len()).filter_map(move |left| {
+                let term = Term::operation(
+                    pool[left_leaves][left].clone(),
+                    pool[leaves - left_leaves][right].clone(),
+                );
+                keep(&term).then_some(term)
+            })
+        })
+    }))
+}
+
+/// Iterator over the terms of `TermIterator` all of whose subterms are kept by a predicate.
+/// Terms are only built from kept subterms, so a subterm that is not kept is never extended. For
+/// a predicate that holds for all subterms of a term it holds for, these are exactly the terms it
+/// holds for, so it is enough for it to look at the root.
+pub struct PrunedTermIterator {
+    terms: Box<dyn Iterator<Item = TermRef>>,
+}
+
+impl PrunedTermIterator {
+    #[allow(dead_code)]
+    pub fn new(leaves: impl LeafCounts, keep: impl Fn(&TermRef) -> bool + 'static) -> Self {
+        let (min_leaves, max_leaves) = leaves.bounds();
+        assert!(min_leaves > 0, "terms have at least one leaf");
+        let keep: Rc<dyn Fn(&TermRef) -> bool> = Rc::new(keep);
+        // the proper subterms are stored, each built once
+        let pool = Rc::new(kept_pool(max_leaves.max(2) - 1, keep.as_ref()));
+        PrunedTermIterator {
+            terms: Box::new(
+                (min_leaves..=max_leaves).flat_map(move |leaves| kept_terms(leaves, &pool, &keep)),
+            ),
+        }
+    }
+}
+
+impl Iterator for PrunedTermIterator {
+    type Item = TermRef;
+
+    fn next(&mut self) -> Option<TermRef> {
+        self.terms.next()
+    }
+}
+
+// the terms whose depth is at most `depth`, or exactly `depth` if `exact`
+fn terms_of_depth(depth: usize, exact: bool) -> Box<dyn Iterator<Item = TermRef>> {
+    let leaf = std::iter::once(Rc::new(Term::Variable));
+    if depth == 0 {
+        return Box::new(leaf);
+    }
+    let pairs = |rights: Box<dyn Iterator<Item = TermRef>>, exact_left: bool| {
+        rights.flat_map(move |right| {
+            terms_of_depth(depth - 1, exact_left)
+                .map(move |left| Term::operation(left, right.clone()))
+        })
+    };
+    // one child is exactly one shallower, first the right one
+    let deepest = pairs(terms_of_depth(depth - 1, true), false);
+    let left_deeper = (depth >= 2).then(|| pairs(terms_of_depth(depth - 2, false), true));
+    let left_deeper = left_deeper.into_iter().flatten();
+    if exact {
+        Box::new(deepest.chain(left_deeper))
+    } else {
+        Box::new(
+            leaf.chain(
+                (1..depth)
+                    .flat_map(|depth| terms_of_depth(depth, true))
+                    .chain(deepest)
+                    .chain(left_deeper),
+            ),
+        )
+    }
+}
+
+/// Iterator over all terms of at most a depth, by increasing depth. There are `a(d)` terms of
+/// depth at most `d`, with `a(0) = 1` and `a(d + 1) = a(d)^2 + 1`.
+pub struct DepthTermIterator {
+    terms: Box<dyn Iterator<Item = TermRef>>,
+}
+
+impl DepthTermIterator {
+    #[allow(dead_code)]
+    pub fn new(max_depth: usize) -> Self {
+        DepthTermIterator {
+            terms: terms_of_depth(max_depth, false),
+        }
+    }
+}
+
+impl Iterator for DepthTermIterator {
+    type Item = TermRef;
+
+    fn next(&mut self) -> Option<TermRef> {
+        self.terms.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::*;
+
+    // the terms in the order of the iterator, by recursion
+    fn terms(leaves: usize) -> Vec<TermRef> {
+        if leaves == 1 {
+            return vec![Rc::new(Term::Variable)];
+        }
+        let mut all = Vec::new();
+        for split in 1..leaves {
+            for right in terms(leaves - split) {
+                for left in terms(split) {
+                    all.push(Term::operation(left, right.clone()));
+                }
+            }
+        }
+        all
+    }
+
+    #[test]
+    fn term_iterator() {
+        for leaves in 1..=9 {
+            assert_eq!(TermIterator::new(leaves).collect::<Vec<_>>(), terms(leaves));
+        }
+        let mut iter = TermIterator::new(3);
+        assert_eq!(iter.by_ref().count(), 2);
+        assert_eq!(iter.next(), None);
+
+        let sizes: Vec<_> = TermIterator::new(2..=5).map(|term| term.leaves()).collect();
+        let expected: Vec<_> = (2..=5)
+            .flat_map(|leaves| vec![leaves; terms(leaves).len()])
+            .collect();
+        assert_eq!(sizes, expected);
+    }
+
+    #[test]
+    fn nth_term() {
+        let all: Vec<_> = TermIterator::new(3..=7).collect();
+        let mut iter = TermIterator::new(3..=7);
+        for index in [0, 5, 20, 2, 100] {
+            assert_eq!(iter.nth_term(index), all.get(index as usize).cloned());
+        }
+        assert_eq!(iter.nth(20).as_ref(), Some(&all[20]));
+        assert_eq!(iter.nth_term(3).as_ref(), Some(&all[24]));
+        assert_eq!(iter.next().as_ref(), Some(&all[21]));
+        let rest: Vec<_> = iter.collect();
+        assert_eq!(rest, all[22..]);
+
+        // inside a chunk, only its own terms count
+        let (mut first, _) = TermIterator::new(3..=7).split_at(30);
+        assert_eq!(first.nth_term(30), None);
+        assert_eq!(first.nth(29).as_ref(), Some(&all[29]));
+        assert_eq!(first.next(), None);
+        assert_eq!(TermIterator::new(3..=7).nth(all.len()), None);
+    }
+
+    #[test]
+    fn chunks() {
+        let all: Vec<_> = TermIterator::new(1..=7).collect();
+        for n in [1, 2, 5, 200] {
+            let chunks = TermIterator::new(1..=7).chunks(n);
+            assert_eq!(chunks.len(), n as usize);
+            let lengths: Vec<_> = chunks.iter().map(|chunk| chunk.remaining()).collect();
+            assert!(lengths.iter().max().unwrap() - lengths.iter().min().unwrap() <= 1);
+            let joined: Vec<_> = chunks.into_iter().flatten().collect();
+            assert_eq!(joined, all);
+        }
+
+        // splitting after some terms were taken, and splitting again
+        let mut iter = TermIterator::new(4..=6);
+        iter.nth(3);
+        let (first, rest) = iter.split_at(10);
+        let (second, third) = rest.split_at(30);
+        let all: Vec<_> = TermIterator::new(4..=6).skip(4).collect();
+        assert_eq!(first.collect::<Vec<_>>(), all[..10]);
+        assert_eq!(second.collect::<Vec<_>>(), all[10..40]);
+        assert_eq!(third.collect::<Vec<_>>(), all[40..]);
+    }
+
+    #[test]
+    fn resume() {
+        let all: Vec<_> = TermIterator::new(3..=6).collect();
+        for taken in [0, 1, 4, 5, 50, all.len()] {
+            let mut iter = TermIterator::new(3..=6);
+            iter.by_ref().take(taken).for_each(drop);
+            let resumed: Vec<_> = TermIterator::resume(iter.cursor()).collect();
+            assert_eq!(resumed, all[taken..]);
+        }
+
+        let (mut first, _) = TermIterator::new(3..=6).split_at(20);
+        first.nth(3);
+        let cursor = first.cursor();
+        assert_eq!(cursor.limit, Some(16));
+        assert_eq!(TermIterator::resume(cursor).collect::<Vec<_>>(), all[4..20]);
+    }
+
+    #[test]
+    fn remaining() {
+        let mut iter = TermIterator::new(2..=5);
+        assert_eq!(iter.checked_remaining(), Some(1 + 2 + 5 + 14));
+        iter.nth(5);
+        assert_eq!(iter.size_hint(), (16, Some(16)));
+        let (first, rest) = iter.split_at(3);
+        assert_eq!(
+            (first.checked_remaining(), rest.checked_remaining()),
+            (Some(3), Some(13))
+        );
+        // more than `usize::MAX` terms, but they can still be counted
+        let many = TermIterator::new(40);
+        assert_eq!(many.size_hint(), (usize::MAX, None));
+        assert_eq!(many.checked_remaining(), Some(Term::shape_count(40)));
+        assert_eq!(TermIterator::new(1..=1000).checked_remaining(), None);
+    }
+
+    #[test]
+    fn labeled_term_iterator() {
+        let all: Vec<_> = LabeledTermIterator::new(3, vec!['x', 'y'], Labeling::All)
+            .map(|term| term.to_string())
+            .collect();
+        // two shapes with 2^3 labelings each
+        assert_eq!(all.len(), 16);
+        assert_eq!(all[..3], ["x * (x * x)", "x * (x * y)", "x * (y * x)"]);
+
+        // the restricted growth strings of three leaves are xxx, xxy, xyx, xyy and xyz
+        let renamed: Vec<_> =
+            LabeledTermIterator::new(3, vec!['x', 'y', 'z'], Labeling::UpToRenaming)
+                .map(|term| term.to_string())
+                .collect();
+        assert_eq!(renamed.len(), 10);
+        assert_eq!(
+            renamed[..5],
+            [
+                "x * (x * x)",
+                "x * (x * y)",
+                "x * (y * x)",
+                "x * (y * y)",
+                "x * (y * z)"
+            ]
+        );
+        assert_eq!(
+            LabeledTermIterator::<char>::new(3, vec![], Labeling::All).count(),
+            0
+        );
+    }
+
+    #[test]
+    fn depth_term_iterator() {
+        let terms: Vec<_> = DepthTermIterator::new(3).collect();
+        assert_eq!(terms.len(), 26);
+        assert!(terms.is_sorted_by_key(|term| term.depth()));
+        let expected: HashSet<_> = TermIterator::new(1..=8)
+            .filter(|term| term.depth() <= 3)
+            .collect();
+        assert_eq!(terms.into_iter().collect::<HashSet<_>>(), expected);
+        assert_eq!(DepthTermIterator::new(4).count(), 677);
+    }
+
+    #[test]
+    fn pruned_term_iterator() {
+        let shallow: Vec<_> = PrunedTermIterator::new(1..=7, |term| term.depth() <= 3).collect();
+        let expected: Vec<_> = TermIterator::new(1..=7)
+            .filter(|term| term.depth() <= 3)
+            .collect();
+        assert_eq!(shallow, expected);
+
+        // only looking at the root, at most two leaves on the left of every node
+        let short_left =
+            |term: &TermRef| term.children().is_none_or(|(left, _)| left.leaves() <= 2);
+        let pruned: Vec<_> = PrunedTermIterator::new(6, short_left).collect();
+        let expected: Vec<_> = TermIterator::new(6)
+            .filter(|term| term.subterms().all(|(_, subterm)| short_left(subterm)))
+            .collect();
+        assert_eq!(pruned, expected);
+
+        // every term is built and checked once, not again as a subterm of larger ones
+        let calls = Rc::new(std::cell::Cell::new(0));
+        let counted = calls.clone();
+        let all = PrunedTermIterator::new(1..=6, move |_| {
+            counted.set(counted.get() + 1);
+            true
+        });
+        assert_eq!(all.count(), 1 + 1 + 2 + 5 + 14 + 42);
+        assert_eq!(calls.get(), 1 + 1 + 2 + 5 + 14 + 42);
+        assert_eq!(PrunedTermIterator::new(1, |_| true).count(), 1);
+    }
+
+    #[test]
+    fn with_pool() {
+        let all: Vec<_> = TermIterator::new(1..=8).collect();
+        let pooled: Vec<_> = TermIterator::with_pool(1..=8, 4).collect();
+        assert_eq!(pooled, all);
+
+        // small subterms are shared across all terms, also after jumping
+        let mut nodes: HashMap<TermRef, *const Term> = HashMap::new();
+        let mut iter = TermIterator::with_pool(6..=8, 4);
+        iter.nth(100);
+        for term in iter.chunks(3).into_iter().flatten() {
+            for (_, subterm) in term.subterms() {
+                if subterm.leaves() <= 4 {
+                    let node = *nodes.entry(subterm.clone()).or_insert(Rc::as_ptr(subterm));
+                    assert_eq!(node, Rc::as_ptr(subterm));
+                }
+            }
+        }
+        assert_eq!(nodes.len(), 1 + 1 + 2 + 5);
+    }
+
+    #[test]
+    fn operator_term_iterator() {
+        let all: Vec<_> = OperatorTermIterator::new(3, 2, Labeling::All).collect();
+        assert_eq!(all.len(), 2 * 4);
+        assert_eq!(all[1].1, [0, 1]);
+        assert_eq!((&all[3].1[..], &all[4].1[..]), (&[1, 1][..], &[0, 0][..]));
+        assert_ne!(all[3].0, all[4].0);
+
+        // both operators can be swapped, as for distributivity of min and max
+        let renamed: Vec<_> = OperatorTermIterator::new(1..=4, 2, Labeling::UpToRenaming)
+            .map(|(_, operators)| operators)
+            .collect();
+        assert_eq!(renamed.len(), 1 + 1 + 2 * 2 + 5 * 4);
+        assert!(
+            renamed
+                .iter()
+                .all(|operators| operators.first() != Some(&1))
+        );
+    }
+
+    #[test]
+    fn dovetail_term_iterator() {
+        let terms: Vec<_> = DovetailTermIterator::new(1..=6).collect();
+        let leaves: Vec<_> = terms[..12].iter().map(|term| term.leaves()).collect();
+        assert_eq!(leaves, [1, 2, 3, 3, 4, 4, 5, 4, 5, 6, 4, 5]);
+        assert_eq!(
+            terms.iter().collect::<HashSet<_>>(),
+            TermIterator::new(1..=6)
+                .collect::<Vec<_>>()
+                .iter()
+                .collect()
+        );
+        assert_eq!(terms.len(), 1 + 1 + 2 + 5 + 14 + 42);
+    }
+
+    #[test]
+    fn stats() {
+        let mut iter = TermIterator::new(3..=7).with_stats();
+        iter.by_ref().for_each(drop);
+        let stats = iter.stats().unwrap();
+        for leaves in 3..=7 {
+            assert_eq!(stats.sizes[leaves].terms as u128, Term::shape_count(leaves));
+        }
+        assert_eq!(stats.sizes[3].allocations, 1);
+
+        let mut pooled = TermIterator::with_pool(3..=7, 5).with_stats();
+        pooled.by_ref().for_each(drop);
+        let pooled = pooled.stats().unwrap();
+        assert_eq!(pooled.sizes[5].allocations, 0);
+        assert!(pooled.sizes[7].allocations < stats.sizes[7].allocations);
     }
 }
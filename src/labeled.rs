@@ -2,12 +2,14 @@ use std::{collections::HashMap, fmt::Display, hash::Hash, iter::Peekable, rc::Rc
 
 use crate::{
     bidag::{BinaryChildren, FromChildren},
+    codec::{ByteReader, ByteWriter, DecodeError, LabelCodec},
     maps::{NodeIndex, TermMap},
-    term::TermRef,
+    term::{Term, TermRef},
 };
 
 pub type LabeledTermRef<T> = Rc<LabeledTerm<T>>;
 
+#[derive(PartialEq, Eq)]
 pub enum LabeledTerm<T> {
     Variable(T),
     Operation(Rc<LabeledTerm<T>>, Rc<LabeledTerm<T>>),
@@ -24,35 +26,97 @@ impl<T> LabeledTerm<T> {
     pub fn skeleton(&self) -> TermRef {
         self.map(&mut |_| ())
     }
+
+    /// The labels of the leaves from left to right.
+    pub fn leaf_labels(&self) -> Vec<&T> {
+        let mut labels = Vec::new();
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            match node {
+                Self::Variable(label) => labels.push(label),
+                Self::Operation(left, right) => stack.extend([right.as_ref(), left.as_ref()]),
+            }
+        }
+        labels
+    }
+}
+
+/// Why `LabeledTerm::parse` rejected its input.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// A term was expected, but the input had this character or ended.
+    ExpectedTerm(Option<char>),
+    /// A parenthesis was not closed, but the input had this character or ended.
+    Unclosed(Option<char>),
+    /// The term was complete, but the input went on with this character.
+    Trailing(char),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let found = |c: &Option<char>| match c {
+            Some(c) => format!("'{}'", c),
+            None => "the end".to_string(),
+        };
+        match self {
+            Self::ExpectedTerm(c) => write!(f, "expected a variable or '(', found {}", found(c)),
+            Self::Unclosed(c) => write!(f, "expected ')', found {}", found(c)),
+            Self::Trailing(c) => write!(f, "unexpected '{}' after the term", c),
+        }
+    }
 }
 
 impl LabeledTerm<String> {
-    pub fn parse(input: &str) -> Rc<Self> {
-        Self::parse_inner(&mut input.replace(" ", "").chars().peekable())
+    /// Parses infix syntax with single letters as variables, where `*` groups to the right.
+    pub fn parse(input: &str) -> Result<Rc<Self>, ParseError> {
+        let input = input.replace(" ", "");
+        let mut input = input.chars().peekable();
+        let term = Self::parse_inner(&mut input)?;
+        match input.next() {
+            Some(c) => Err(ParseError::Trailing(c)),
+            None => Ok(term),
+        }
     }
 
-    fn parse_inner(input: &mut Peekable<Chars>) -> Rc<Self> {
+    fn parse_inner(input: &mut Peekable<Chars>) -> Result<Rc<Self>, ParseError> {
         let left = match input.next() {
             Some('(') => {
-                let child = Self::parse_inner(input);
-                assert_eq!(input.next(), Some(')'));
-                child
+                let child = Self::parse_inner(input)?;
+                match input.next() {
+                    Some(')') => child,
+                    other => return Err(ParseError::Unclosed(other)),
+                }
             }
             Some(x @ ('a'..='z' | 'A'..='Z')) => Rc::new(Self::Variable(x.to_string())),
-            _ => panic!(),
+            other => return Err(ParseError::ExpectedTerm(other)),
         };
 
         match input.peek() {
             Some('*') => {
                 input.next();
-                let right = Self::parse_inner(input);
-                Rc::new(Self::Operation(left, right))
+                let right = Self::parse_inner(input)?;
+                Ok(Rc::new(Self::Operation(left, right)))
             }
-            _ => left,
+            _ => Ok(left),
         }
     }
 }
 
+impl<T: Hash + Eq> LabeledTerm<T> {
+    /// Numbers the labels in the order of their first occurrence, giving the number of every
+    /// leaf from left to right.
+    pub fn variable_indices(&self) -> Vec<usize> {
+        let mut numbers = HashMap::new();
+        self.leaf_labels()
+            .into_iter()
+            .map(|label| {
+                let next = numbers.len();
+                *numbers.entry(label).or_insert(next)
+            })
+            .collect()
+    }
+}
+
 impl<T: Clone + Hash + PartialEq + Eq> LabeledTerm<T> {
     pub fn map_to(self: LabeledTermRef<T>, target: LabeledTermRef<T>) -> TermMap<'static> {
         let mut target_labels = HashMap::new();
@@ -72,6 +136,79 @@ impl<T: Clone + Hash + PartialEq + Eq> LabeledTerm<T> {
     }
 }
 
+impl<T: Hash + Eq> LabeledTerm<T> {
+    /// Whether the terms are equal if the operation is associative, i.e. whether the leaves carry
+    /// the same labels in the same order. With `commutative`, the order does not matter either.
+    pub fn eq_modulo_assoc(&self, other: &LabeledTerm<T>, commutative: bool) -> bool {
+        let (labels, other_labels) = (self.leaf_labels(), other.leaf_labels());
+
+        if !commutative || labels.len() != other_labels.len() {
+            return labels == other_labels;
+        }
+
+        let mut counts = HashMap::new();
+        labels
+            .into_iter()
+            .for_each(|label| *counts.entry(label).or_insert(0) += 1);
+        for label in other_labels {
+            match counts.get_mut(label) {
+                Some(count) if *count > 0 => *count -= 1,
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+impl<T: LabelCodec + Clone + Hash + Eq> LabeledTerm<T> {
+    /// The shape as in `Term::encode`, followed by a table of the distinct labels and the index
+    /// into it for every leaf.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = ByteWriter::new();
+        self.skeleton().encode_into(&mut writer);
+
+        let mut table = HashMap::new();
+        let mut labels = Vec::new();
+        let mut indices = Vec::new();
+        self.walk_leaves(&mut |leaf| {
+            let label = leaf.label().unwrap();
+            let index = *table.entry(label.clone()).or_insert_with(|| {
+                labels.push(label.clone());
+                labels.len() as u32 - 1
+            });
+            indices.push(index);
+        });
+
+        writer.u32(labels.len() as u32);
+        labels
+            .iter()
+            .for_each(|label| label.encode_label(&mut writer));
+        indices.iter().for_each(|index| writer.u32(*index));
+        writer.into_bytes()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<LabeledTermRef<T>, DecodeError> {
+        let mut reader = ByteReader::new(bytes);
+        let shape = Term::decode_from(&mut reader)?;
+
+        let labels = (0..reader.u32()?)
+            .map(|_| T::decode_label(&mut reader))
+            .collect::<Result<Vec<_>, _>>()?;
+        let leaves = (0..shape.leaves())
+            .map(|_| {
+                let index = reader.u32()?;
+                labels
+                    .get(index as usize)
+                    .cloned()
+                    .ok_or(DecodeError::InvalidIndex(index))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        reader.finish()?;
+        Ok(shape.label(leaves.into_iter()).unwrap())
+    }
+}
+
 impl<T> BinaryChildren for LabeledTerm<T> {
     fn children(&self) -> Option<(&Self, &Self)> {
         match self {
@@ -88,6 +225,10 @@ impl<T> BinaryChildren for Rc<LabeledTerm<T>> {
             LabeledTerm::Operation(left, right) => Some((left, right)),
         }
     }
+
+    fn address(&self) -> *const () {
+        Rc::as_ptr(self) as *const ()
+    }
 }
 
 impl<T> FromChildren<T> for Rc<LabeledTerm<T>> {
@@ -111,3 +252,50 @@ impl<T: Display> Display for LabeledTerm<T> {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lterm;
+
+    #[test]
+    fn parse() {
+        let parsed = LabeledTerm::parse("(a * b) * c * (d*e)").unwrap();
+        assert!(parsed == lterm!((a * b) * (c * (d * e))));
+
+        assert_eq!(
+            LabeledTerm::parse("(a*b").err(),
+            Some(ParseError::Unclosed(None))
+        );
+        assert_eq!(
+            LabeledTerm::parse("a*").err(),
+            Some(ParseError::ExpectedTerm(None))
+        );
+        assert_eq!(
+            LabeledTerm::parse("a+b").err(),
+            Some(ParseError::Trailing('+'))
+        );
+        assert_eq!(
+            LabeledTerm::parse("a*1").err(),
+            Some(ParseError::ExpectedTerm(Some('1')))
+        );
+    }
+
+    #[test]
+    fn eq_modulo_assoc() {
+        let a = lterm!((a * b) * (c * a));
+        let b = lterm!(a * ((b * c) * a));
+        let c = lterm!(a * (a * (c * b)));
+        assert!(a.eq_modulo_assoc(&b, false));
+        assert!(!a.eq_modulo_assoc(&c, false));
+        assert!(a.eq_modulo_assoc(&c, true));
+        assert!(!a.eq_modulo_assoc(&lterm!(a * (b * (c * c))), true));
+
+        let a = lterm!((a * b) * (c * d));
+        let map = a.clone().map_to(lterm!(a * ((b * c) * d)));
+        assert!(map.trivial_modulo_assoc(false));
+        let swap = a.map_to(lterm!(a * ((c * b) * d)));
+        assert!(!swap.trivial_modulo_assoc(false) && swap.trivial_modulo_assoc(true));
+        assert!(map.source().eq_modulo_assoc(map.target()));
+    }
+}
@@ -0,0 +1,33 @@
+/// Builds a `LabeledTermRef<String>` from infix syntax, with the same precedence as
+/// `LabeledTerm::parse`, e.g. `lterm!((a * b) * c)`.
+#[macro_export]
+macro_rules! lterm {
+    ($leaf:ident) => {
+        std::rc::Rc::new($crate::labeled::LabeledTerm::Variable(
+            stringify!($leaf).to_string(),
+        ))
+    };
+    (($($inner:tt)+)) => {
+        $crate::lterm!($($inner)+)
+    };
+    ($left:tt * $($right:tt)+) => {
+        std::rc::Rc::new($crate::labeled::LabeledTerm::Operation(
+            $crate::lterm!($left),
+            $crate::lterm!($($right)+),
+        ))
+    };
+}
+
+/// Builds a `TermRef` from infix syntax, e.g. `term!((a * b) * c)`. The leaf names are ignored.
+#[macro_export]
+macro_rules! term {
+    ($leaf:ident) => {
+        std::rc::Rc::new($crate::term::Term::Variable)
+    };
+    (($($inner:tt)+)) => {
+        $crate::term!($($inner)+)
+    };
+    ($left:tt * $($right:tt)+) => {
+        $crate::term::Term::operation($crate::term!($left), $crate::term!($($right)+))
+    };
+}
@@ -1,28 +1,90 @@
 #![feature(stmt_expr_attributes)]
 
+mod acmatch;
+#[allow(dead_code)]
+mod arena;
 mod bidag;
+#[allow(dead_code)]
+mod builder;
 mod byaddr;
+mod codec;
+mod commutative;
+mod completion;
+mod containing;
+#[allow(dead_code)]
+mod dag;
+mod diff;
+mod egraph;
+mod elide;
 mod eqclass;
+mod export;
+mod fingerprint;
+#[allow(dead_code)]
+mod gray;
 mod indexing;
 mod iter;
 mod labeled;
+mod macros;
 mod maps;
+#[allow(dead_code)]
+mod memo;
+#[allow(dead_code)]
+mod narrowing;
+#[allow(dead_code)]
+mod nary;
+mod ordering;
+mod patindex;
 mod perm;
+mod pipeline;
+mod position;
+mod proof;
+mod prove;
+mod random;
+mod rank;
+mod relation;
+mod rewrite;
+mod substtree;
+mod sync;
 mod term;
+#[allow(dead_code)]
+mod termindex;
+mod unify;
+#[allow(dead_code)]
+mod zipper;
 
-use std::rc::Rc;
+use std::{collections::BTreeMap, fmt::Display, rc::Rc};
 
-use clap::Parser;
+use clap::{CommandFactory, Parser, error::ErrorKind};
 
 use crate::{
-    byaddr::TermByAddress, eqclass::EquivalenceClasses, indexing::IndexedTerm, iter::TermIterator,
+    commutative::CommutativeTermIterator,
+    completion::{CompletionLimits, Depth, OrientationHeuristic, complete_with, ground_complete},
+    containing::{ContainingTermIterator, MatchingTermIterator},
+    egraph::EGraph,
+    eqclass::EquivalenceClasses,
+    indexing::{MatcherStats, PatternAutomaton},
+    iter::{DovetailTermIterator, TermIterator},
     labeled::LabeledTerm,
+    maps::TermMap,
+    ordering::{Kbo, Lpo, Status, TermOrdering},
+    pipeline::MatchPipeline,
+    prove::{axioms, prove},
+    random::RandomTermIterator,
+    relation::TermRelation,
+    rewrite::RewriteSystem,
 };
 
+const PARALLEL_BATCH_SIZE: usize = 4096;
+
 #[derive(Parser)]
 struct Args {
-    #[arg(short, long, help = "equivalence")]
-    equivalence: String,
+    #[arg(
+        short,
+        long,
+        required = true,
+        help = "equivalence, can be given multiple times"
+    )]
+    equivalence: Vec<String>,
 
     #[arg(
         short,
@@ -30,33 +92,458 @@ struct Args {
         help = "maximum number of leaves of expressions that are tried"
     )]
     leaves: usize,
+
+    #[arg(
+        long,
+        conflicts_with = "ground",
+        help = "minimum number of leaves of expressions that are tried, the maximum by default"
+    )]
+    min_leaves: Option<usize>,
+
+    #[arg(
+        short,
+        long,
+        help = "match the terms against the equivalences in parallel"
+    )]
+    parallel: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["parallel", "sample", "containing", "commutative", "dovetail"],
+        help = "print how much work matching each equivalence and enumerating the expressions \
+                took"
+    )]
+    stats: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["parallel", "stats"],
+        help = "saturate an e-graph of the terms instead of matching every term"
+    )]
+    egraph: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["parallel", "stats", "egraph"],
+        help = "print rules that rewrite every term to the least term of its class"
+    )]
+    ground: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["parallel", "stats", "egraph", "ground", "prove", "commutative"],
+        help = "print a confluent rewrite system for the equivalences, found by Knuth-Bendix \
+                completion, and the equivalences it keeps unoriented"
+    )]
+    complete: bool,
+
+    #[arg(
+        long,
+        requires = "complete",
+        help = "orient equivalences the ordering can't by rewriting the deeper side to the \
+                shallower one, which need not terminate"
+    )]
+    orient_by_depth: bool,
+
+    #[arg(
+        long,
+        requires = "ground",
+        value_parser = ["trs", "maude"],
+        help = "print the ground rules in the .trs or the Maude format instead"
+    )]
+    export: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "kbo",
+        value_parser = ["kbo", "lpo"],
+        help = "the Knuth-Bendix or the lexicographic path ordering, which orients the rules of \
+                --ground and --demodulate"
+    )]
+    ordering: String,
+
+    #[arg(
+        long,
+        help = "compare the children of operations from right to left in the ordering"
+    )]
+    right_to_left: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["egraph", "ground"],
+        help = "rewrite both sides of derived equivalences with the orientable ones first"
+    )]
+    demodulate: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["parallel", "egraph", "ground"],
+        help = "skip terms already in a class, which is faster but can miss merges of classes \
+                through the skipped terms, so there may be more classes than without it"
+    )]
+    dedup: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["min_leaves", "egraph", "ground"],
+        help = "try this many uniformly random expressions instead of all of them"
+    )]
+    sample: Option<usize>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["sample", "egraph", "ground"],
+        help = "only try expressions that contain the left side of an equivalence"
+    )]
+    containing: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["sample", "containing", "demodulate", "egraph", "ground"],
+        help = "only try one expression per class of those equal modulo commutativity, which \
+                has to be one of the equivalences"
+    )]
+    commutative: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["sample", "containing", "commutative", "egraph", "ground"],
+        help = "take turns between the numbers of leaves instead of trying them in order"
+    )]
+    dovetail: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "parallel", "stats", "egraph", "ground", "sample", "containing", "commutative",
+            "dovetail"
+        ],
+        help = "match the expressions while they are built from their subexpressions instead \
+                of one by one, which is usually slower"
+    )]
+    fused: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["egraph", "ground"],
+        help = "print the expressions in classes that are instances of this term, where leaves \
+                of the same variable match equal subexpressions"
+    )]
+    instances: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["parallel", "stats", "egraph", "ground", "commutative"],
+        help = "print a shortest derivation of this equivalence from the others instead"
+    )]
+    prove: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = 8,
+        requires = "prove",
+        help = "maximum number of steps of the derivation"
+    )]
+    max_steps: usize,
+
+    #[arg(long, default_value_t = 0, help = "seed of the random expressions")]
+    seed: u64,
 }
 
-fn main() {
-    let args = Args::parse();
-    let (left, right) = args.equivalence.split_once("=").unwrap();
-    let (left_tree, right_tree) = (
+fn invalid(equivalence: &str, error: &dyn Display) -> ! {
+    Args::command()
+        .error(
+            ErrorKind::ValueValidation,
+            format!("invalid equivalence {}: {}", equivalence, error),
+        )
+        .exit()
+}
+
+fn parse_equivalence(equivalence: &str) -> TermMap<'static> {
+    let Some((left, right)) = equivalence.split_once("=") else {
+        invalid(equivalence, &"expected two terms separated by '='");
+    };
+    let (left_tree, right_tree) = match (
         LabeledTerm::<String>::parse(left),
         LabeledTerm::<String>::parse(right),
-    );
+    ) {
+        (Ok(left), Ok(right)) => (left, right),
+        (Err(error), _) | (_, Err(error)) => invalid(equivalence, &error),
+    };
+
+    // rules like a*a=a or a*b=a are relations, but classes of shapes need bijections
+    let Some(equiv) = TermRelation::new(&left_tree, &right_tree).to_map() else {
+        Args::command()
+            .error(
+                ErrorKind::ValueValidation,
+                format!(
+                    "equivalence {} has to contain every variable once on each side",
+                    equivalence
+                ),
+            )
+            .exit();
+    };
+    if let Err(error) = equiv.validate() {
+        invalid(equivalence, &error);
+    }
+    equiv
+}
+
+fn main() {
+    let args = Args::parse();
+    if args.leaves == 0 {
+        Args::command()
+            .error(
+                ErrorKind::ValueValidation,
+                "--leaves has to be positive, since every expression has a leaf",
+            )
+            .exit();
+    }
+    if let Some(min_leaves) = args.min_leaves
+        && !(1..=args.leaves).contains(&min_leaves)
+    {
+        Args::command()
+            .error(
+                ErrorKind::ValueValidation,
+                format!("--min-leaves has to be between 1 and {}", args.leaves),
+            )
+            .exit();
+    }
+
+    let instances = args.instances.as_ref().map(|pattern| {
+        LabeledTerm::<String>::parse(pattern).unwrap_or_else(|error| {
+            Args::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    format!("invalid pattern {}: {}", pattern, error),
+                )
+                .exit()
+        })
+    });
+
+    let goal = args.prove.as_deref().map(parse_equivalence);
+
+    let mut equivs = Vec::new();
+    let mut sources = Vec::new();
+    for equivalence in &args.equivalence {
+        let equiv = parse_equivalence(equivalence);
+        // exported rules are read by other tools
+        if args.export.is_none() {
+            println!("equiv: {:?}", equiv);
+        }
+
+        sources.push(Rc::new(equiv.source().as_ref().clone()));
+        equivs.push(equiv);
+    }
+
+    if args.commutative {
+        let commutes = equivs
+            .iter()
+            .any(|equiv| equiv.source().leaves() == 2 && !equiv.perm().is_identity());
+        if !commutes {
+            Args::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    "--commutative needs the equivalence a*b=b*a",
+                )
+                .exit();
+        }
+        // only canonical terms are tried, so the sources are matched with their children
+        // swapped in every way
+        equivs = equivs
+            .iter()
+            .flat_map(TermMap::commutative_variants)
+            .collect();
+        sources = equivs
+            .iter()
+            .map(|equiv| Rc::new(equiv.source().as_ref().clone()))
+            .collect();
+    }
+    // the derived equivalences lead to canonical terms as well then
+    let canonical = |equiv: TermMap<'static>| {
+        if args.commutative {
+            let canonicalize = equiv.target().canonicalize_commutative();
+            &equiv * canonicalize
+        } else {
+            equiv
+        }
+    };
+
+    let leaves = args.min_leaves.unwrap_or(args.leaves)..=args.leaves;
+    let status = if args.right_to_left {
+        Status::RightToLeft
+    } else {
+        Status::LeftToRight
+    };
+    let kbo = Kbo::new(1, 1, status);
+    let lpo = Lpo { status };
+
+    if let Some(goal) = &goal {
+        let Some(proof) = prove(&equivs, goal, args.max_steps) else {
+            eprintln!("no derivation within {} steps", args.max_steps);
+            std::process::exit(1)
+        };
+        let system = axioms(&equivs);
+        for step in &proof.steps {
+            println!("step: {} at {}", system.rule(step.rule), step.position);
+        }
+        let proved = proof.replay(&system).expect("derivations replay");
+        println!("proved: {:?}", proved);
+        return;
+    }
+
+    if args.complete {
+        let heuristics: &[&dyn OrientationHeuristic] =
+            if args.orient_by_depth { &[&Depth] } else { &[] };
+        let limits = CompletionLimits::default();
+        let completion = match args.ordering.as_str() {
+            "lpo" => complete_with(equivs.clone(), &lpo, heuristics, &limits),
+            _ => complete_with(equivs.clone(), &kbo, heuristics, &limits),
+        };
+        let completion = completion.unwrap_or_else(|error| {
+            eprintln!("completion failed: {}", error);
+            std::process::exit(1)
+        });
+        for rule in completion.system.rules() {
+            println!("rule: {}", rule);
+        }
+        for equation in &completion.equations {
+            println!("equation: {}", equation);
+        }
+        return;
+    }
 
-    let equiv = left_tree.map_to(right_tree);
+    if args.ground {
+        let max_steps = CompletionLimits::default().max_steps;
+        let system = match args.ordering.as_str() {
+            "lpo" => ground_complete(&equivs, args.leaves, &lpo, max_steps),
+            _ => ground_complete(&equivs, args.leaves, &kbo, max_steps),
+        };
+        let system = system.unwrap_or_else(|error| {
+            eprintln!("ground completion failed: {}", error);
+            std::process::exit(1)
+        });
+        match args.export.as_deref() {
+            Some("trs") => print!("{}", system.to_trs()),
+            Some(_) => print!("{}", system.to_maude("GROUND")),
+            None => {
+                for rule in system.rules() {
+                    println!("rule: {}", rule);
+                }
+            }
+        }
+        return;
+    }
 
-    println!("equiv: {:?}", equiv);
+    if args.egraph {
+        let terms: Vec<_> = TermIterator::new(leaves.clone()).collect();
+        let mut egraph = EGraph::new();
+        for term in &terms {
+            egraph.add(term);
+        }
+        let rounds = egraph.saturate(&equivs);
+        println!(
+            "saturated after {} rounds with {} e-classes",
+            rounds,
+            egraph.len()
+        );
 
-    let pattern = IndexedTerm::from(Rc::new(equiv.source().as_ref().clone()));
+        let mut classes: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for term in &terms {
+            classes
+                .entry(egraph.lookup(term).unwrap())
+                .or_default()
+                .push(term);
+        }
+        for class in classes.values() {
+            let terms: Vec<_> = class.iter().map(|term| term.to_string()).collect();
+            println!("class: {}", terms.join(", "));
+        }
+        return;
+    }
 
+    let mut enumeration = TermIterator::new(leaves.clone());
+    if args.stats {
+        enumeration = enumeration.with_stats();
+    }
+    let terms: Box<dyn Iterator<Item = _>> = match args.sample {
+        Some(count) => Box::new(RandomTermIterator::new(args.leaves, args.seed).take(count)),
+        None if args.commutative => Box::new(CommutativeTermIterator::new(leaves.clone())),
+        None if args.dovetail => Box::new(DovetailTermIterator::new(leaves.clone())),
+        None if args.containing => Box::new(ContainingTermIterator::new(leaves.clone(), &sources)),
+        None => Box::new(enumeration.by_ref()),
+    };
+    let patterns = PatternAutomaton::new(sources.clone());
     let mut eqclasses = EquivalenceClasses::new();
+    let ordering: &dyn TermOrdering = match args.ordering.as_str() {
+        "lpo" => &lpo,
+        _ => &kbo,
+    };
+    let demodulator = args.demodulate.then(|| {
+        RewriteSystem::new(
+            equivs
+                .iter()
+                .filter_map(|equiv| ordering.orient(equiv.clone()))
+                .collect(),
+        )
+    });
 
-    for term in TermIterator::new(args.leaves) {
-        println!("Considering term: {}", term);
-        let matches = pattern.matches(&term);
-        for matched in matches {
-            let result_equiv = term.substitute(TermByAddress::from(matched.as_ref()), &equiv);
-            println!(" - equivalence: {:?}", result_equiv);
-            eqclasses.add_equiv(result_equiv);
+    let mut stats = MatcherStats::new();
+    if args.parallel {
+        MatchPipeline::new(&equivs, PARALLEL_BATCH_SIZE).run(terms, |term, result_equivs| {
+            println!("Considering term: {}", term);
+            for result_equiv in result_equivs.into_iter().map(canonical) {
+                println!(" - equivalence: {:?}", result_equiv);
+                match &demodulator {
+                    Some(system) => {
+                        eqclasses.add_demodulated(result_equiv, system);
+                    }
+                    None => eqclasses.add_equiv(result_equiv),
+                }
+            }
+        });
+    } else {
+        let terms: Box<dyn Iterator<Item = _>> = if args.fused {
+            // this replaces the plain enumeration
+            drop(terms);
+            let matched = MatchingTermIterator::new(leaves.clone(), &sources);
+            Box::new(matched.map(|(term, matches)| (term, Some(matches))))
+        } else {
+            Box::new(terms.map(|term| (term, None)))
+        };
+        for (term, matches) in terms {
+            if args.dedup && eqclasses.contains(&term) {
+                continue;
+            }
+            println!("Considering term: {}", term);
+            let matches = match matches {
+                Some(matches) => matches,
+                None if args.stats => patterns.matches_with_stats(&term, &mut stats),
+                None => patterns.matches(&term),
+            };
+            for (id, matched) in matches {
+                let result_equiv = canonical(term.substitute_match(&matched, &equivs[id]));
+                println!(" - equivalence: {:?}", result_equiv);
+                match &demodulator {
+                    Some(system) => {
+                        eqclasses.add_demodulated(result_equiv, system);
+                    }
+                    None => eqclasses.add_equiv(result_equiv),
+                }
+            }
         }
     }
 
     println!("{:#?}", eqclasses);
+    if let Some(pattern) = &instances {
+        for term in eqclasses.instances_of(pattern) {
+            println!("instance: {}", term);
+        }
+    }
+    if args.stats {
+        print!("{}", stats);
+        if let Some(stats) = enumeration.stats() {
+            print!("{}", stats);
+        }
+    }
 }
@@ -5,21 +5,87 @@ use std::{
 };
 
 use crate::{
+    labeled::LabeledTermRef,
     perm::perms::{PermIndex, Permutation},
-    term::{Term, TermRef},
+    term::{TermPointer, TermRef},
 };
 
 pub type NodeIndex = PermIndex;
 
+/// Why a `TermMap` is inconsistent, see `TermMap::validate`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum MapError {
+    /// The permutation has an entry for `perm` leaves, but the source has `leaves`.
+    PermLength { perm: usize, leaves: NodeIndex },
+    /// Leaf `leaf` of the source goes to `image`, which is not a leaf of the target.
+    OutOfRange { leaf: NodeIndex, image: NodeIndex },
+    /// Leaves `first` and `second` of the source both go to `image`.
+    NotInjective {
+        first: NodeIndex,
+        second: NodeIndex,
+        image: NodeIndex,
+    },
+    /// The target has more leaves than the source, so some are not reached.
+    NotSurjective {
+        source: NodeIndex,
+        target: NodeIndex,
+    },
+}
+
+impl Display for MapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::PermLength { perm, leaves } => write!(
+                f,
+                "permutation of length {} for a source with {} leaves",
+                perm, leaves
+            ),
+            Self::OutOfRange { leaf, image } => write!(
+                f,
+                "leaf {} goes to {}, which is not a leaf of the target",
+                leaf, image
+            ),
+            Self::NotInjective {
+                first,
+                second,
+                image,
+            } => write!(f, "leaves {} and {} both go to {}", first, second, image),
+            Self::NotSurjective { source, target } => write!(
+                f,
+                "{} leaves cannot reach all {} leaves of the target",
+                source, target
+            ),
+        }
+    }
+}
+
+/// Two maps that `TermMap::compose` cannot compose, as the first ends in `target` and the
+/// second starts from `source`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ComposeError<P> {
+    pub target: P,
+    pub source: P,
+}
+
+impl<P: Display> Display for ComposeError<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "cannot compose a map to {} with one from {}",
+            self.target, self.source
+        )
+    }
+}
+
 #[derive(Clone)]
-pub struct TermMap<'a> {
-    source: TermRef,
-    target: TermRef,
+pub struct TermMap<'a, P: TermPointer = TermRef> {
+    source: P,
+    target: P,
     perm: Permutation<'a>,
 }
 
-impl<'a> TermMap<'a> {
-    pub fn new(source: TermRef, target: TermRef, perm: Permutation<'a>) -> Self {
+impl<'a, P: TermPointer> TermMap<'a, P> {
+    pub fn new(source: P, target: P, perm: Permutation<'a>) -> Self {
         TermMap {
             source,
             target,
@@ -27,15 +93,24 @@ impl<'a> TermMap<'a> {
         }
     }
 
-    pub fn source(&self) -> &TermRef {
+    pub fn identity(term: P) -> TermMap<'static, P> {
+        let leaf_count = term.leaf_count();
+        TermMap {
+            source: term.clone(),
+            target: term,
+            perm: (0..leaf_count).collect::<Vec<_>>().into(),
+        }
+    }
+
+    pub fn source(&self) -> &P {
         &self.source
     }
 
-    pub fn target(&self) -> &TermRef {
+    pub fn target(&self) -> &P {
         &self.target
     }
 
-    pub fn backward(&self) -> TermMap<'static> {
+    pub fn backward(&self) -> TermMap<'static, P> {
         TermMap {
             perm: self.perm.inverse(),
             source: self.target.clone(),
@@ -51,26 +126,106 @@ impl<'a> TermMap<'a> {
         self.perm
     }
 
-    pub fn into_backward(self) -> TermMap<'static> {
+    pub fn into_backward(self) -> TermMap<'static, P> {
         TermMap {
             perm: self.perm.inverse(),
             source: self.target,
             target: self.source,
         }
     }
+
+    /// `self` followed by `rhs`, like `self * rhs`, but an error instead of a meaningless map if
+    /// `rhs` does not start where `self` ends.
+    pub fn compose(&self, rhs: &TermMap<'_, P>) -> Result<TermMap<'static, P>, ComposeError<P>> {
+        if self.target != rhs.source {
+            return Err(ComposeError {
+                target: self.target.clone(),
+                source: rhs.source.clone(),
+            });
+        }
+        Ok(self * rhs)
+    }
+
+    /// Checks that the permutation is a bijection from the leaves of the source to those of the
+    /// target.
+    pub fn validate(&self) -> Result<(), MapError> {
+        let (source, target) = (self.source.leaf_count(), self.target.leaf_count());
+        let perm = self.perm._storage();
+        if perm.len() != source as usize {
+            return Err(MapError::PermLength {
+                perm: perm.len(),
+                leaves: source,
+            });
+        }
+        let mut preimages = vec![None; target as usize];
+        for (leaf, &image) in perm.iter().enumerate() {
+            let leaf = leaf as NodeIndex;
+            let Some(preimage) = preimages.get_mut(image as usize) else {
+                return Err(MapError::OutOfRange { leaf, image });
+            };
+            if let Some(first) = *preimage {
+                return Err(MapError::NotInjective {
+                    first,
+                    second: leaf,
+                    image,
+                });
+            }
+            *preimage = Some(leaf);
+        }
+        if source != target {
+            return Err(MapError::NotSurjective { source, target });
+        }
+        Ok(())
+    }
+
+    /// Whether the equivalence already follows from associativity, i.e. it keeps every leaf in
+    /// place. With `commutative` as well, every equivalence between terms is trivial.
+    pub fn trivial_modulo_assoc(&self, commutative: bool) -> bool {
+        commutative || self.perm.is_identity()
+    }
+
+    /// Both sides with their leaves labeled by the leaf indices in the source.
+    pub fn labeled(&self) -> (LabeledTermRef<NodeIndex>, LabeledTermRef<NodeIndex>) {
+        let backward = self.perm.inverse();
+        let (mut source_count, mut target_count) = (0, 0);
+        let source = self.source.map(&mut |_| {
+            source_count += 1;
+            source_count - 1
+        });
+        let target = self.target.map(&mut |_| {
+            target_count += 1;
+            backward.get(target_count - 1)
+        });
+        (source, target)
+    }
+
+    fn labeled_target(&self) -> LabeledTermRef<String> {
+        let backward = self.perm.inverse();
+        let mut count = 0;
+        self.target.map(&mut |_| {
+            count += 1;
+            backward.get(count - 1).to_string()
+        })
+    }
 }
 
-impl<'a> Index<NodeIndex> for TermMap<'a> {
+impl<'a, P: TermPointer> Index<NodeIndex> for TermMap<'a, P> {
     type Output = NodeIndex;
     fn index(&self, index: NodeIndex) -> &Self::Output {
         &self.perm._storage()[index as usize]
     }
 }
 
-impl<'a, B: Borrow<TermMap<'a>>> Mul<B> for &TermMap<'_> {
-    type Output = TermMap<'static>;
+impl<'a, P: TermPointer, B: Borrow<TermMap<'a, P>>> Mul<B> for &TermMap<'_, P> {
+    type Output = TermMap<'static, P>;
     fn mul(self, rhs: B) -> Self::Output {
         let rhs_ref = rhs.borrow();
+        debug_assert!(
+            self.target == rhs_ref.source,
+            "cannot compose a map to {} with one from {}",
+            self.target,
+            rhs_ref.source
+        );
         TermMap {
             source: self.source.clone(),
             target: rhs_ref.target.clone(),
@@ -79,36 +234,113 @@ impl<'a, B: Borrow<TermMap<'a>>> Mul<B> for &TermMap<'_> {
     }
 }
 
-impl<'a, B: Borrow<TermMap<'a>>> MulAssign<B> for &mut TermMap<'_> {
+impl<'a, P: TermPointer, B: Borrow<TermMap<'a, P>>> MulAssign<B> for &mut TermMap<'_, P> {
     fn mul_assign(&mut self, rhs: B) {
-        self.target = rhs.borrow().target().clone();
-        self.perm *= &rhs.borrow().perm;
+        let rhs = rhs.borrow();
+        debug_assert!(
+            self.target == rhs.source,
+            "cannot compose a map to {} with one from {}",
+            self.target,
+            rhs.source
+        );
+        self.target = rhs.target().clone();
+        self.perm *= &rhs.perm;
     }
 }
 
-impl<'a, B: Borrow<TermMap<'a>>> MulAssign<B> for TermMap<'_> {
+impl<'a, P: TermPointer, B: Borrow<TermMap<'a, P>>> MulAssign<B> for TermMap<'_, P> {
     fn mul_assign(&mut self, rhs: B) {
-        self.target = rhs.borrow().target().clone();
-        self.perm *= &rhs.borrow().perm;
+        let rhs = rhs.borrow();
+        debug_assert!(
+            self.target == rhs.source,
+            "cannot compose a map to {} with one from {}",
+            self.target,
+            rhs.source
+        );
+        self.target = rhs.target().clone();
+        self.perm *= &rhs.perm;
     }
 }
 
-impl Debug for TermMap<'_> {
+impl<P: TermPointer> Debug for TermMap<'_, P> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let backward = self.perm.inverse();
-        let formatted_target = self
-            .target
-            .label_with(&mut |index| backward.get(index as PermIndex).to_string());
-        write!(f, "TreeMap[{} -> {}]", self.source, formatted_target)
+        write!(f, "TreeMap[{} -> {}]", self.source, self.labeled_target())
     }
 }
 
-impl Display for TermMap<'_> {
+impl<P: TermPointer> Display for TermMap<'_, P> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let backward = self.perm.inverse();
-        let formatted_target = self
-            .target
-            .label_with(&mut |index| backward.get(index as PermIndex).to_string());
-        write!(f, "{} -> {}", self.source, formatted_target)
+        write!(f, "{} -> {}", self.source, self.labeled_target())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lterm, term};
+
+    #[test]
+    fn compose() {
+        let rotate = lterm!((a * b) * c).map_to(lterm!(a * (b * c)));
+        let commute = lterm!(a * (b * c)).map_to(lterm!((b * c) * a));
+        let composed = rotate.compose(&commute).unwrap();
+        assert_eq!(composed.perm(), (&rotate * &commute).perm());
+        assert_eq!(
+            commute.compose(&commute).unwrap_err(),
+            ComposeError {
+                target: term!((a * b) * c),
+                source: term!(a * (b * c))
+            }
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "cannot compose")]
+    fn mul_mismatched() {
+        let rotate = lterm!((a * b) * c).map_to(lterm!(a * (b * c)));
+        let _ = &rotate * &rotate;
+    }
+
+    #[test]
+    fn validate() {
+        let map = lterm!((a * b) * c).map_to(lterm!(c * (a * b)));
+        assert_eq!(map.validate(), Ok(()));
+        assert_eq!(map.backward().validate(), Ok(()));
+
+        let (source, target) = (term!((a * b) * c), term!(a * (b * c)));
+        let map = |perm: Vec<NodeIndex>| TermMap::new(source.clone(), target.clone(), perm.into());
+        assert_eq!(
+            map(vec![1, 0]).validate(),
+            Err(MapError::PermLength { perm: 2, leaves: 3 })
+        );
+        assert_eq!(
+            map(vec![0, 3, 1]).validate(),
+            Err(MapError::OutOfRange { leaf: 1, image: 3 })
+        );
+        assert_eq!(
+            map(vec![2, 0, 2]).validate(),
+            Err(MapError::NotInjective {
+                first: 0,
+                second: 2,
+                image: 2
+            })
+        );
+        let wider = TermMap::new(
+            source.clone(),
+            term!(a * (b * (c * d))),
+            vec![0, 1, 2].into(),
+        );
+        assert_eq!(
+            wider.validate(),
+            Err(MapError::NotSurjective {
+                source: 3,
+                target: 4
+            })
+        );
+        assert_eq!(
+            map(vec![0, 2, 2]).validate().unwrap_err().to_string(),
+            "leaves 1 and 2 both go to 2"
+        );
     }
 }
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use crate::term::{TermPointer, TermRef};
+
+/// A bottom-up reduction that remembers its result for every physical subterm it has seen, also
+/// across calls. Terms from `TermIterator` share many of their subterms with earlier ones, so
+/// reducing all of them is cheaper this way. The cache keeps the subterms alive, so their
+/// addresses cannot be reused by other terms.
+pub struct MemoReduce<S, F, L, P: TermPointer = TermRef> {
+    reduction: F,
+    labeler: L,
+    cache: HashMap<*const (), (P, S)>,
+}
+
+impl<S: Clone, F: FnMut(&P, S, S) -> S, L: FnMut(&P) -> S, P: TermPointer> MemoReduce<S, F, L, P> {
+    pub fn new(reduction: F, labeler: L) -> Self {
+        MemoReduce {
+            reduction,
+            labeler,
+            cache: HashMap::new(),
+        }
+    }
+
+    pub fn reduce(&mut self, term: &P) -> S {
+        let mut results = Vec::new();
+        let mut stack = vec![(term, false)];
+
+        while let Some((node, expanded)) = stack.pop() {
+            if !expanded && let Some((_, result)) = self.cache.get(&node.address()) {
+                results.push(result.clone());
+                continue;
+            }
+            let result = match node.children() {
+                None => (self.labeler)(node),
+                Some((left, right)) => {
+                    if !expanded {
+                        stack.push((node, true));
+                        stack.push((right, false));
+                        stack.push((left, false));
+                        continue;
+                    }
+                    let result_right = results.pop().unwrap();
+                    let result_left = results.pop().unwrap();
+                    (self.reduction)(node, result_left, result_right)
+                }
+            };
+            self.cache
+                .insert(node.address(), (node.clone(), result.clone()));
+            results.push(result);
+        }
+
+        results.pop().unwrap()
+    }
+
+    /// Number of cached subterms.
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iter::TermIterator;
+
+    #[test]
+    fn memo_reduce() {
+        let mut reductions = 0;
+        let mut memo = MemoReduce::new(
+            |_: &TermRef, left: usize, right: usize| {
+                reductions += 1;
+                left.max(right) + 1
+            },
+            |_: &TermRef| 0,
+        );
+
+        let terms: Vec<_> = TermIterator::new(9).collect();
+        let operations: usize = terms.iter().map(|term| term.leaves() - 1).sum();
+        for term in &terms {
+            assert_eq!(memo.reduce(term), term.depth());
+        }
+        let cached = memo.len();
+        // the second pass is answered from the cache alone
+        for term in &terms {
+            assert_eq!(memo.reduce(term), term.depth());
+        }
+        assert_eq!(memo.len(), cached);
+        memo.clear();
+        assert!(memo.is_empty());
+        drop(memo);
+        assert!(reductions < operations);
+    }
+}
@@ -0,0 +1,85 @@
+use crate::{
+    bidag::BinaryChildren,
+    completion::merge,
+    maps::TermMap,
+    position::Position,
+    rewrite::{RewriteSystem, RuleId},
+    term::{TermPointer, TermRef},
+};
+
+/// One narrowing step from a goal: the goal is instantiated just enough for a rule to apply at
+/// `position`, and then rewritten there.
+#[derive(Clone, Debug)]
+pub struct Narrowing {
+    pub rule: RuleId,
+    pub position: Position,
+    /// The subterms the leaves of the goal are instantiated with, in order.
+    pub bindings: Vec<TermRef>,
+    /// From the instantiated goal to the result of rewriting it.
+    pub map: TermMap<'static>,
+}
+
+impl Narrowing {
+    pub fn instance(&self) -> &TermRef {
+        self.map.source()
+    }
+
+    pub fn result(&self) -> &TermRef {
+        self.map.target()
+    }
+}
+
+impl RewriteSystem {
+    /// All narrowing steps from `goal`, whose leaves are variables, ordered by position in
+    /// pre-order and then by rule. Unlike rewriting, rules apply to every operation of the goal
+    /// that unifies with their source, not only to instances of it.
+    pub fn narrow(&self, goal: &TermRef) -> Vec<Narrowing> {
+        let mut narrowings = Vec::new();
+        goal.walk_with_positions(&mut |position, node| {
+            if node.is_leaf() {
+                return;
+            }
+            for (id, rule) in self.rules().iter().enumerate() {
+                let instance = goal.graft(position, merge(node, rule.source()));
+                narrowings.push(Narrowing {
+                    rule: id,
+                    position: position.clone(),
+                    bindings: goal.instance_bindings(&instance).unwrap(),
+                    map: instance.substitute_at(position, rule),
+                });
+            }
+        });
+        narrowings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lterm, term};
+
+    #[test]
+    fn narrow() {
+        let system = RewriteSystem::new(vec![lterm!((a * b) * c).map_to(lterm!(a * (b * c)))]);
+
+        // x * y is no instance of the source, but becomes one for x = a * b
+        let narrowings = system.narrow(&term!(x * y));
+        assert_eq!(narrowings.len(), 1);
+        assert_eq!(narrowings[0].rule, 0);
+        assert_eq!(narrowings[0].bindings, [term!(a * b), term!(c)]);
+        assert_eq!(narrowings[0].result(), &term!(a * (b * c)));
+
+        let narrowings = system.narrow(&term!(x * (y * z)));
+        let positions: Vec<_> = narrowings
+            .iter()
+            .map(|narrowing| narrowing.position.to_string())
+            .collect();
+        assert_eq!(positions, ["ε", "R"]);
+        assert_eq!(narrowings[0].instance(), &term!((a * b) * (c * d)));
+        assert_eq!(narrowings[0].result(), &term!(a * (b * (c * d))));
+        assert_eq!(narrowings[1].result(), &term!(a * (b * (c * d))));
+        assert_eq!(narrowings[1].bindings, [term!(a), term!(b * c), term!(d)]);
+
+        assert!(system.narrow(&term!(x)).is_empty());
+    }
+}
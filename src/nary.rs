@@ -0,0 +1,268 @@
+use std::{
+    fmt::{Debug, Display},
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
+
+use crate::{
+    bidag::BinaryChildren,
+    term::{Term, TermRef},
+};
+
+/// Term whose operation nodes have any number of children. Chains of one associative operator
+/// become a single node.
+pub enum NaryTerm {
+    Variable,
+    Operation(Vec<NaryTermRef>),
+}
+
+pub type NaryTermRef = Rc<NaryTerm>;
+
+/// How a chain of operands is nested in a binary term.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Bracketing {
+    /// `(a * b) * c`
+    Left,
+    /// `a * (b * c)`
+    Right,
+}
+
+impl NaryTerm {
+    pub fn operation(children: Vec<NaryTermRef>) -> NaryTermRef {
+        assert!(children.len() >= 2, "operations need at least two children");
+        Rc::new(NaryTerm::Operation(children))
+    }
+
+    pub fn children(&self) -> &[NaryTermRef] {
+        match self {
+            NaryTerm::Variable => &[],
+            NaryTerm::Operation(children) => children,
+        }
+    }
+
+    pub fn leaves(&self) -> usize {
+        let mut leaves = 0;
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            match node {
+                NaryTerm::Variable => leaves += 1,
+                NaryTerm::Operation(children) => stack.extend(children.iter().map(Rc::as_ref)),
+            }
+        }
+        leaves
+    }
+
+    /// Collapses every chain nested according to `bracketing` into one node. The inverse of
+    /// `to_term` with the same bracketing.
+    pub fn from_term(term: &TermRef, bracketing: Bracketing) -> NaryTermRef {
+        term.reduce(
+            &mut |_, left, right| {
+                let children = match bracketing {
+                    Bracketing::Left => {
+                        let mut children = Self::operands(left);
+                        children.push(right);
+                        children
+                    }
+                    Bracketing::Right => {
+                        let mut children = vec![left];
+                        children.extend(Self::operands(right));
+                        children
+                    }
+                };
+                Self::operation(children)
+            },
+            &mut |_| Rc::new(NaryTerm::Variable),
+        )
+    }
+
+    /// Flattens the term completely, i.e. the result is a single operation over all leaves.
+    pub fn flatten(term: &TermRef) -> NaryTermRef {
+        term.reduce(
+            &mut |_, left, right| {
+                let mut children = Self::operands(left);
+                children.extend(Self::operands(right));
+                Self::operation(children)
+            },
+            &mut |_| Rc::new(NaryTerm::Variable),
+        )
+    }
+
+    // the children of an operation, or the term itself if it is a variable
+    fn operands(term: NaryTermRef) -> Vec<NaryTermRef> {
+        match Rc::try_unwrap(term) {
+            Ok(mut term) => match &mut term {
+                NaryTerm::Variable => vec![Rc::new(NaryTerm::Variable)],
+                NaryTerm::Operation(children) => std::mem::take(children),
+            },
+            Err(term) => match term.as_ref() {
+                NaryTerm::Variable => vec![term],
+                NaryTerm::Operation(children) => children.clone(),
+            },
+        }
+    }
+
+    /// Nests the children of every operation according to `bracketing`.
+    pub fn to_term(&self, bracketing: Bracketing) -> TermRef {
+        let mut results: Vec<TermRef> = Vec::new();
+        let mut stack = vec![(self, false)];
+
+        while let Some((node, expanded)) = stack.pop() {
+            match node {
+                NaryTerm::Variable => results.push(Rc::new(Term::Variable)),
+                NaryTerm::Operation(children) if !expanded => {
+                    stack.push((node, true));
+                    stack.extend(children.iter().rev().map(|child| (child.as_ref(), false)));
+                }
+                NaryTerm::Operation(children) => {
+                    let operands = results.split_off(results.len() - children.len());
+                    let result = match bracketing {
+                        Bracketing::Left => operands.into_iter().reduce(Term::operation),
+                        Bracketing::Right => operands
+                            .into_iter()
+                            .rev()
+                            .reduce(|right, left| Term::operation(left, right)),
+                    };
+                    results.push(result.unwrap());
+                }
+            }
+        }
+
+        results.pop().unwrap()
+    }
+}
+
+impl PartialEq for NaryTerm {
+    fn eq(&self, other: &Self) -> bool {
+        let mut stack = vec![(self, other)];
+
+        while let Some((a, b)) = stack.pop() {
+            match (a, b) {
+                (NaryTerm::Variable, NaryTerm::Variable) => {}
+                (NaryTerm::Operation(a), NaryTerm::Operation(b)) if a.len() == b.len() => {
+                    stack.extend(a.iter().zip(b).map(|(a, b)| (a.as_ref(), b.as_ref())));
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+impl Eq for NaryTerm {}
+
+impl Hash for NaryTerm {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            let children = node.children();
+            state.write_usize(children.len());
+            stack.extend(children.iter().rev().map(Rc::as_ref));
+        }
+    }
+}
+
+// see `Drop for Term`
+impl Drop for NaryTerm {
+    fn drop(&mut self) {
+        let NaryTerm::Operation(children) = self else {
+            return;
+        };
+
+        let mut stack = std::mem::take(children);
+        while let Some(node) = stack.pop() {
+            if let Ok(mut term) = Rc::try_unwrap(node)
+                && let NaryTerm::Operation(children) = &mut term
+            {
+                stack.append(children);
+            }
+        }
+    }
+}
+
+impl Display for NaryTerm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        enum Step<'n> {
+            // the flag tells whether an operation has to be parenthesized
+            Visit(&'n NaryTerm, bool),
+            Text(&'static str),
+        }
+
+        let mut leaf_count = 0;
+        let mut stack = vec![Step::Visit(self, false)];
+
+        while let Some(step) = stack.pop() {
+            match step {
+                Step::Text(text) => write!(f, "{}", text)?,
+                Step::Visit(NaryTerm::Variable, _) => {
+                    write!(f, "{}", leaf_count)?;
+                    leaf_count += 1;
+                }
+                Step::Visit(NaryTerm::Operation(children), parenthesize) => {
+                    if parenthesize {
+                        stack.push(Step::Text(")"));
+                    }
+                    for (i, child) in children.iter().enumerate().rev() {
+                        stack.push(Step::Visit(child, true));
+                        if i > 0 {
+                            stack.push(Step::Text(" * "));
+                        }
+                    }
+                    if parenthesize {
+                        stack.push(Step::Text("("));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Debug for NaryTerm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "NaryTerm[{}]", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{iter::TermIterator, term};
+
+    #[test]
+    fn bracketing_roundtrip() {
+        for term in TermIterator::new(6) {
+            for bracketing in [Bracketing::Left, Bracketing::Right] {
+                let nary = NaryTerm::from_term(&term, bracketing);
+                assert_eq!(nary.leaves(), 6);
+                assert!(nary.to_term(bracketing) == term);
+            }
+            let flat = NaryTerm::flatten(&term);
+            assert_eq!(flat.children().len(), 6);
+            assert_eq!(flat.to_string(), "0 * 1 * 2 * 3 * 4 * 5");
+        }
+
+        let term = term!((a * b) * (c * (d * e)));
+        assert_eq!(
+            NaryTerm::from_term(&term, Bracketing::Right).to_string(),
+            "(0 * 1) * 2 * 3 * 4"
+        );
+        assert_eq!(
+            NaryTerm::from_term(&term, Bracketing::Left).to_string(),
+            "0 * 1 * (2 * (3 * 4))"
+        );
+
+        // a left comb is a single node when read left bracketed, but a deep chain otherwise
+        let comb = (1..50_000).fold(Rc::new(Term::Variable), |left, _| {
+            Term::operation(left, Rc::new(Term::Variable))
+        });
+        assert_eq!(
+            NaryTerm::from_term(&comb, Bracketing::Left)
+                .children()
+                .len(),
+            50_000
+        );
+        let deep = NaryTerm::from_term(&comb, Bracketing::Right);
+        assert!(deep.to_term(Bracketing::Right) == comb);
+        assert!(*deep == *NaryTerm::from_term(&comb, Bracketing::Right));
+    }
+}
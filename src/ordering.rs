@@ -0,0 +1,248 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use crate::{
+    bidag::BinaryChildren,
+    labeled::{LabeledTerm, LabeledTermRef},
+    maps::{NodeIndex, TermMap},
+    term::TermRef,
+};
+
+/// Order in which the children of two operations are compared. There is a single operation, so
+/// this is all a precedence can say about it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Status {
+    #[default]
+    LeftToRight,
+    RightToLeft,
+}
+
+impl Status {
+    fn order<'t, T>(self, left: &'t T, right: &'t T) -> [&'t T; 2] {
+        match self {
+            Status::LeftToRight => [left, right],
+            Status::RightToLeft => [right, left],
+        }
+    }
+}
+
+/// Reduction orderings on terms whose leaves are variables, identified by their labels.
+pub trait TermOrdering {
+    fn greater(&self, s: &LabeledTermRef<NodeIndex>, t: &LabeledTermRef<NodeIndex>) -> bool;
+
+    /// `None` if the terms are different but incomparable.
+    fn compare(
+        &self,
+        s: &LabeledTermRef<NodeIndex>,
+        t: &LabeledTermRef<NodeIndex>,
+    ) -> Option<Ordering> {
+        if s == t {
+            Some(Ordering::Equal)
+        } else if self.greater(s, t) {
+            Some(Ordering::Greater)
+        } else if self.greater(t, s) {
+            Some(Ordering::Less)
+        } else {
+            None
+        }
+    }
+
+    /// The rule that rewrites the greater side of `equation` to the smaller one, `None` if the
+    /// sides are incomparable or the same.
+    fn orient(&self, equation: TermMap<'static>) -> Option<TermMap<'static>> {
+        let (s, t) = equation.labeled();
+        match self.compare(&s, &t)? {
+            Ordering::Greater => Some(equation),
+            Ordering::Less => Some(equation.into_backward()),
+            Ordering::Equal => None,
+        }
+    }
+
+    /// The least of `terms`, the first one if several have the same shape. With all leaves the
+    /// same variable, the ordering is total on shapes, so this is a canonical choice.
+    fn least<'t>(&self, terms: impl IntoIterator<Item = &'t TermRef>) -> Option<&'t TermRef>
+    where
+        Self: Sized,
+    {
+        let mut least: Option<(&TermRef, LabeledTermRef<NodeIndex>)> = None;
+        for term in terms {
+            let labeled = term.map(&mut |_| 0);
+            if least
+                .as_ref()
+                .is_none_or(|(_, current)| self.greater(current, &labeled))
+            {
+                least = Some((term, labeled));
+            }
+        }
+        least.map(|(term, _)| term)
+    }
+}
+
+// every variable occurs in `s` at least as often as in `t`
+fn variables_contained(s: &LabeledTermRef<NodeIndex>, t: &LabeledTermRef<NodeIndex>) -> bool {
+    let mut counts: HashMap<_, isize> = HashMap::new();
+    for label in s.leaf_labels() {
+        *counts.entry(label).or_insert(0) += 1;
+    }
+    t.leaf_labels().into_iter().all(|label| {
+        let count = counts.entry(label).or_insert(0);
+        *count -= 1;
+        *count >= 0
+    })
+}
+
+/// Knuth-Bendix ordering. Heavier terms are greater, equally heavy ones are compared by their
+/// children, and the greater term has to contain every variable at least as often.
+#[derive(Clone, Copy, Debug)]
+pub struct Kbo {
+    variable_weight: u64,
+    operation_weight: u64,
+    status: Status,
+}
+
+impl Default for Kbo {
+    fn default() -> Self {
+        Kbo::new(1, 1, Status::default())
+    }
+}
+
+impl Kbo {
+    /// Panics unless `variable_weight` is positive, otherwise a term would not be heavier than
+    /// its subterms and the ordering would not be well-founded.
+    pub fn new(variable_weight: u64, operation_weight: u64, status: Status) -> Self {
+        assert!(variable_weight > 0, "variables need a positive weight");
+        Kbo {
+            variable_weight,
+            operation_weight,
+            status,
+        }
+    }
+
+    fn weight(&self, term: &LabeledTermRef<NodeIndex>) -> u64 {
+        term.reduce(
+            &mut |_, left, right| left + right + self.operation_weight,
+            &mut |_| self.variable_weight,
+        )
+    }
+}
+
+impl TermOrdering for Kbo {
+    fn greater(&self, s: &LabeledTermRef<NodeIndex>, t: &LabeledTermRef<NodeIndex>) -> bool {
+        if !variables_contained(s, t) {
+            return false;
+        }
+        match self.weight(s).cmp(&self.weight(t)) {
+            Ordering::Greater => true,
+            Ordering::Less => false,
+            Ordering::Equal => match (s.as_ref(), t.as_ref()) {
+                (
+                    LabeledTerm::Operation(s_left, s_right),
+                    LabeledTerm::Operation(t_left, t_right),
+                ) => {
+                    let s_children = self.status.order(s_left, s_right);
+                    let t_children = self.status.order(t_left, t_right);
+                    match s_children.into_iter().zip(t_children).find(|(a, b)| a != b) {
+                        Some((a, b)) => self.greater(a, b),
+                        None => false,
+                    }
+                }
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Lexicographic path ordering. A term is greater than its subterms, and than the terms whose
+/// children it is greater than if its children are lexicographically greater.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Lpo {
+    pub status: Status,
+}
+
+impl TermOrdering for Lpo {
+    fn greater(&self, s: &LabeledTermRef<NodeIndex>, t: &LabeledTermRef<NodeIndex>) -> bool {
+        match (s.as_ref(), t.as_ref()) {
+            (_, LabeledTerm::Variable(x)) => s != t && s.leaf_labels().contains(&x),
+            (LabeledTerm::Variable(_), _) => false,
+            (LabeledTerm::Operation(s_left, s_right), LabeledTerm::Operation(t_left, t_right)) => {
+                if [s_left, s_right]
+                    .into_iter()
+                    .any(|child| child == t || self.greater(child, t))
+                {
+                    return true;
+                }
+                if !self.greater(s, t_left) || !self.greater(s, t_right) {
+                    return false;
+                }
+                let s_children = self.status.order(s_left, s_right);
+                let t_children = self.status.order(t_left, t_right);
+                match s_children.into_iter().zip(t_children).find(|(a, b)| a != b) {
+                    Some((a, b)) => self.greater(a, b),
+                    None => false,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{iter::TermIterator, lterm, term};
+
+    fn numbered(term: &LabeledTermRef<String>) -> LabeledTermRef<NodeIndex> {
+        term.map(&mut |leaf: &LabeledTermRef<String>| {
+            leaf.label().unwrap().as_bytes()[0] as NodeIndex
+        })
+    }
+
+    fn check(ordering: &impl TermOrdering, reversed: &impl TermOrdering) {
+        let associativity = lterm!((a * b) * c).map_to(lterm!(a * (b * c)));
+        let rule = ordering.orient(associativity.backward()).unwrap();
+        assert_eq!(rule.source(), associativity.source());
+        let rule = reversed.orient(associativity.clone()).unwrap();
+        assert_eq!(rule.source(), associativity.target());
+        assert!(
+            ordering
+                .orient(lterm!(a * b).map_to(lterm!(b * a)))
+                .is_none()
+        );
+
+        // a term is greater than its proper subterms, but a variable can't occur more often
+        let (s, t) = (numbered(&lterm!(a * (b * a))), numbered(&lterm!(b * a)));
+        assert_eq!(ordering.compare(&s, &t), Some(Ordering::Greater));
+        let (s, t) = (numbered(&lterm!(a * b)), numbered(&lterm!(a * a)));
+        assert_eq!(ordering.compare(&s, &t), None);
+
+        let terms: Vec<_> = TermIterator::new(5).collect();
+        let least = ordering.least(&terms).unwrap();
+        assert!(
+            terms
+                .iter()
+                .all(|term| ordering.least([least, term]) == Some(least))
+        );
+    }
+
+    #[test]
+    fn orderings() {
+        let status = Status::RightToLeft;
+        let reversed = Kbo::new(1, 1, status);
+        check(&Kbo::default(), &reversed);
+        check(&Lpo::default(), &Lpo { status });
+
+        let terms: Vec<_> = TermIterator::new(5).collect();
+        assert_eq!(
+            Kbo::default().least(&terms),
+            Some(&term!(a * (b * (c * (d * e)))))
+        );
+        assert_eq!(
+            reversed.least(&terms),
+            Some(&term!((((a * b) * c) * d) * e))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "variables need a positive weight")]
+    fn kbo_zero_variable_weight() {
+        Kbo::new(0, 1, Status::default());
+    }
+}
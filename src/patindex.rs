@@ -0,0 +1,363 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    bidag::BinaryChildren,
+    fingerprint::FingerprintIndex,
+    indexing::Match,
+    position::{Direction, Position},
+    term::{TermPointer, TermRef},
+};
+
+pub type PatternId = usize;
+
+// Trie over the pre-order sequences of the patterns, where every node is an operation or a
+// variable. No sequence is a prefix of another, so patterns only end in leaves of the trie.
+#[derive(Default)]
+struct TrieNode {
+    operation: Option<usize>,
+    variable: Option<usize>,
+    patterns: Vec<PatternId>,
+}
+
+/// Discrimination tree over the source terms of many equivalences. Finds all matches of all
+/// patterns in a term with one pass over the term. Nodes whose fingerprint no pattern can match
+/// are skipped without walking the tree.
+pub struct PatternIndex {
+    patterns: Vec<TermRef>,
+    trie: Vec<TrieNode>,
+    fingerprints: FingerprintIndex<PatternId>,
+}
+
+// a term flattened in pre-order, `end` is the index after the subterm of a node
+struct FlatNode<'t> {
+    node: &'t TermRef,
+    end: usize,
+    parent: Option<(usize, Direction)>,
+}
+
+impl Default for PatternIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PatternIndex {
+    pub fn new() -> Self {
+        PatternIndex {
+            patterns: Vec::new(),
+            trie: vec![TrieNode::default()],
+            fingerprints: FingerprintIndex::new(),
+        }
+    }
+
+    pub fn insert(&mut self, pattern: TermRef) -> PatternId {
+        let id = self.patterns.len();
+        let mut current = 0;
+        pattern.walk_preorder(&mut |node| {
+            let next = self.trie.len();
+            let edge = if node.is_leaf() {
+                &mut self.trie[current].variable
+            } else {
+                &mut self.trie[current].operation
+            };
+            current = *edge.get_or_insert(next);
+            if current == next {
+                self.trie.push(TrieNode::default());
+            }
+        });
+        self.trie[current].patterns.push(id);
+        self.fingerprints.insert(&pattern, id);
+        self.patterns.push(pattern);
+        id
+    }
+
+    fn flatten(term: &TermRef) -> Vec<FlatNode<'_>> {
+        let mut flat = Vec::with_capacity(term.node_count());
+        let mut stack = vec![(term, None)];
+        while let Some((node, parent)) = stack.pop() {
+            let index = flat.len();
+            flat.push(FlatNode {
+                node,
+                end: 0,
+                parent,
+            });
+            if let Some((left, right)) = node.children() {
+                stack.push((right, Some((index, Direction::Right))));
+                stack.push((left, Some((index, Direction::Left))));
+            }
+        }
+
+        // children come after their parent, so going backwards their ends are known
+        for index in (0..flat.len()).rev() {
+            flat[index].end = if flat[index].node.is_leaf() {
+                index + 1
+            } else {
+                flat[flat[index + 1].end].end
+            };
+        }
+        flat
+    }
+
+    fn position(flat: &[FlatNode], mut index: usize) -> Position {
+        let mut steps = Vec::new();
+        while let Some((parent, direction)) = flat[index].parent {
+            steps.push(direction);
+            index = parent;
+        }
+        steps.reverse();
+        steps.into()
+    }
+
+    // adds the patterns that match the subterm starting at `start`
+    fn root_matches(&self, flat: &[FlatNode], start: usize, found: &mut Vec<PatternId>) {
+        if self.fingerprints.candidates(flat[start].node).is_empty() {
+            return;
+        }
+        let mut stack = vec![(0, start)];
+        while let Some((trie_node, index)) = stack.pop() {
+            let trie_node = &self.trie[trie_node];
+            found.extend(&trie_node.patterns);
+            if index >= flat.len() {
+                continue;
+            }
+            if let Some(next) = trie_node.variable {
+                stack.push((next, flat[index].end));
+            }
+            if let Some(next) = trie_node.operation
+                && !flat[index].node.is_leaf()
+            {
+                stack.push((next, index + 1));
+            }
+        }
+    }
+
+    /// The patterns that are generalizations of `term`, i.e. match it at the root, in order.
+    #[allow(dead_code)]
+    pub fn generalizations(&self, term: &TermRef) -> Vec<PatternId> {
+        let mut found = Vec::new();
+        self.root_matches(&Self::flatten(term), 0, &mut found);
+        found.sort_unstable();
+        found
+    }
+
+    /// All matches of all patterns, ordered by position in pre-order and then by pattern.
+    pub fn matches(&self, term: &TermRef) -> Vec<(PatternId, Match)> {
+        let flat = Self::flatten(term);
+        let mut matches = Vec::new();
+        let mut found: Vec<PatternId> = Vec::new();
+
+        for start in 0..flat.len() {
+            self.root_matches(&flat, start, &mut found);
+            found.sort_unstable();
+            for id in found.drain(..) {
+                matches.push(self.matched(&flat, start, id));
+            }
+        }
+        matches
+    }
+
+    fn matched(&self, flat: &[FlatNode], start: usize, id: PatternId) -> (PatternId, Match) {
+        let root = flat[start].node;
+        let matched = Match {
+            position: Self::position(flat, start),
+            root: root.clone(),
+            bindings: self.patterns[id].instance_bindings(root).unwrap(),
+        };
+        (id, matched)
+    }
+
+    // the least pattern matching at `start`
+    fn first_root_match(&self, flat: &[FlatNode], start: usize) -> Option<PatternId> {
+        let mut found = Vec::new();
+        self.root_matches(flat, start, &mut found);
+        found.into_iter().min()
+    }
+
+    /// The matches at the outermost positions where some pattern matches, in pre-order, with the
+    /// least pattern at each. Subterms below them are not looked at.
+    pub fn outermost_matches(&self, term: &TermRef) -> Vec<(PatternId, Match)> {
+        let flat = Self::flatten(term);
+        let mut matches = Vec::new();
+        let mut start = 0;
+        while start < flat.len() {
+            match self.first_root_match(&flat, start) {
+                Some(id) => {
+                    matches.push(self.matched(&flat, start, id));
+                    start = flat[start].end;
+                }
+                None => start += 1,
+            }
+        }
+        matches
+    }
+
+    /// The first match in pre-order with the least pattern, stopping there.
+    pub fn leftmost_outermost(&self, term: &TermRef) -> Option<(PatternId, Match)> {
+        let flat = Self::flatten(term);
+        (0..flat.len()).find_map(|start| {
+            let id = self.first_root_match(&flat, start)?;
+            Some(self.matched(&flat, start, id))
+        })
+    }
+
+    /// The first match in post-order with the least pattern, stopping there.
+    pub fn leftmost_innermost(&self, term: &TermRef) -> Option<(PatternId, Match)> {
+        let flat = Self::flatten(term);
+        let mut stack = vec![(0, false)];
+        while let Some((index, visited)) = stack.pop() {
+            if visited || flat[index].node.is_leaf() {
+                if let Some(id) = self.first_root_match(&flat, index) {
+                    return Some(self.matched(&flat, index, id));
+                }
+            } else {
+                stack.push((index, true));
+                stack.push((flat[index + 1].end, false));
+                stack.push((index + 1, false));
+            }
+        }
+        None
+    }
+
+    /// Like `matches`, but with the operation commutative, so the children of every node of
+    /// `term` may be matched the other way around. Every pattern matches at most once per
+    /// position, with the bindings of the first orientation found, which prefers the given one.
+    #[allow(dead_code)]
+    pub fn matches_modulo_commutativity(&self, term: &TermRef) -> Vec<(PatternId, Match)> {
+        let flat = Self::flatten(term);
+        let mut matches = Vec::new();
+        let mut found: BTreeMap<PatternId, Vec<usize>> = BTreeMap::new();
+
+        for start in 0..flat.len() {
+            // `pending` holds the nodes still to be matched, the next one on top
+            let mut stack = vec![(0, vec![start], Vec::new())];
+            while let Some((trie_node, mut pending, bindings)) = stack.pop() {
+                let trie_node = &self.trie[trie_node];
+                let Some(index) = pending.pop() else {
+                    for &id in &trie_node.patterns {
+                        found.entry(id).or_insert_with(|| bindings.clone());
+                    }
+                    continue;
+                };
+                if let Some(next) = trie_node.variable {
+                    let mut bindings = bindings.clone();
+                    bindings.push(index);
+                    stack.push((next, pending.clone(), bindings));
+                }
+                if let Some(next) = trie_node.operation
+                    && !flat[index].node.is_leaf()
+                {
+                    let (left, right) = (index + 1, flat[index + 1].end);
+                    // swapping equal children gives the same matches again
+                    if flat[left].node != flat[right].node {
+                        let mut swapped = pending.clone();
+                        swapped.extend([left, right]);
+                        stack.push((next, swapped, bindings.clone()));
+                    }
+                    pending.extend([right, left]);
+                    stack.push((next, pending, bindings));
+                }
+            }
+
+            for (id, bindings) in std::mem::take(&mut found) {
+                matches.push((
+                    id,
+                    Match {
+                        position: Self::position(&flat, start),
+                        root: flat[start].node.clone(),
+                        bindings: bindings
+                            .into_iter()
+                            .map(|index| flat[index].node.clone())
+                            .collect(),
+                    },
+                ));
+            }
+        }
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{indexing::IndexedTerm, iter::TermIterator, term};
+
+    #[test]
+    fn pattern_index() {
+        let patterns = [
+            term!((a * b) * c),
+            term!(a * (b * c)),
+            term!((a * b) * (c * d)),
+            term!(a * b),
+            term!((a * b) * c),
+        ];
+        let mut index = PatternIndex::new();
+        for pattern in &patterns {
+            index.insert(pattern.clone());
+        }
+
+        for term in TermIterator::new(6) {
+            let matches = index.matches(&term);
+            for (id, pattern) in patterns.iter().enumerate() {
+                let expected = IndexedTerm::from(pattern.clone()).occurrences(&term);
+                let found: Vec<_> = matches
+                    .iter()
+                    .filter(|(other, _)| *other == id)
+                    .map(|(_, matched)| matched.position.clone())
+                    .collect();
+                assert_eq!(found, expected.positions());
+            }
+
+            let generalizations: Vec<_> = (0..patterns.len())
+                .filter(|&id| patterns[id].instance_bindings(&term).is_some())
+                .collect();
+            assert_eq!(index.generalizations(&term), generalizations);
+        }
+    }
+
+    // matching modulo commutativity, by brute force
+    fn matches_commutative(pattern: &TermRef, term: &TermRef) -> bool {
+        match (pattern.children(), term.children()) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some((pattern_left, pattern_right)), Some((left, right))) => {
+                matches_commutative(pattern_left, left) && matches_commutative(pattern_right, right)
+                    || matches_commutative(pattern_left, right)
+                        && matches_commutative(pattern_right, left)
+            }
+        }
+    }
+
+    #[test]
+    fn pattern_index_commutative() {
+        let patterns = [term!((a * b) * c), term!((a * b) * (c * d)), term!(a * b)];
+        let mut index = PatternIndex::new();
+        for pattern in &patterns {
+            index.insert(pattern.clone());
+        }
+
+        for term in TermIterator::new(6) {
+            let matches = index.matches_modulo_commutativity(&term);
+            for (id, pattern) in patterns.iter().enumerate() {
+                let expected: Vec<_> = term
+                    .subterms()
+                    .filter(|(_, node)| matches_commutative(pattern, node))
+                    .map(|(position, _)| position)
+                    .collect();
+                let indexed = IndexedTerm::from(pattern.clone());
+                assert_eq!(
+                    indexed.occurrences_modulo_commutativity(&term).positions(),
+                    expected
+                );
+
+                let found: Vec<_> = matches.iter().filter(|(other, _)| *other == id).collect();
+                for (_, matched) in &found {
+                    assert_eq!(matched.bindings.len(), pattern.leaves());
+                    let leaves: usize = matched.bindings.iter().map(|b| b.leaves()).sum();
+                    assert_eq!(leaves, matched.root.leaves());
+                }
+                let found: Vec<_> = found.iter().map(|(_, m)| m.position.clone()).collect();
+                assert_eq!(found, expected);
+            }
+        }
+    }
+}
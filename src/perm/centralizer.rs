@@ -0,0 +1,223 @@
+use crate::perm::{
+    group::PermutationGroup,
+    perms::{PermIndex, Permutation},
+};
+
+struct CycleStructure {
+    cycles: Vec<Vec<PermIndex>>,
+    cycle_of: Vec<usize>,
+    position: Vec<usize>,
+}
+
+impl CycleStructure {
+    fn new(perm: &Permutation<'_>, degree: usize) -> Self {
+        let mut cycles = perm.cycles().collect::<Vec<_>>();
+        let mut cycle_of = vec![usize::MAX; degree];
+        let mut position = vec![0; degree];
+
+        for (i, cycle) in cycles.iter().enumerate() {
+            for (j, point) in cycle.iter().enumerate() {
+                cycle_of[*point as usize] = i;
+                position[*point as usize] = j;
+            }
+        }
+
+        for (point, cycle) in cycle_of.iter_mut().enumerate() {
+            if *cycle == usize::MAX {
+                *cycle = cycles.len();
+                cycles.push(vec![point as PermIndex]);
+            }
+        }
+
+        CycleStructure {
+            cycles,
+            cycle_of,
+            position,
+        }
+    }
+
+    fn len_of(&self, point: PermIndex) -> usize {
+        self.cycles[self.cycle_of[point as usize]].len()
+    }
+
+    /// Whether an element commuting with the permutation can map `point` to `image`, given that
+    /// it maps the points of `assigned` to their respective images.
+    fn consistent(
+        &self,
+        point: PermIndex,
+        image: PermIndex,
+        assigned: &[(PermIndex, PermIndex)],
+    ) -> bool {
+        if self.len_of(point) != self.len_of(image) {
+            return false;
+        }
+
+        let cycle = self.cycle_of[point as usize];
+        assigned.iter().all(|&(other_point, other_image)| {
+            if self.cycle_of[other_point as usize] != cycle {
+                return self.cycle_of[other_image as usize] != self.cycle_of[image as usize];
+            }
+            // point = other_point^(x^k) forces image = other_image^(x^k)
+            let len = self.cycles[cycle].len();
+            let k =
+                (self.position[point as usize] + len - self.position[other_point as usize]) % len;
+            let image_cycle = &self.cycles[self.cycle_of[other_image as usize]];
+            image_cycle[(self.position[other_image as usize] + k) % len] == image
+        })
+    }
+}
+
+struct CentralizerSearch<'g, 'a> {
+    levels: Vec<&'g PermutationGroup<'a>>,
+    perm: Permutation<'static>,
+    cycles: CycleStructure,
+    degree: usize,
+}
+
+impl CentralizerSearch<'_, '_> {
+    fn commutes(&self, element: &Permutation<'_>) -> bool {
+        (0..self.degree as PermIndex)
+            .all(|i| element.get(self.perm.get(i)) == self.perm.get(element.get(i)))
+    }
+
+    /// Depth first search for an element `u_m ⋯ u_level · suffix` of the group commuting with the
+    /// permutation, where `u_i` ranges over the transversal of level `i`.
+    fn search(
+        &self,
+        level: usize,
+        suffix: Permutation<'static>,
+        assigned: &mut Vec<(PermIndex, PermIndex)>,
+    ) -> Option<Permutation<'static>> {
+        let Some(group) = self.levels.get(level) else {
+            return self.commutes(&suffix).then_some(suffix);
+        };
+
+        let base_point = group.stab_point();
+        for &orbit in group.orbit() {
+            let image = suffix.get(orbit);
+            if !self.cycles.consistent(base_point, image, assigned) {
+                continue;
+            }
+
+            assigned.push((base_point, image));
+            let repr = group.coset_repr(orbit).unwrap();
+            let found = self.search(level + 1, repr * &suffix, assigned);
+            assigned.pop();
+
+            if found.is_some() {
+                return found;
+            }
+        }
+        None
+    }
+}
+
+fn orbit_of(point: PermIndex, generators: &[Permutation<'_>]) -> Vec<PermIndex> {
+    let mut orbit = vec![point];
+    let mut i = 0;
+    while i < orbit.len() {
+        for generator in generators {
+            let image = generator.get(orbit[i]);
+            if !orbit.contains(&image) {
+                orbit.push(image);
+            }
+        }
+        i += 1;
+    }
+    orbit
+}
+
+impl PermutationGroup<'_> {
+    /// Computes the centralizer of `perm` in the group by a backtrack search over the stabilizer
+    /// chain. Levels are processed from the bottom up, so that on each level only one element per
+    /// orbit point of the partially known centralizer has to be found.
+    #[allow(dead_code)]
+    pub fn centralizer(&self, perm: &Permutation<'_>) -> PermutationGroup<'static> {
+        let levels = self.chain().collect::<Vec<_>>();
+        let degree = levels
+            .iter()
+            .flat_map(|group| group.orbit().iter().copied())
+            .max()
+            .map_or(0, |point| point as usize + 1)
+            .max(self.degree() as usize)
+            .max(perm._storage().len());
+
+        let search = CentralizerSearch {
+            cycles: CycleStructure::new(perm, degree),
+            perm: perm.clone().into_owned(),
+            levels,
+            degree,
+        };
+
+        let mut generators: Vec<Permutation<'static>> = Vec::new();
+
+        for level in (0..search.levels.len()).rev() {
+            let group = search.levels[level];
+            let base_point = group.stab_point();
+            // all generators found so far fix the base points above this level
+            let mut known_orbit = orbit_of(base_point, &generators);
+
+            let mut assigned = search.levels[..level]
+                .iter()
+                .map(|group| (group.stab_point(), group.stab_point()))
+                .collect::<Vec<_>>();
+
+            for &orbit in group.orbit() {
+                if known_orbit.contains(&orbit)
+                    || !search.cycles.consistent(base_point, orbit, &assigned)
+                {
+                    continue;
+                }
+
+                assigned.push((base_point, orbit));
+                let repr = group.coset_repr(orbit).unwrap();
+                let found = search.search(level + 1, repr, &mut assigned);
+                assigned.pop();
+
+                if let Some(element) = found {
+                    generators.push(element);
+                    known_orbit = orbit_of(base_point, &generators);
+                }
+            }
+        }
+
+        if generators.is_empty() {
+            PermutationGroup::new(0)
+        } else {
+            PermutationGroup::from_generators(generators)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symmetric_group(n: PermIndex) -> PermutationGroup<'static> {
+        PermutationGroup::from_generators(vec![
+            Permutation::from((1..n).chain([0]).collect::<Vec<_>>()),
+            Permutation::from(vec![1, 0]),
+        ])
+    }
+
+    #[test]
+    fn centralizer() {
+        let s4 = symmetric_group(4);
+        assert_eq!(s4.order(), 24);
+
+        let three_cycle = Permutation::from(vec![1, 2, 0]);
+        let centralizer = s4.centralizer(&three_cycle);
+        assert_eq!(centralizer.order(), 3);
+        assert!(centralizer.contains(&three_cycle));
+
+        let double_transposition = Permutation::from(vec![1, 0, 3, 2]);
+        let centralizer = s4.centralizer(&double_transposition);
+        assert_eq!(centralizer.order(), 8);
+        assert!(centralizer.contains(&Permutation::from(vec![2, 3, 0, 1])));
+        assert!(!centralizer.contains(&Permutation::from(vec![0, 2, 1, 3])));
+
+        let s6 = symmetric_group(6);
+        assert_eq!(s6.centralizer(&Permutation::from(vec![1, 0])).order(), 48);
+        assert_eq!(s6.centralizer(&Permutation::identity()).order(), 720);
+    }
+}
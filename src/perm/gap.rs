@@ -0,0 +1,278 @@
+use std::{fmt::Display, iter::Peekable, str::Chars};
+
+use crate::perm::{
+    group::PermutationGroup,
+    perms::{PermIndex, Permutation},
+};
+
+// GAP acts from the right and numbers points starting at 1, so the only translation needed is the
+// index shift: `i^(a*b) = (i^a)^b` agrees with `Permutation::times`.
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum GapParseError {
+    UnexpectedChar(usize, char),
+    UnexpectedEnd,
+    ZeroPoint(usize),
+    RepeatedPoint(usize, usize),
+    PointTooLarge(usize),
+}
+
+impl Display for GapParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnexpectedChar(pos, c) => write!(f, "unexpected '{}' at position {}", c, pos),
+            Self::UnexpectedEnd => write!(f, "unexpected end of input"),
+            Self::ZeroPoint(pos) => write!(f, "GAP points start at 1 (position {})", pos),
+            Self::RepeatedPoint(pos, point) => {
+                write!(f, "point {} repeated in cycle (position {})", point, pos)
+            }
+            Self::PointTooLarge(pos) => write!(f, "point too large (position {})", pos),
+        }
+    }
+}
+
+struct GapParser<'s> {
+    input: Peekable<Chars<'s>>,
+    pos: usize,
+}
+
+impl<'s> GapParser<'s> {
+    fn new(input: &'s str) -> Self {
+        GapParser {
+            input: input.chars().peekable(),
+            pos: 0,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.input.next_if(|c| c.is_whitespace()).is_some() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.input.peek().copied()
+    }
+
+    fn next(&mut self) -> Result<char, GapParseError> {
+        self.skip_whitespace();
+        self.pos += 1;
+        self.input.next().ok_or(GapParseError::UnexpectedEnd)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), GapParseError> {
+        match self.next()? {
+            c if c == expected => Ok(()),
+            c => Err(GapParseError::UnexpectedChar(self.pos - 1, c)),
+        }
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.input.next();
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> Result<bool, GapParseError> {
+        if self.peek() != keyword.chars().next() {
+            return Ok(false);
+        }
+        for expected in keyword.chars() {
+            self.expect(expected)?;
+        }
+        Ok(true)
+    }
+
+    fn point(&mut self) -> Result<PermIndex, GapParseError> {
+        let start = self.pos;
+        let mut value: usize = 0;
+        let mut digits = 0;
+        self.skip_whitespace();
+        while let Some(digit) = self.input.peek().and_then(|c| c.to_digit(10)) {
+            self.input.next();
+            self.pos += 1;
+            digits += 1;
+            value = value
+                .checked_mul(10)
+                .and_then(|value| value.checked_add(digit as usize))
+                .ok_or(GapParseError::PointTooLarge(start))?;
+        }
+        match (digits, value) {
+            (0, _) => match self.input.peek() {
+                Some(c) => Err(GapParseError::UnexpectedChar(self.pos, *c)),
+                None => Err(GapParseError::UnexpectedEnd),
+            },
+            (_, 0) => Err(GapParseError::ZeroPoint(start)),
+            // the points and the degree one above them have to fit
+            _ => PermIndex::try_from(value)
+                .map(|value| value - 1)
+                .map_err(|_| GapParseError::PointTooLarge(start)),
+        }
+    }
+
+    fn cycle(&mut self) -> Result<Permutation<'static>, GapParseError> {
+        self.expect('(')?;
+        if self.eat(')') {
+            return Ok(Permutation::identity());
+        }
+
+        let mut points = vec![self.point()?];
+        while self.eat(',') {
+            let pos = self.pos;
+            let point = self.point()?;
+            if points.contains(&point) {
+                return Err(GapParseError::RepeatedPoint(pos, point as usize + 1));
+            }
+            points.push(point);
+        }
+        self.expect(')')?;
+
+        let len = *points.iter().max().unwrap() as usize + 1;
+        let mut perm: Vec<PermIndex> = (0..len as PermIndex).collect();
+        for (i, point) in points.iter().enumerate() {
+            perm[*point as usize] = points[(i + 1) % points.len()];
+        }
+        Ok(perm.into())
+    }
+
+    fn permutation(&mut self) -> Result<Permutation<'static>, GapParseError> {
+        let mut perm = self.cycle()?;
+        while self.peek() == Some('(') {
+            perm *= self.cycle()?;
+        }
+        Ok(perm)
+    }
+
+    fn generators(&mut self) -> Result<Vec<Permutation<'static>>, GapParseError> {
+        let closing = if self.eat_keyword("Group")? {
+            self.expect('(')?;
+            Some(')')
+        } else if self.eat('[') {
+            Some(']')
+        } else {
+            None
+        };
+
+        let mut generators = Vec::new();
+        if closing.is_none_or(|c| self.peek() != Some(c)) {
+            generators.push(self.permutation()?);
+            while self.eat(',') {
+                generators.push(self.permutation()?);
+            }
+        }
+
+        if let Some(c) = closing {
+            self.expect(c)?;
+        }
+        self.eat(';');
+
+        match self.peek() {
+            None => Ok(generators),
+            Some(c) => Err(GapParseError::UnexpectedChar(self.pos, c)),
+        }
+    }
+}
+
+impl Permutation<'_> {
+    pub fn to_gap(&self) -> String {
+        let cycles = self
+            .cycles()
+            .map(|cycle| {
+                let points = cycle
+                    .iter()
+                    .map(|index| (index + 1).to_string())
+                    .collect::<Vec<_>>();
+                format!("({})", points.join(","))
+            })
+            .collect::<String>();
+
+        if cycles.is_empty() {
+            "()".to_string()
+        } else {
+            cycles
+        }
+    }
+
+    pub fn from_gap(input: &str) -> Result<Permutation<'static>, GapParseError> {
+        let mut parser = GapParser::new(input);
+        let perm = parser.permutation()?;
+        match parser.peek() {
+            None => Ok(perm),
+            Some(c) => Err(GapParseError::UnexpectedChar(parser.pos, c)),
+        }
+    }
+}
+
+impl PermutationGroup<'_> {
+    #[allow(dead_code)]
+    pub fn to_gap(&self) -> String {
+        let generators = self
+            .generators()
+            .iter()
+            .map(Permutation::to_gap)
+            .collect::<Vec<_>>();
+
+        if generators.is_empty() {
+            "Group(());".to_string()
+        } else {
+            format!("Group({});", generators.join(", "))
+        }
+    }
+
+    /// Accepts `Group(...);`, a bracketed list `[...]` or a bare comma separated list of
+    /// permutations in GAP cycle notation.
+    #[allow(dead_code)]
+    pub fn from_gap(input: &str) -> Result<PermutationGroup<'static>, GapParseError> {
+        let generators = GapParser::new(input)
+            .generators()?
+            .into_iter()
+            .filter(|perm| !perm.is_identity())
+            .collect::<Vec<_>>();
+
+        if generators.is_empty() {
+            Ok(PermutationGroup::new(0))
+        } else {
+            Ok(PermutationGroup::from_generators(generators))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gap_roundtrip() {
+        let perm = Permutation::from_gap("(1,3)(2, 4,5)").unwrap();
+        assert_eq!(perm, Permutation::from(vec![2, 3, 0, 4, 1]));
+        assert_eq!(perm.to_gap(), "(1,3)(2,4,5)");
+        assert_eq!(Permutation::identity().to_gap(), "()");
+
+        let group = PermutationGroup::from_gap("Group((1,2,3), (1,4));").unwrap();
+        assert_eq!(group.to_gap(), "Group((1,2,3), (1,4));");
+        assert!(group.contains(&Permutation::from_gap("(1,2,3,4)").unwrap()));
+
+        let reparsed = PermutationGroup::from_gap("[(1,2,3),(1,4)]").unwrap();
+        assert!(reparsed.contains(&Permutation::from_gap("(2,4)").unwrap()));
+
+        assert_eq!(
+            Permutation::from_gap("(1,0)"),
+            Err(GapParseError::ZeroPoint(3))
+        );
+        assert!(PermutationGroup::from_gap("Group((1,2),(1,2,3,2))").is_err());
+        assert_eq!(
+            Permutation::from_gap("(1,65536)"),
+            Err(GapParseError::PointTooLarge(3))
+        );
+        assert_eq!(
+            Permutation::from_gap("(1,99999999999999999999999)"),
+            Err(GapParseError::PointTooLarge(3))
+        );
+        assert!(Permutation::from_gap("(1,65535)").is_ok());
+    }
+}
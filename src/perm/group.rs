@@ -1,12 +1,75 @@
-use std::{collections::VecDeque, fmt::Debug};
+use std::{
+    collections::VecDeque,
+    fmt::{Debug, Display},
+};
 
-use crate::perm::perms::{PermIndex, Permutation};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    codec::{ByteReader, ByteWriter, DecodeError},
+    perm::perms::{PermIndex, Permutation},
+};
+
+#[derive(Debug)]
+pub enum VerifyErrorKind {
+    /// The transversal entry of `orbit` does not map `orbit` back to the stabilized point.
+    BadTransversal { orbit: PermIndex },
+    /// `generator` maps a point of the orbit outside of it.
+    OrbitNotClosed {
+        orbit: PermIndex,
+        generator: Permutation<'static>,
+    },
+    /// A Schreier generator of the level is not contained in the stabilizer subgroup.
+    SchreierGeneratorMissing {
+        orbit: PermIndex,
+        generator: Permutation<'static>,
+        schreier_generator: Permutation<'static>,
+    },
+}
+
+#[derive(Debug)]
+pub struct VerifyError {
+    pub level: usize,
+    pub stab_point: PermIndex,
+    pub kind: VerifyErrorKind,
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "level {} (stabilizing {}): ",
+            self.level, self.stab_point
+        )?;
+        match &self.kind {
+            VerifyErrorKind::BadTransversal { orbit } => {
+                write!(f, "transversal entry of {} is broken", orbit)
+            }
+            VerifyErrorKind::OrbitNotClosed { orbit, generator } => write!(
+                f,
+                "generator {} maps orbit point {} outside of the orbit",
+                generator, orbit
+            ),
+            VerifyErrorKind::SchreierGeneratorMissing {
+                orbit,
+                generator,
+                schreier_generator,
+            } => write!(
+                f,
+                "Schreier generator {} (orbit point {}, generator {}) is not in the stabilizer",
+                schreier_generator, orbit, generator
+            ),
+        }
+    }
+}
 
 /// An implementation of the Schreier–Sims algorithm
 /// See for details:
 /// - https://en.wikipedia.org/wiki/Schreier%E2%80%93Sims_algorithm
 /// - https://arxiv.org/pdf/math/9201304
 /// - https://blogs.cs.st-andrews.ac.uk/codima/files/2015/11/CoDiMa2015_Holt.pdf
+#[derive(Serialize, Deserialize)]
+#[serde(try_from = "Unchecked<'a>")]
 pub struct PermutationGroup<'a> {
     stab_point: PermIndex,
     stab_subgroup: Option<Box<PermutationGroup<'a>>>,
@@ -16,6 +79,32 @@ pub struct PermutationGroup<'a> {
     orbits: Vec<PermIndex>,
 }
 
+// the serialized form, before checking that the stabilizer chain is consistent
+#[derive(Deserialize)]
+struct Unchecked<'a> {
+    stab_point: PermIndex,
+    stab_subgroup: Option<Box<PermutationGroup<'a>>>,
+    generators: Vec<Permutation<'a>>,
+    transversal_inv: Vec<Option<Permutation<'a>>>,
+    orbits: Vec<PermIndex>,
+}
+
+impl<'a> TryFrom<Unchecked<'a>> for PermutationGroup<'a> {
+    type Error = DecodeError;
+
+    fn try_from(unchecked: Unchecked<'a>) -> Result<Self, DecodeError> {
+        let group = PermutationGroup {
+            stab_point: unchecked.stab_point,
+            stab_subgroup: unchecked.stab_subgroup,
+            generators: unchecked.generators,
+            transversal_inv: unchecked.transversal_inv,
+            orbits: unchecked.orbits,
+        };
+        group.check_decoded()?;
+        Ok(group)
+    }
+}
+
 impl<'a> PermutationGroup<'a> {
     fn inv_coset_repr(&self, orbit: PermIndex) -> &Option<Permutation<'a>> {
         if orbit as usize >= self.transversal_inv.len() {
@@ -77,17 +166,112 @@ impl<'a> PermutationGroup<'a> {
         }
     }
 
+    #[allow(dead_code)]
     pub fn stab_subgroup(&self) -> &Option<Box<PermutationGroup<'_>>> {
         &self.stab_subgroup
     }
 
+    pub fn stab_point(&self) -> PermIndex {
+        self.stab_point
+    }
+
+    pub fn orbit(&self) -> &[PermIndex] {
+        &self.orbits
+    }
+
+    /// The coset representative mapping the stabilized point to `orbit`.
+    pub fn coset_repr(&self, orbit: PermIndex) -> Option<Permutation<'static>> {
+        self.inv_coset_repr(orbit)
+            .as_ref()
+            .map(Permutation::inverse)
+    }
+
+    /// The same element for all of `perm * self`, namely the one under which the preimages of the
+    /// base points are least, in the order of the stabilizer chain.
+    pub fn canonical_coset_repr(&self, perm: &Permutation<'_>) -> Permutation<'static> {
+        let mut repr = perm.clone().into_owned();
+        for group in self.chain() {
+            let preimages = repr.inverse();
+            let best = *group
+                .orbit()
+                .iter()
+                .min_by_key(|&&orbit| preimages.get(orbit))
+                .unwrap();
+            // moves `best` to the base point, whose preimage the stabilizers below keep
+            repr *= group.inv_coset_repr(best).as_ref().unwrap();
+        }
+        let len = (perm._storage().len() as PermIndex).max(self.degree());
+        (0..len)
+            .map(|index| repr.get(index))
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    /// The levels of the stabilizer chain, starting with `self`.
+    pub fn chain(&self) -> impl Iterator<Item = &PermutationGroup<'a>> {
+        std::iter::successors(Some(self), |group| group.stab_subgroup.as_deref())
+    }
+
+    #[allow(dead_code)]
+    pub fn order(&self) -> u128 {
+        self.chain()
+            .map(|group| group.orbits.len() as u128)
+            .product()
+    }
+
+    pub fn generators(&self) -> &[Permutation<'a>] {
+        &self.generators
+    }
+
+    /// One more than the largest point moved by any generator.
+    pub fn degree(&self) -> PermIndex {
+        self.generators
+            .iter()
+            .filter_map(Permutation::largest_moved_point)
+            .max()
+            .map_or(0, |point| point + 1)
+    }
+
+    /// The direct product acting on the disjoint union of both domains, where `domain` is the
+    /// number of points `self` acts on and the points of `other` are shifted behind them. The
+    /// generators do not tell the domain, since they leave the points beyond `degree` fixed.
+    #[allow(dead_code)]
+    pub fn direct_product(
+        &self,
+        domain: PermIndex,
+        other: &PermutationGroup<'_>,
+    ) -> PermutationGroup<'static> {
+        assert!(
+            self.degree() <= domain,
+            "the group moves points outside of its domain"
+        );
+        let offset = domain;
+        let generators = self
+            .generators
+            .iter()
+            .map(|generator| generator.clone().into_owned())
+            .chain(
+                other
+                    .generators
+                    .iter()
+                    .map(|generator| generator.shifted(offset)),
+            )
+            .collect::<Vec<_>>();
+
+        if generators.is_empty() {
+            PermutationGroup::new(0)
+        } else {
+            PermutationGroup::from_generators(generators)
+        }
+    }
+
     pub fn extend(&mut self, generator: Permutation<'a>) {
         if self.contains(&generator) {
             return;
         }
 
-        self.generators.push(generator.clone());
-        let mut generator_inv = None;
+        self.generators.push(generator);
+        let mut generators_inv = vec![None; self.generators.len()];
 
         fn process_orbit<'a>(
             group: &mut PermutationGroup<'a>,
@@ -126,15 +310,202 @@ impl<'a> PermutationGroup<'a> {
         }
 
         let mut queue = VecDeque::new();
+        let new_index = self.generators.len() - 1;
+        let generator = self.generators[new_index].clone();
 
         for i in 0..self.orbits.len() {
             let orbit = self.orbits[i];
-            process_orbit(self, &generator, &mut generator_inv, orbit, &mut queue);
+            process_orbit(
+                self,
+                &generator,
+                &mut generators_inv[new_index],
+                orbit,
+                &mut queue,
+            );
         }
 
+        // points that newly joined the orbit have to be processed with the old generators as well
         while let Some(orbit) = queue.pop_front() {
-            process_orbit(self, &generator, &mut generator_inv, orbit, &mut queue);
+            for (i, generator_inv) in generators_inv.iter_mut().enumerate() {
+                let generator = self.generators[i].clone();
+                process_orbit(self, &generator, generator_inv, orbit, &mut queue);
+            }
+        }
+
+        if cfg!(debug_assertions)
+            && let Err(error) = self.verify()
+        {
+            panic!("corrupted stabilizer chain after extend: {}", error);
+        }
+    }
+
+    /// Sims verification: checks on every level that the orbit is closed under the generators and
+    /// that all Schreier generators are contained in the next level's group.
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        for (level, current) in self.chain().enumerate() {
+            let error = |kind| VerifyError {
+                level,
+                stab_point: current.stab_point,
+                kind,
+            };
+
+            for &orbit in &current.orbits {
+                let inv_coset_repr = match current.inv_coset_repr(orbit) {
+                    Some(repr) if repr.get(orbit) == current.stab_point => repr,
+                    _ => return Err(error(VerifyErrorKind::BadTransversal { orbit })),
+                };
+
+                for generator in &current.generators {
+                    let new_orbit = generator.get(orbit);
+                    let Some(new_inv_coset_repr) = current.inv_coset_repr(new_orbit) else {
+                        return Err(error(VerifyErrorKind::OrbitNotClosed {
+                            orbit,
+                            generator: generator.clone().into_owned(),
+                        }));
+                    };
+
+                    let schreier_generator =
+                        inv_coset_repr.inverse() * generator * new_inv_coset_repr;
+                    let contained = match &current.stab_subgroup {
+                        Some(subgroup) => {
+                            schreier_generator.get(current.stab_point) == current.stab_point
+                                && subgroup.contains(&schreier_generator)
+                        }
+                        None => schreier_generator.is_identity(),
+                    };
+
+                    if !contained {
+                        return Err(error(VerifyErrorKind::SchreierGeneratorMissing {
+                            orbit,
+                            generator: generator.clone().into_owned(),
+                            schreier_generator,
+                        }));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// every level of a stabilizer chain fixes one more point, so real chains are far shallower, and
+// corrupt input must not exhaust the stack
+const MAX_DECODED_DEPTH: usize = 1024;
+
+impl PermutationGroup<'_> {
+    /// Compact binary encoding of the whole stabilizer chain, including transversals, so that
+    /// decoding does not need to rerun Schreier–Sims.
+    #[allow(dead_code)]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = ByteWriter::new();
+        self.encode_into(&mut writer);
+        writer.into_bytes()
+    }
+
+    #[allow(dead_code)]
+    pub fn decode(bytes: &[u8]) -> Result<PermutationGroup<'static>, DecodeError> {
+        let mut reader = ByteReader::new(bytes);
+        let group = PermutationGroup::decode_from(&mut reader, 0)?;
+        reader.finish()?;
+        group.check_decoded()?;
+        Ok(group)
+    }
+
+    // The orbit of every level starts with its stabilized point and has a transversal entry for
+    // exactly its points, the next level fixes the point, and the chain passes `verify`.
+    // Otherwise `contains` and `extend` could panic or answer wrongly.
+    fn check_decoded(&self) -> Result<(), DecodeError> {
+        for group in self.chain() {
+            let mut in_orbit = vec![false; group.transversal_inv.len()];
+            for &orbit in &group.orbits {
+                match in_orbit.get_mut(orbit as usize) {
+                    Some(seen) if !*seen => *seen = true,
+                    _ => return Err(DecodeError::InvalidGroup),
+                }
+            }
+            let fixed = group.stab_subgroup.as_ref().is_none_or(|subgroup| {
+                subgroup
+                    .generators
+                    .iter()
+                    .all(|generator| generator.get(group.stab_point) == group.stab_point)
+            });
+            if group.orbits.first() != Some(&group.stab_point)
+                || (0..in_orbit.len()).any(|i| in_orbit[i] != group.transversal_inv[i].is_some())
+                || !fixed
+            {
+                return Err(DecodeError::InvalidGroup);
+            }
         }
+        self.verify().map_err(|_| DecodeError::InvalidGroup)
+    }
+
+    fn encode_into(&self, writer: &mut ByteWriter) {
+        writer.u16(self.stab_point);
+
+        writer.u32(self.generators.len() as u32);
+        self.generators
+            .iter()
+            .for_each(|generator| generator.encode_into(writer));
+
+        writer.u32(self.transversal_inv.len() as u32);
+        for repr in &self.transversal_inv {
+            match repr {
+                None => writer.u8(0),
+                Some(perm) => {
+                    writer.u8(1);
+                    perm.encode_into(writer);
+                }
+            }
+        }
+
+        writer.u16_slice(&self.orbits);
+
+        match &self.stab_subgroup {
+            None => writer.u8(0),
+            Some(subgroup) => {
+                writer.u8(1);
+                subgroup.encode_into(writer);
+            }
+        }
+    }
+
+    fn decode_from(
+        reader: &mut ByteReader,
+        depth: usize,
+    ) -> Result<PermutationGroup<'static>, DecodeError> {
+        if depth > MAX_DECODED_DEPTH {
+            return Err(DecodeError::TooDeep);
+        }
+        let stab_point = reader.u16()?;
+
+        let generators = (0..reader.u32()?)
+            .map(|_| Permutation::decode_from(reader))
+            .collect::<Result<_, _>>()?;
+
+        let transversal_inv = (0..reader.u32()?)
+            .map(|_| {
+                reader
+                    .bool()?
+                    .then(|| Permutation::decode_from(reader))
+                    .transpose()
+            })
+            .collect::<Result<_, _>>()?;
+
+        let orbits = reader.u16_vec()?;
+
+        let stab_subgroup = reader
+            .bool()?
+            .then(|| PermutationGroup::decode_from(reader, depth + 1).map(Box::new))
+            .transpose()?;
+
+        Ok(PermutationGroup {
+            stab_point,
+            stab_subgroup,
+            generators,
+            transversal_inv,
+            orbits,
+        })
     }
 }
 
@@ -179,4 +550,117 @@ mod tests {
             group.contains(&Permutation::from(vec![1, 0, 2, 3]))
         );
     }
+
+    #[test]
+    fn direct_product() {
+        let rotation = PermutationGroup::from_generators(vec![Permutation::from(vec![1, 2, 0])]);
+        let swap = PermutationGroup::from_generators(vec![Permutation::from(vec![1, 0])]);
+        let product = rotation.direct_product(3, &swap);
+        assert_eq!(product.order(), 6);
+        assert_eq!(product.degree(), 5);
+        assert!(product.contains(&Permutation::from(vec![1, 2, 0, 4, 3])));
+        assert!(!product.contains(&Permutation::from(vec![1, 0])));
+        assert_eq!(swap.direct_product(2, &PermutationGroup::new(0)).order(), 2);
+
+        // S3 on 0..5 fixes 3 and 4, which the second factor must not take over
+        let symmetric = PermutationGroup::from_generators(vec![
+            Permutation::from(vec![1, 2, 0]),
+            Permutation::from(vec![1, 0, 2]),
+        ]);
+        let product = symmetric.direct_product(5, &symmetric);
+        assert_eq!(product.order(), 36);
+        assert_eq!(product.degree(), 8);
+        assert!(product.contains(&Permutation::from(vec![1, 0, 2, 3, 4, 6, 5, 7])));
+        assert!(!product.contains(&Permutation::from(vec![0, 1, 2, 4, 3])));
+        assert!(!product.contains(&Permutation::from(vec![0, 1, 2, 3, 5, 4])));
+    }
+
+    #[test]
+    #[should_panic(expected = "outside of its domain")]
+    fn direct_product_small_domain() {
+        let rotation = PermutationGroup::from_generators(vec![Permutation::from(vec![1, 2, 0])]);
+        rotation.direct_product(2, &rotation);
+    }
+
+    #[test]
+    fn canonical_coset_repr() {
+        let perms: Vec<_> = [
+            [0, 1, 2],
+            [0, 2, 1],
+            [1, 0, 2],
+            [1, 2, 0],
+            [2, 0, 1],
+            [2, 1, 0],
+        ]
+        .into_iter()
+        .map(|perm| Permutation::from(perm.to_vec()))
+        .collect();
+        let count = |group: &PermutationGroup| {
+            let reprs: std::collections::HashSet<_> = perms
+                .iter()
+                .map(|perm| group.canonical_coset_repr(perm))
+                .collect();
+            // every representative is in the coset of the permutations it represents
+            for perm in &perms {
+                let repr = group.canonical_coset_repr(perm);
+                assert!(group.contains(&(perm.inverse() * &repr)));
+            }
+            reprs.len()
+        };
+
+        let swap = PermutationGroup::from_generators(vec![Permutation::from(vec![1, 0])]);
+        assert_eq!(count(&swap), 3);
+        let symmetric = PermutationGroup::from_generators(vec![
+            Permutation::from(vec![1, 2, 0]),
+            Permutation::from(vec![1, 0, 2]),
+        ]);
+        assert_eq!(count(&symmetric), 1);
+    }
+
+    #[test]
+    fn encode_roundtrip() {
+        let group = PermutationGroup::from_generators(vec![
+            Permutation::from(vec![1, 2, 0]),
+            Permutation::from(vec![3, 1, 2, 0]),
+        ]);
+
+        let decoded = PermutationGroup::decode(&group.encode()).unwrap();
+        assert_eq!(format!("{:#?}", group), format!("{:#?}", decoded));
+        assert!(decoded.contains(&Permutation::from(vec![1, 0, 2, 3])));
+        assert!(PermutationGroup::decode(&group.encode()[1..]).is_err());
+
+        // well-formed, but the chain does not describe a group
+        let rotation = PermutationGroup::from_generators(vec![Permutation::from(vec![1, 2, 0])]);
+        let mut corrupt = PermutationGroup::decode(&rotation.encode()).unwrap();
+        corrupt.generators[0] = Permutation::from(vec![1, 0, 2]);
+        assert_eq!(
+            PermutationGroup::decode(&corrupt.encode()).err(),
+            Some(DecodeError::InvalidGroup)
+        );
+        let mut corrupt = PermutationGroup::decode(&rotation.encode()).unwrap();
+        corrupt.orbits.swap(0, 1);
+        assert_eq!(
+            PermutationGroup::decode(&corrupt.encode()).err(),
+            Some(DecodeError::InvalidGroup)
+        );
+
+        // a chain of empty levels, each announcing another one below it
+        let mut nested = Vec::new();
+        for _ in 0..=MAX_DECODED_DEPTH {
+            nested.extend([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        }
+        assert_eq!(
+            PermutationGroup::decode(&nested).err(),
+            Some(DecodeError::TooDeep)
+        );
+    }
+
+    #[test]
+    fn orbit_closed_under_old_generators() {
+        let mut group =
+            PermutationGroup::from_generators(vec![Permutation::from(vec![1, 0, 3, 2])]);
+        group.extend(Permutation::from(vec![0, 2, 1]));
+        assert!(group.verify().is_ok());
+        assert!(group.contains(&Permutation::from(vec![3, 1, 2, 0])));
+    }
 }
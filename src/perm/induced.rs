@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use crate::perm::{
+    group::PermutationGroup,
+    perms::{PermIndex, Permutation},
+};
+
+/// A group acting on tuples or subsets of the points of another group. The point `i` of `group`
+/// corresponds to `points[i]`.
+#[allow(dead_code)]
+pub struct InducedAction {
+    pub group: PermutationGroup<'static>,
+    pub points: Vec<Vec<PermIndex>>,
+}
+
+impl InducedAction {
+    // `points` have to be closed under `act`, otherwise this panics
+    fn new<'a, F: Fn(&[PermIndex], &Permutation<'a>) -> Vec<PermIndex>>(
+        generators: &[Permutation<'a>],
+        points: Vec<Vec<PermIndex>>,
+        act: F,
+    ) -> Self {
+        assert!(
+            points.len() <= PermIndex::MAX as usize + 1,
+            "induced action has too many points"
+        );
+
+        let index = points
+            .iter()
+            .enumerate()
+            .map(|(i, point)| (point.clone(), i as PermIndex))
+            .collect::<HashMap<_, _>>();
+
+        let induced_generators = generators
+            .iter()
+            .map(|generator| {
+                points
+                    .iter()
+                    .map(|point| {
+                        *index
+                            .get(&act(point, generator))
+                            .expect("the points are not closed under the group")
+                    })
+                    .collect::<Vec<_>>()
+                    .into()
+            })
+            .filter(|perm: &Permutation<'static>| !perm.is_identity())
+            .collect::<Vec<_>>();
+
+        let group = if induced_generators.is_empty() {
+            PermutationGroup::new(0)
+        } else {
+            PermutationGroup::from_generators(induced_generators)
+        };
+
+        InducedAction { group, points }
+    }
+}
+
+fn subsets(n: PermIndex, k: usize) -> Vec<Vec<PermIndex>> {
+    fn extend(
+        n: PermIndex,
+        k: usize,
+        current: &mut Vec<PermIndex>,
+        result: &mut Vec<Vec<PermIndex>>,
+    ) {
+        if current.len() == k {
+            result.push(current.clone());
+            return;
+        }
+        let start = current.last().map_or(0, |last| last + 1);
+        for point in start..n {
+            current.push(point);
+            extend(n, k, current, result);
+            current.pop();
+        }
+    }
+
+    let mut result = Vec::new();
+    extend(n, k, &mut Vec::new(), &mut result);
+    result
+}
+
+impl PermutationGroup<'_> {
+    /// The action on ordered pairs of distinct points of `0..n`, where `n` is at least the
+    /// degree of the group.
+    #[allow(dead_code)]
+    pub fn induced_on_pairs(&self, n: PermIndex) -> InducedAction {
+        assert!(n >= self.degree(), "the group moves points outside of 0..n");
+        let points = (0..n)
+            .flat_map(|a| (0..n).filter(move |b| a != *b).map(move |b| vec![a, b]))
+            .collect();
+
+        InducedAction::new(self.generators(), points, |pair, generator| {
+            pair.iter().map(|point| generator.get(*point)).collect()
+        })
+    }
+
+    /// The action on `k`-element subsets of `0..n`, which are represented as sorted lists. `n`
+    /// is at least the degree of the group.
+    #[allow(dead_code)]
+    pub fn induced_on_subsets(&self, n: PermIndex, k: usize) -> InducedAction {
+        assert!(n >= self.degree(), "the group moves points outside of 0..n");
+        InducedAction::new(self.generators(), subsets(n, k), |subset, generator| {
+            let mut image = subset
+                .iter()
+                .map(|point| generator.get(*point))
+                .collect::<Vec<_>>();
+            image.sort_unstable();
+            image
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn induced_action() {
+        let symmetric = PermutationGroup::from_generators(vec![
+            Permutation::from(vec![1, 2, 0]),
+            Permutation::from(vec![1, 0]),
+        ]);
+        let pairs = symmetric.induced_on_pairs(3);
+        assert_eq!(pairs.points.len(), 6);
+        assert_eq!(pairs.group.order(), 6);
+
+        // a rotation of the square moves the diagonals {0, 2} and {1, 3} onto each other
+        let rotation = PermutationGroup::from_generators(vec![Permutation::from(vec![1, 2, 3, 0])]);
+        let edges = rotation.induced_on_subsets(4, 2);
+        assert_eq!(edges.points.len(), 6);
+        assert_eq!(edges.group.order(), 4);
+        let diagonal = edges
+            .points
+            .iter()
+            .position(|point| point == &[0, 2])
+            .unwrap();
+        let image = edges.group.generators()[0].get(diagonal as PermIndex);
+        assert_eq!(edges.points[image as usize], [1, 3]);
+
+        // points outside of the group's domain are fixed
+        assert_eq!(symmetric.induced_on_subsets(5, 1).group.order(), 6);
+    }
+
+    #[test]
+    #[should_panic(expected = "outside of 0..n")]
+    fn induced_below_degree() {
+        let rotation = PermutationGroup::from_generators(vec![Permutation::from(vec![1, 2, 0])]);
+        rotation.induced_on_pairs(2);
+    }
+}
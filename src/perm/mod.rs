@@ -1,2 +1,5 @@
+pub mod centralizer;
+pub mod gap;
 pub mod group;
+pub mod induced;
 pub mod perms;
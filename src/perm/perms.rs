@@ -4,37 +4,68 @@ use std::{
     ops::{Mul, MulAssign},
 };
 
+use serde::{Deserialize, Serialize};
+
+use crate::codec::{ByteReader, ByteWriter, DecodeError};
+
 pub type PermIndex = u16;
 
-#[derive(Hash, PartialEq, Eq, Clone)]
+#[derive(Hash, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(try_from = "Unchecked")]
 pub struct Permutation<'a> {
     perm: Cow<'a, [PermIndex]>,
 }
 
+// the serialized form, before checking that it is a bijection
+#[derive(Deserialize)]
+struct Unchecked {
+    perm: Vec<PermIndex>,
+}
+
+impl TryFrom<Unchecked> for Permutation<'_> {
+    type Error = DecodeError;
+
+    fn try_from(unchecked: Unchecked) -> Result<Self, DecodeError> {
+        Permutation::checked(unchecked.perm)
+    }
+}
+
 impl<'a> Permutation<'a> {
-    fn display_cycle(
-        &self,
-        f: &mut std::fmt::Formatter<'_>,
-        start: PermIndex,
-        visited: &mut [bool],
-        identity: &mut bool,
-    ) -> std::fmt::Result {
-        visited[start as usize] = true;
-
-        let mut index = self.get(start);
-        if index == start {
-            return Ok(());
-        }
-
-        *identity = false;
-        write!(f, "({}", start)?;
-        loop {
-            visited[index as usize] = true;
-            write!(f, " {}", index)?;
-            index = self.get(index);
-            if index == start {
-                return write!(f, ")");
-            }
+    /// Iterates over the nontrivial cycles, each starting with its smallest element.
+    pub fn cycles(&self) -> Cycles<'_, 'a> {
+        Cycles {
+            perm: self,
+            visited: vec![false; self.perm.len()],
+            start: 0,
+        }
+    }
+
+    /// Lengths of the nontrivial cycles in descending order.
+    pub fn cycle_type(&self) -> Vec<usize> {
+        let mut lengths = self.cycles().map(|cycle| cycle.len()).collect::<Vec<_>>();
+        lengths.sort_unstable_by(|a, b| b.cmp(a));
+        lengths
+    }
+
+    /// The least common multiple of the cycle lengths, or `None` if it does not fit in a `u128`,
+    /// which happens for some permutations of a few hundred points.
+    pub fn order(&self) -> Option<u128> {
+        fn gcd(a: u128, b: u128) -> u128 {
+            if b == 0 { a } else { gcd(b, a % b) }
+        }
+
+        self.cycles().try_fold(1, |acc: u128, cycle| {
+            let len = cycle.len() as u128;
+            (acc / gcd(acc, len)).checked_mul(len)
+        })
+    }
+
+    pub fn sign(&self) -> i8 {
+        let transpositions: usize = self.cycles().map(|cycle| cycle.len() - 1).sum();
+        if transpositions.is_multiple_of(2) {
+            1
+        } else {
+            -1
         }
     }
 
@@ -47,6 +78,12 @@ impl<'a> Permutation<'a> {
         }
     }
 
+    pub fn into_owned(self) -> Permutation<'static> {
+        Permutation {
+            perm: Cow::Owned(self.perm.into_owned()),
+        }
+    }
+
     pub fn nonfix_index(&self) -> Option<PermIndex> {
         for (i, v) in self.perm.iter().enumerate() {
             if i != *v as usize {
@@ -56,6 +93,28 @@ impl<'a> Permutation<'a> {
         None
     }
 
+    pub fn largest_moved_point(&self) -> Option<PermIndex> {
+        self.perm
+            .iter()
+            .enumerate()
+            .rposition(|(i, v)| i != *v as usize)
+            .map(|i| i as PermIndex)
+    }
+
+    /// The permutation acting on `offset..` the same way `self` acts on `0..`. The shifted degree
+    /// has to fit into a `PermIndex`.
+    pub fn shifted(&self, offset: PermIndex) -> Permutation<'static> {
+        assert!(
+            offset as usize + self.perm.len() <= PermIndex::MAX as usize + 1,
+            "shifted permutation has too many points"
+        );
+        Permutation {
+            perm: (0..offset)
+                .chain(self.perm.iter().map(|v| v.checked_add(offset).unwrap()))
+                .collect(),
+        }
+    }
+
     pub fn is_identity(&self) -> bool {
         self.nonfix_index().is_none()
     }
@@ -107,6 +166,56 @@ impl<'a> Permutation<'a> {
 
         self.perm.to_mut().iter_mut().for_each(|v| *v = rhs.get(*v));
     }
+
+    pub fn encode_into(&self, writer: &mut ByteWriter) {
+        writer.u16_slice(&self.perm);
+    }
+
+    pub fn decode_from(reader: &mut ByteReader) -> Result<Permutation<'static>, DecodeError> {
+        Permutation::checked(reader.u16_vec()?)
+    }
+
+    /// The permutation with these images, if every index below their number occurs once.
+    pub fn checked(perm: Vec<PermIndex>) -> Result<Permutation<'static>, DecodeError> {
+        let mut seen = vec![false; perm.len()];
+        for &v in &perm {
+            match seen.get_mut(v as usize) {
+                Some(seen) if !*seen => *seen = true,
+                _ => return Err(DecodeError::NotPermutation),
+            }
+        }
+        Ok(perm.into())
+    }
+}
+
+pub struct Cycles<'p, 'a> {
+    perm: &'p Permutation<'a>,
+    visited: Vec<bool>,
+    start: usize,
+}
+
+impl Iterator for Cycles<'_, '_> {
+    type Item = Vec<PermIndex>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.start < self.visited.len() {
+            let start = self.start;
+            self.start += 1;
+            if self.visited[start] || self.perm.perm[start] as usize == start {
+                continue;
+            }
+
+            let mut cycle = Vec::new();
+            let mut index = start;
+            while !self.visited[index] {
+                self.visited[index] = true;
+                cycle.push(index as PermIndex);
+                index = self.perm.perm[index] as usize;
+            }
+            return Some(cycle);
+        }
+        None
+    }
 }
 
 impl<'a, B: Borrow<Permutation<'a>>> Mul<B> for Permutation<'_> {
@@ -151,20 +260,14 @@ impl<'a> Debug for Permutation<'a> {
 impl<'a> Display for Permutation<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if !f.alternate() {
-            let mut visited = vec![false; self.perm.len()];
             let mut identity = true;
-            if !self.perm.is_empty() {
-                'inf: loop {
-                    let mut start_index = 0;
-                    while visited[start_index] {
-                        start_index += 1;
-
-                        if start_index == self.perm.len() {
-                            break 'inf;
-                        }
-                    }
-                    self.display_cycle(f, start_index as PermIndex, &mut visited, &mut identity)?;
+            for cycle in self.cycles() {
+                identity = false;
+                write!(f, "({}", cycle[0])?;
+                for index in &cycle[1..] {
+                    write!(f, " {}", index)?;
                 }
+                write!(f, ")")?;
             }
             if identity { write!(f, "()") } else { Ok(()) }
         } else {
@@ -182,3 +285,67 @@ impl<'a> Display for Permutation<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde::de::{IntoDeserializer, value::MapDeserializer};
+
+    use super::*;
+
+    fn decode(values: &[PermIndex]) -> Result<Permutation<'static>, DecodeError> {
+        let mut writer = ByteWriter::new();
+        writer.u16_slice(values);
+        let bytes = writer.into_bytes();
+        Permutation::decode_from(&mut ByteReader::new(&bytes))
+    }
+
+    fn deserialize(values: Vec<PermIndex>) -> Result<Permutation<'static>, String> {
+        let fields = MapDeserializer::<_, serde::de::value::Error>::new(
+            [("perm", values.into_deserializer())].into_iter(),
+        );
+        Permutation::deserialize(fields).map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn shifted() {
+        let perm = Permutation::from(vec![1, 0]);
+        assert_eq!(perm.shifted(2), Permutation::from(vec![0, 1, 3, 2]));
+        assert_eq!(perm.shifted(PermIndex::MAX - 1)._storage().len(), 1 << 16);
+    }
+
+    #[test]
+    #[should_panic(expected = "too many points")]
+    fn shifted_overflow() {
+        Permutation::from(vec![1, 0]).shifted(PermIndex::MAX);
+    }
+
+    #[test]
+    fn order() {
+        assert_eq!(Permutation::identity().order(), Some(1));
+        assert_eq!(Permutation::from(vec![1, 0, 3, 4, 2]).order(), Some(6));
+
+        // one cycle for each of the primes below 120, whose product does not fit in a u128
+        let primes = (2..120).filter(|&n| (2..n).all(|d| n % d != 0));
+        let mut images = Vec::new();
+        for prime in primes {
+            let start = images.len() as PermIndex;
+            images.extend((1..prime).map(|i| start + i));
+            images.push(start);
+        }
+        assert_eq!(Permutation::from(images).order(), None);
+    }
+
+    #[test]
+    fn decode_checks_bijection() {
+        let perm = Permutation::from(vec![2, 0, 1]);
+        assert_eq!(decode(perm._storage()), Ok(perm.clone()));
+        assert_eq!(decode(&[5]), Err(DecodeError::NotPermutation));
+        assert_eq!(decode(&[0, 0]), Err(DecodeError::NotPermutation));
+
+        assert_eq!(deserialize(vec![2, 0, 1]), Ok(perm));
+        assert_eq!(
+            deserialize(vec![1, 1]),
+            Err(DecodeError::NotPermutation.to_string())
+        );
+    }
+}
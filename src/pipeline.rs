@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use rayon::prelude::*;
+
+use crate::{
+    bidag::BinaryChildren,
+    indexing::PatternAutomaton,
+    maps::TermMap,
+    perm::perms::Permutation,
+    position::Position,
+    sync::{SyncTerm, SyncTermRef},
+    term::{TermPointer, TermRef},
+};
+
+/// Finds the equivalences that apply to a stream of terms. The terms are collected into batches,
+/// and the terms of a batch are matched against all patterns in parallel, as `SyncTermRef`s,
+/// which also computes the permutations of the results. Only the new nodes of the results are
+/// built afterwards, so the results arrive in the same order as with a plain loop over the terms.
+pub struct MatchPipeline<'e> {
+    patterns: PatternAutomaton<SyncTermRef>,
+    backward: Vec<Permutation<'static>>,
+    equivs: &'e [TermMap<'static>],
+    batch_size: usize,
+}
+
+// Converts the terms of a batch, converting subterms shared between them once. Consecutive terms
+// of an enumeration share most of their subterms.
+fn to_sync(batch: &[TermRef]) -> Vec<SyncTermRef> {
+    fn convert(term: &TermRef, converted: &mut HashMap<*const (), SyncTermRef>) -> SyncTermRef {
+        if let Some(sync) = converted.get(&term.address()) {
+            return sync.clone();
+        }
+        let sync = match term.children() {
+            Some((left, right)) => SyncTermRef::new(SyncTerm::Operation(
+                convert(left, converted),
+                convert(right, converted),
+            )),
+            None => SyncTermRef::new(SyncTerm::Variable),
+        };
+        converted.insert(term.address(), sync.clone());
+        sync
+    }
+
+    // the addresses stay valid because the batch keeps all terms alive
+    let mut converted = HashMap::new();
+    batch
+        .iter()
+        .map(|term| convert(term, &mut converted))
+        .collect()
+}
+
+impl<'e> MatchPipeline<'e> {
+    pub fn new(equivs: &'e [TermMap<'static>], batch_size: usize) -> Self {
+        assert!(batch_size > 0, "batches must not be empty");
+        MatchPipeline {
+            patterns: PatternAutomaton::new(
+                equivs
+                    .iter()
+                    .map(|equiv| SyncTerm::from_term(equiv.source()))
+                    .collect(),
+            ),
+            backward: equivs.iter().map(|equiv| equiv.perm().inverse()).collect(),
+            equivs,
+            batch_size,
+        }
+    }
+
+    /// Calls `on_term` for every term with the equivalences from applying the patterns at all
+    /// their occurrences, ordered by position in pre-order and then by pattern.
+    pub fn run<I, F>(&self, terms: I, mut on_term: F)
+    where
+        I: IntoIterator<Item = TermRef>,
+        F: FnMut(&TermRef, Vec<TermMap<'static>>),
+    {
+        // the equivalences and terms hold `Rc`s, so only the patterns, the permutations of the
+        // equivalences and converted terms go to the other threads
+        let (patterns, backward) = (&self.patterns, &self.backward);
+        let mut terms = terms.into_iter().fuse().peekable();
+        while terms.peek().is_some() {
+            let batch: Vec<_> = terms.by_ref().take(self.batch_size).collect();
+            let rewrites: Vec<Vec<(usize, Position, Permutation<'static>)>> = to_sync(&batch)
+                .par_iter()
+                .map(|term| {
+                    patterns
+                        .matches(term)
+                        .into_iter()
+                        .map(|(id, matched)| {
+                            let perm = term.rewrite_perm(&matched, &backward[id]);
+                            (id, matched.position, perm)
+                        })
+                        .collect()
+                })
+                .collect();
+
+            for (term, rewrites) in batch.iter().zip(rewrites) {
+                let equivs = rewrites
+                    .into_iter()
+                    .map(|(id, position, perm)| {
+                        term.substitute_with_perm(&position, &self.equivs[id], perm)
+                    })
+                    .collect();
+                on_term(term, equivs);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{indexing::PatternAutomaton, iter::TermIterator, lterm};
+
+    #[test]
+    fn pipeline() {
+        let equivs = [
+            lterm!((a * b) * c).map_to(lterm!(a * (b * c))),
+            lterm!((a * b) * (c * d)).map_to(lterm!((a * c) * (b * d))),
+        ];
+        let patterns = PatternAutomaton::new(equivs.iter().map(|e| e.source().clone()).collect());
+        let pipeline = MatchPipeline::new(&equivs, 7);
+
+        let mut expected = Vec::new();
+        for term in TermIterator::new(7) {
+            for (id, matched) in patterns.matches(&term) {
+                expected.push(term.substitute_match(&matched, &equivs[id]));
+            }
+        }
+        let mut found = Vec::new();
+        pipeline.run(TermIterator::new(7), |_, equivs| found.extend(equivs));
+
+        assert_eq!(found.len(), expected.len());
+        for (found, expected) in found.iter().zip(&expected) {
+            assert!(found.source() == expected.source());
+            assert!(found.target() == expected.target());
+            assert_eq!(found.perm(), expected.perm());
+        }
+    }
+}
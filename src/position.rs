@@ -0,0 +1,239 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::codec::{ByteReader, ByteWriter, DecodeError};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+pub enum Direction {
+    Left,
+    Right,
+}
+
+/// A path from the root of a term, as a sequence of left/right steps.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Serialize, Deserialize)]
+pub struct Position(Vec<Direction>);
+
+impl Position {
+    pub fn root() -> Self {
+        Position(Vec::new())
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn steps(&self) -> &[Direction] {
+        &self.0
+    }
+
+    pub fn push(&mut self, direction: Direction) {
+        self.0.push(direction);
+    }
+
+    pub fn pop(&mut self) -> Option<Direction> {
+        self.0.pop()
+    }
+
+    pub fn child(&self, direction: Direction) -> Position {
+        let mut child = self.clone();
+        child.push(direction);
+        child
+    }
+
+    pub fn left(&self) -> Position {
+        self.child(Direction::Left)
+    }
+
+    pub fn right(&self) -> Position {
+        self.child(Direction::Right)
+    }
+
+    /// Writes the steps as bits, `true` for right.
+    pub fn encode_into(&self, writer: &mut ByteWriter) {
+        let bits: Vec<_> = self
+            .0
+            .iter()
+            .map(|step| *step == Direction::Right)
+            .collect();
+        writer.bits(&bits);
+    }
+
+    pub fn decode_from(reader: &mut ByteReader) -> Result<Self, DecodeError> {
+        Ok(Position(
+            reader
+                .bits()?
+                .into_iter()
+                .map(|right| {
+                    if right {
+                        Direction::Right
+                    } else {
+                        Direction::Left
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    pub fn parent(&self) -> Option<Position> {
+        let mut parent = self.clone();
+        parent.pop().map(|_| parent)
+    }
+
+    pub fn is_prefix_of(&self, other: &Position) -> bool {
+        other.0.starts_with(&self.0)
+    }
+
+    /// The position of `self` relative to `prefix`, if `prefix` is a prefix of `self`.
+    pub fn strip_prefix(&self, prefix: &Position) -> Option<Position> {
+        self.0
+            .strip_prefix(prefix.0.as_slice())
+            .map(|rest| Position(rest.to_vec()))
+    }
+
+    pub fn concat(&self, other: &Position) -> Position {
+        Position([self.0.as_slice(), other.0.as_slice()].concat())
+    }
+}
+
+/// A set of positions, e.g. all occurrences of a pattern in a term. Iterates in pre-order, which
+/// is the derived order on `Position`.
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct Occurrences(Vec<Position>);
+
+impl Occurrences {
+    pub fn new() -> Self {
+        Occurrences(Vec::new())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn contains(&self, position: &Position) -> bool {
+        self.0.binary_search(position).is_ok()
+    }
+
+    pub fn positions(&self) -> &[Position] {
+        &self.0
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Position> {
+        self.0.iter()
+    }
+}
+
+impl FromIterator<Position> for Occurrences {
+    fn from_iter<I: IntoIterator<Item = Position>>(iter: I) -> Self {
+        let mut positions: Vec<_> = iter.into_iter().collect();
+        positions.sort();
+        positions.dedup();
+        Occurrences(positions)
+    }
+}
+
+impl<'o> IntoIterator for &'o Occurrences {
+    type Item = &'o Position;
+    type IntoIter = std::slice::Iter<'o, Position>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl From<Vec<Direction>> for Position {
+    fn from(value: Vec<Direction>) -> Self {
+        Position(value)
+    }
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "ε");
+        }
+        for direction in &self.0 {
+            match direction {
+                Direction::Left => write!(f, "L")?,
+                Direction::Right => write!(f, "R")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Position[{}]", self)
+    }
+}
+
+impl FromStr for Position {
+    type Err = char;
+
+    /// Parses the output of `Display`, i.e. `ε` or a word over `L` and `R`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "ε" {
+            return Ok(Position::root());
+        }
+        s.chars()
+            .map(|c| match c {
+                'L' => Ok(Direction::Left),
+                'R' => Ok(Direction::Right),
+                c => Err(c),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn position() {
+        let position = Position::root().left().right().right();
+        assert_eq!(position.to_string(), "LRR");
+        assert_eq!("LRR".parse(), Ok(position.clone()));
+        assert_eq!("ε".parse(), Ok(Position::root()));
+        assert_eq!("LX".parse::<Position>(), Err('X'));
+
+        let mut writer = ByteWriter::new();
+        position.encode_into(&mut writer);
+        let bytes = writer.into_bytes();
+        assert_eq!(
+            Position::decode_from(&mut ByteReader::new(&bytes)),
+            Ok(position.clone())
+        );
+
+        let prefix = Position::root().left();
+        assert!(prefix.is_prefix_of(&position));
+        assert!(!position.is_prefix_of(&prefix));
+        let rest = position.strip_prefix(&prefix).unwrap();
+        assert_eq!(rest.to_string(), "RR");
+        assert_eq!(prefix.concat(&rest), position);
+        assert_eq!(position.parent().unwrap().to_string(), "LR");
+        assert_eq!(Position::root().parent(), None);
+
+        let occurrences: Occurrences = ["R", "L", "ε", "L"]
+            .into_iter()
+            .map(|position| position.parse().unwrap())
+            .collect();
+        let positions: Vec<_> = occurrences.iter().map(|p| p.to_string()).collect();
+        assert_eq!(positions, ["ε", "L", "R"]);
+        assert!(occurrences.contains(&prefix));
+    }
+}
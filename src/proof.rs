@@ -0,0 +1,118 @@
+use crate::{
+    codec::{ByteReader, ByteWriter, DecodeError},
+    maps::TermMap,
+    position::Position,
+    rewrite::{RewriteSystem, RuleId},
+    term::{Term, TermPointer, TermRef},
+};
+
+/// A rule applied at a position, with the subterms bound to the leaves of its source in order.
+#[derive(Clone, Debug)]
+pub struct ProofStep {
+    pub rule: RuleId,
+    pub position: Position,
+    pub bindings: Vec<TermRef>,
+}
+
+/// Rewrite steps starting from `term`, which can be checked against the rules without trusting
+/// whoever found them.
+#[derive(Clone, Debug)]
+pub struct Proof {
+    pub term: TermRef,
+    pub steps: Vec<ProofStep>,
+}
+
+impl Proof {
+    pub fn new(term: TermRef) -> Self {
+        Proof {
+            term,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Applies the steps to `term`, checking that each rule matches at its position with the
+    /// recorded bindings. Returns the map from `term` to the result, or the index of the first
+    /// step that doesn't apply.
+    pub fn replay(&self, system: &RewriteSystem) -> Result<TermMap<'static>, usize> {
+        let mut map = self.term.identity_map();
+        for (index, step) in self.steps.iter().enumerate() {
+            let current = map.target().clone();
+            let rule = (step.rule < system.len())
+                .then(|| system.rule(step.rule))
+                .ok_or(index)?;
+            let redex = current.subterm_at(&step.position).ok_or(index)?;
+            if rule.source().instance_bindings(redex).as_ref() != Some(&step.bindings) {
+                return Err(index);
+            }
+            map *= current.substitute_at(&step.position, rule);
+        }
+        Ok(map)
+    }
+
+    /// The certificate: the term, then every step as rule, position and bindings.
+    #[allow(dead_code)]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = ByteWriter::new();
+        self.term.encode_into(&mut writer);
+        writer.u32(self.steps.len() as u32);
+        for step in &self.steps {
+            writer.u32(step.rule as u32);
+            step.position.encode_into(&mut writer);
+            writer.u32(step.bindings.len() as u32);
+            for binding in &step.bindings {
+                binding.encode_into(&mut writer);
+            }
+        }
+        writer.into_bytes()
+    }
+
+    #[allow(dead_code)]
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = ByteReader::new(bytes);
+        let mut proof = Proof::new(Term::decode_from(&mut reader)?);
+        for _ in 0..reader.u32()? {
+            let rule = reader.u32()? as RuleId;
+            let position = Position::decode_from(&mut reader)?;
+            let bindings = (0..reader.u32()?)
+                .map(|_| Term::decode_from(&mut reader))
+                .collect::<Result<_, _>>()?;
+            proof.steps.push(ProofStep {
+                rule,
+                position,
+                bindings,
+            });
+        }
+        reader.finish()?;
+        Ok(proof)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lterm, rewrite::Strategy, term};
+
+    #[test]
+    fn replay() {
+        let system = RewriteSystem::new(vec![lterm!((a * b) * c).map_to(lterm!(c * (a * b)))]);
+        let term = term!(((a * b) * c) * d);
+        let normalized = system.normalize(&term, Strategy::Innermost);
+        let proof = &normalized.proof;
+        assert_eq!(proof.steps.len(), normalized.rounds);
+
+        let replayed = Proof::decode(&proof.encode())
+            .unwrap()
+            .replay(&system)
+            .unwrap();
+        assert_eq!(replayed.target(), normalized.normal_form());
+        assert_eq!(replayed.perm(), normalized.map.perm());
+
+        // a step with bindings that don't match is rejected
+        let mut forged = proof.clone();
+        forged.steps[1].bindings.swap(0, 1);
+        assert_eq!(forged.replay(&system).unwrap_err(), 1);
+        let mut forged = proof.clone();
+        forged.steps[0].rule = 1;
+        assert_eq!(forged.replay(&system).unwrap_err(), 0);
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+
+use crate::{
+    maps::{NodeIndex, TermMap},
+    proof::{Proof, ProofStep},
+    rewrite::{RewriteSystem, RuleId, leaf_places},
+    term::{TermPointer, TermRef},
+};
+
+/// The equivalences as rules in both directions, rule `i + equivs.len()` is equivalence `i`
+/// backward. The proofs of `prove` refer to these rules.
+pub fn axioms(equivs: &[TermMap<'static>]) -> RewriteSystem {
+    let mut rules = equivs.to_vec();
+    rules.extend(equivs.iter().map(|equiv| equiv.backward()));
+    RewriteSystem::new(rules)
+}
+
+// the maps reached from one side of the goal, with the step that reached each from its parent
+struct Side {
+    reached: Vec<(TermMap<'static>, Option<(usize, ProofStep)>)>,
+    index: HashMap<(TermRef, Vec<NodeIndex>), usize>,
+    frontier: std::ops::Range<usize>,
+}
+
+impl Side {
+    fn new(map: TermMap<'static>) -> Self {
+        Side {
+            index: HashMap::from([(leaf_places(&map), 0)]),
+            reached: vec![(map, None)],
+            frontier: 0..1,
+        }
+    }
+
+    fn expand(&mut self, system: &RewriteSystem) {
+        let end = self.reached.len();
+        for parent in self.frontier.clone() {
+            let map = self.reached[parent].0.clone();
+            for (id, matched) in system.all_redexes(map.target()) {
+                let next = &map * map.target().substitute_match(&matched, system.rule(id));
+                let key = leaf_places(&next);
+                if self.index.contains_key(&key) {
+                    continue;
+                }
+                self.index.insert(key, self.reached.len());
+                let step = ProofStep {
+                    rule: id,
+                    position: matched.position,
+                    bindings: matched.bindings,
+                };
+                self.reached.push((next, Some((parent, step))));
+            }
+        }
+        self.frontier = end..self.reached.len();
+    }
+
+    // the steps from the start of the side to `index`
+    fn path(&self, mut index: usize) -> Vec<(usize, ProofStep)> {
+        let mut path = Vec::new();
+        while let Some((parent, step)) = &self.reached[index].1 {
+            path.push((index, step.clone()));
+            index = *parent;
+        }
+        path.reverse();
+        path
+    }
+}
+
+/// A shortest derivation of `goal` from the equivalences within `max_steps` steps, found by
+/// applying them at all positions in both directions, from the source and back from the target
+/// of `goal`, until the two meet. The leaves have to correspond as in `goal`, not only the
+/// shapes. Replays with the rules of `axioms`.
+pub fn prove(
+    equivs: &[TermMap<'static>],
+    goal: &TermMap<'static>,
+    max_steps: usize,
+) -> Option<Proof> {
+    let system = axioms(equivs);
+    // both sides hold maps from the source of `goal`, the backward side through `goal`
+    let mut forward = Side::new(goal.source().identity_map());
+    let mut backward = Side::new(goal.clone());
+
+    for steps in 0..=max_steps {
+        if steps > 0 {
+            let side = if forward.frontier.len() <= backward.frontier.len() {
+                &mut forward
+            } else {
+                &mut backward
+            };
+            side.expand(&system);
+        }
+        // only maps not reached before can meet the other side first
+        let meeting = forward.frontier.clone().find_map(|index| {
+            let other = backward
+                .index
+                .get(&leaf_places(&forward.reached[index].0))?;
+            Some((index, *other))
+        });
+        let meeting = meeting.or_else(|| {
+            backward.frontier.clone().find_map(|index| {
+                let other = forward
+                    .index
+                    .get(&leaf_places(&backward.reached[index].0))?;
+                Some((*other, index))
+            })
+        });
+        let Some((forward_end, backward_end)) = meeting else {
+            continue;
+        };
+
+        let mut proof = Proof::new(goal.source().clone());
+        proof
+            .steps
+            .extend(forward.path(forward_end).into_iter().map(|(_, step)| step));
+        // the backward steps undone in reverse, each from the term it reached
+        for (index, step) in backward.path(backward_end).into_iter().rev() {
+            let rule: RuleId = (step.rule + equivs.len()) % system.len();
+            let term = backward.reached[index].0.target();
+            let redex = term.subterm_at(&step.position).unwrap();
+            proof.steps.push(ProofStep {
+                rule,
+                bindings: system.rule(rule).source().instance_bindings(redex).unwrap(),
+                position: step.position,
+            });
+        }
+        return Some(proof);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lterm;
+
+    #[test]
+    fn prove() {
+        let equivs = [
+            lterm!((a * b) * c).map_to(lterm!(a * (b * c))),
+            lterm!(a * b).map_to(lterm!(b * a)),
+        ];
+        let system = axioms(&equivs);
+
+        let goal = lterm!(((a * b) * c) * d).map_to(lterm!(d * (c * (b * a))));
+        let proof = super::prove(&equivs, &goal, 6).unwrap();
+        let map = proof.replay(&system).unwrap();
+        assert_eq!(map.target(), goal.target());
+        assert_eq!(map.perm(), goal.perm());
+
+        // commuting at the root, then at R and at RR
+        assert!(super::prove(&equivs, &goal, 2).is_none());
+        assert_eq!(proof.steps.len(), 3);
+        let shortest = (0..=6)
+            .find(|&steps| super::prove(&equivs, &goal, steps).is_some())
+            .unwrap();
+        assert_eq!(proof.steps.len(), shortest);
+
+        // associativity alone keeps the leaves in order
+        let trivial = lterm!((a * b) * c).map_to(lterm!(a * (b * c)));
+        assert_eq!(
+            super::prove(&equivs[..1], &trivial, 1).unwrap().steps.len(),
+            1
+        );
+        let swapped = lterm!((a * b) * c).map_to(lterm!(b * (a * c)));
+        assert!(super::prove(&equivs[..1], &swapped, 4).is_none());
+    }
+}
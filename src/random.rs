@@ -0,0 +1,200 @@
+use std::rc::Rc;
+
+use rand::{Rng, RngExt, SeedableRng, rngs::StdRng};
+
+use crate::term::{Term, TermRef};
+
+/// Distribution of the shapes produced by `Term::random_with`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ShapeDistribution {
+    /// Every binary term with the given number of leaves is equally likely (Rémy's algorithm).
+    #[default]
+    Uniform,
+    /// The leaves of every node are split close to evenly, giving shallow terms.
+    Balanced,
+    /// The leaves of every node are split close to one end, giving deep, comb like terms.
+    Skewed,
+}
+
+impl Term {
+    /// A term with `leaves` leaves drawn uniformly at random.
+    pub fn random<R: Rng + ?Sized>(leaves: usize, rng: &mut R) -> TermRef {
+        Term::random_with(leaves, ShapeDistribution::Uniform, rng)
+    }
+
+    pub fn random_with<R: Rng + ?Sized>(
+        leaves: usize,
+        distribution: ShapeDistribution,
+        rng: &mut R,
+    ) -> TermRef {
+        assert!(leaves > 0, "terms have at least one leaf");
+        match distribution {
+            ShapeDistribution::Uniform => remy(leaves, rng),
+            ShapeDistribution::Balanced => random_splits(leaves, rng, &mut |leaves, rng| {
+                // binomially distributed around the middle
+                1 + (0..leaves - 2).filter(|_| rng.random::<bool>()).count()
+            }),
+            ShapeDistribution::Skewed => random_splits(leaves, rng, &mut |leaves, rng| {
+                // geometrically distributed distance from a random end
+                let mut distance = 1;
+                while distance < leaves - 1 && rng.random::<bool>() {
+                    distance += 1;
+                }
+                if rng.random() {
+                    distance
+                } else {
+                    leaves - distance
+                }
+            }),
+        }
+    }
+}
+
+/// Endless iterator over independent uniformly random terms with a number of leaves. The same
+/// seed gives the same terms.
+pub struct RandomTermIterator {
+    leaves: usize,
+    rng: StdRng,
+}
+
+impl RandomTermIterator {
+    pub fn new(leaves: usize, seed: u64) -> Self {
+        assert!(leaves > 0, "terms have at least one leaf");
+        RandomTermIterator {
+            leaves,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Iterator for RandomTermIterator {
+    type Item = TermRef;
+
+    fn next(&mut self) -> Option<TermRef> {
+        Some(remy(self.leaves, &mut self.rng))
+    }
+}
+
+// Grows the term by repeatedly picking a uniformly random node and hanging it together with a new
+// leaf below a new operation, on a random side. Every term is reached equally often.
+fn remy<R: Rng + ?Sized>(leaves: usize, rng: &mut R) -> TermRef {
+    let mut children: Vec<Option<(usize, usize)>> = vec![None];
+    let mut parent: Vec<Option<usize>> = vec![None];
+    let mut root = 0;
+
+    for _ in 1..leaves {
+        let node = rng.random_range(0..children.len());
+        let leaf = children.len();
+        let operation = leaf + 1;
+
+        children.push(None);
+        parent.push(Some(operation));
+        children.push(Some(if rng.random() {
+            (node, leaf)
+        } else {
+            (leaf, node)
+        }));
+        parent.push(parent[node]);
+
+        match parent[node] {
+            None => root = operation,
+            Some(above) => {
+                let (left, right) = children[above].as_mut().unwrap();
+                if *left == node {
+                    *left = operation;
+                } else {
+                    *right = operation;
+                }
+            }
+        }
+        parent[node] = Some(operation);
+    }
+
+    let mut results: Vec<TermRef> = Vec::new();
+    let mut stack = vec![(root, false)];
+    while let Some((node, expanded)) = stack.pop() {
+        match children[node] {
+            None => results.push(Rc::new(Term::Variable)),
+            Some((left, right)) if !expanded => {
+                stack.push((node, true));
+                stack.push((right, false));
+                stack.push((left, false));
+            }
+            Some(_) => {
+                let right = results.pop().unwrap();
+                let left = results.pop().unwrap();
+                results.push(Term::operation(left, right));
+            }
+        }
+    }
+    results.pop().unwrap()
+}
+
+// `split` chooses the number of leaves of the left child, between 1 and `leaves - 1`
+fn random_splits<R: Rng + ?Sized, S: FnMut(usize, &mut R) -> usize>(
+    leaves: usize,
+    rng: &mut R,
+    split: &mut S,
+) -> TermRef {
+    let mut results: Vec<TermRef> = Vec::new();
+    let mut stack = vec![(leaves, false)];
+    while let Some((leaves, expanded)) = stack.pop() {
+        if leaves == 1 {
+            results.push(Rc::new(Term::Variable));
+        } else if !expanded {
+            let left = split(leaves, rng);
+            stack.push((leaves, true));
+            stack.push((leaves - left, false));
+            stack.push((left, false));
+        } else {
+            let right = results.pop().unwrap();
+            let left = results.pop().unwrap();
+            results.push(Term::operation(left, right));
+        }
+    }
+    results.pop().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn random_terms() {
+        let mut rng = StdRng::seed_from_u64(1871);
+
+        // the 5 terms with 4 leaves should appear about 1000 times each
+        let mut counts = HashMap::new();
+        for _ in 0..5000 {
+            *counts.entry(Term::random(4, &mut rng)).or_insert(0) += 1;
+        }
+        assert_eq!(counts.len(), 5);
+        assert!(counts.values().all(|count| (850..1150).contains(count)));
+
+        let depth = |distribution, rng: &mut StdRng| {
+            (0..20)
+                .map(|_| {
+                    let term = Term::random_with(256, distribution, rng);
+                    assert_eq!(term.leaves(), 256);
+                    term.depth()
+                })
+                .sum::<usize>()
+        };
+        let balanced = depth(ShapeDistribution::Balanced, &mut rng);
+        let uniform = depth(ShapeDistribution::Uniform, &mut rng);
+        let skewed = depth(ShapeDistribution::Skewed, &mut rng);
+        assert!(balanced < uniform && uniform < skewed);
+    }
+
+    #[test]
+    fn random_term_iterator() {
+        let terms: Vec<_> = RandomTermIterator::new(7, 1931).take(50).collect();
+        assert!(terms.iter().all(|term| term.leaves() == 7));
+        let again: Vec<_> = RandomTermIterator::new(7, 1931).take(50).collect();
+        assert_eq!(terms, again);
+        let other: Vec<_> = RandomTermIterator::new(7, 1932).take(50).collect();
+        assert_ne!(terms, other);
+    }
+}
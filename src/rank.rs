@@ -0,0 +1,111 @@
+use std::rc::Rc;
+
+use crate::{
+    bidag::BinaryChildren,
+    term::{Term, TermRef},
+};
+
+// counts[m] is the number of shapes with m leaves, i.e. the Catalan number C(m - 1)
+fn checked_shape_counts(leaves: usize) -> Option<Vec<u128>> {
+    let mut counts: Vec<u128> = vec![0, 1];
+    for n in 2..=leaves {
+        let count = (1..n)
+            .map(|k| counts[k].checked_mul(counts[n - k]))
+            .try_fold(0u128, |sum, product| sum.checked_add(product?))?;
+        counts.push(count);
+    }
+    Some(counts)
+}
+
+fn shape_counts(leaves: usize) -> Vec<u128> {
+    checked_shape_counts(leaves).expect("number of shapes does not fit into u128")
+}
+
+impl Term {
+    /// Number of terms with `leaves` leaves, panics if it does not fit into `u128`, i.e. for more
+    /// than 70 leaves.
+    pub fn shape_count(leaves: usize) -> u128 {
+        shape_counts(leaves)[leaves]
+    }
+
+    /// Like `shape_count`, but `None` instead of panicking.
+    pub fn checked_shape_count(leaves: usize) -> Option<u128> {
+        Some(checked_shape_counts(leaves)?[leaves])
+    }
+
+    /// Index of the term in the order of `TermIterator`, in `0..Term::shape_count(leaves)`.
+    pub fn rank(&self) -> u128 {
+        let counts = shape_counts(self.leaves());
+        let (_, rank) = self.reduce(
+            &mut |_, (left_leaves, left_rank), (right_leaves, right_rank)| {
+                // all terms with fewer leaves on the left come first, then the left subterm varies
+                // fastest
+                let preceding: u128 = (1..left_leaves)
+                    .map(|k| counts[k] * counts[left_leaves + right_leaves - k])
+                    .sum();
+                (
+                    left_leaves + right_leaves,
+                    preceding + right_rank * counts[left_leaves] + left_rank,
+                )
+            },
+            &mut |_| (1, 0),
+        );
+        rank
+    }
+
+    /// Inverse of `rank`. Panics if `rank` is not below `Term::shape_count(leaves)`.
+    pub fn unrank(leaves: usize, rank: u128) -> TermRef {
+        let counts = shape_counts(leaves);
+        assert!(rank < counts[leaves], "rank out of range");
+
+        let mut results: Vec<TermRef> = Vec::new();
+        let mut stack = vec![(leaves, rank, false)];
+        while let Some((leaves, mut rank, expanded)) = stack.pop() {
+            if leaves == 1 {
+                results.push(Rc::new(Term::Variable));
+            } else if !expanded {
+                let mut left_leaves = 1;
+                while rank >= counts[left_leaves] * counts[leaves - left_leaves] {
+                    rank -= counts[left_leaves] * counts[leaves - left_leaves];
+                    left_leaves += 1;
+                }
+                stack.push((leaves, 0, true));
+                stack.push((leaves - left_leaves, rank / counts[left_leaves], false));
+                stack.push((left_leaves, rank % counts[left_leaves], false));
+            } else {
+                let right = results.pop().unwrap();
+                let left = results.pop().unwrap();
+                results.push(Term::operation(left, right));
+            }
+        }
+        results.pop().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{RngExt, SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::iter::TermIterator;
+
+    #[test]
+    fn rank_matches_enumeration() {
+        for leaves in 1..=9 {
+            let mut count = 0;
+            for (index, term) in TermIterator::new(leaves).enumerate() {
+                assert_eq!(term.rank(), index as u128);
+                assert!(Term::unrank(leaves, index as u128) == term);
+                count += 1;
+            }
+            assert_eq!(Term::shape_count(leaves), count);
+        }
+
+        let mut rng = StdRng::seed_from_u64(1872);
+        let leaves = 70;
+        for _ in 0..100 {
+            let rank = rng.random_range(0..Term::shape_count(leaves));
+            assert_eq!(Term::unrank(leaves, rank).rank(), rank);
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    hash::Hash,
+    rc::Rc,
+};
+
+use crate::{
+    bidag::BinaryChildren,
+    labeled::{LabeledTerm, LabeledTermRef},
+    maps::{ComposeError, NodeIndex, TermMap},
+    position::{Direction, Position},
+    term::{TermPointer, TermRef},
+};
+
+/// An equation between two terms whose leaves are variables, like a `TermMap`, but a variable
+/// may occur any number of times on either side. Repeated variables and variables missing on one
+/// side express rules like `x * x = x` or `x * y = x`, which duplicate or delete leaves. The
+/// variables are numbered in the order they first occur in the source and then the target.
+#[derive(Clone, PartialEq, Eq)]
+pub struct TermRelation {
+    source: LabeledTermRef<NodeIndex>,
+    target: LabeledTermRef<NodeIndex>,
+}
+
+// the term with the labels replaced by their numbers in `numbers`, adding the new ones
+fn renumber<T: Clone + Hash + Eq>(
+    term: &LabeledTermRef<T>,
+    numbers: &mut HashMap<T, NodeIndex>,
+) -> LabeledTermRef<NodeIndex> {
+    term.map(&mut |leaf| {
+        let next = numbers.len() as NodeIndex;
+        *numbers.entry(leaf.label().unwrap().clone()).or_insert(next)
+    })
+}
+
+// the term with `offset` added to every variable
+fn shift(term: &LabeledTermRef<NodeIndex>, offset: NodeIndex) -> LabeledTermRef<NodeIndex> {
+    term.map(&mut |leaf| leaf.label().unwrap() + offset)
+}
+
+fn subterm_at<'t>(
+    term: &'t LabeledTermRef<NodeIndex>,
+    position: &Position,
+) -> Option<&'t LabeledTermRef<NodeIndex>> {
+    position.steps().iter().try_fold(term, |node, direction| {
+        node.children().map(|(left, right)| match direction {
+            Direction::Left => left,
+            Direction::Right => right,
+        })
+    })
+}
+
+fn graft(
+    term: &LabeledTermRef<NodeIndex>,
+    steps: &[Direction],
+    subterm: LabeledTermRef<NodeIndex>,
+) -> LabeledTermRef<NodeIndex> {
+    let Some((direction, steps)) = steps.split_first() else {
+        return subterm;
+    };
+    let (left, right) = term.children().expect("position is not in the term");
+    Rc::new(match direction {
+        Direction::Left => LabeledTerm::Operation(graft(left, steps, subterm), right.clone()),
+        Direction::Right => LabeledTerm::Operation(left.clone(), graft(right, steps, subterm)),
+    })
+}
+
+impl TermRelation {
+    /// Relates the leaves of the terms that carry the same label.
+    pub fn new<T: Clone + Hash + Eq>(
+        source: &LabeledTermRef<T>,
+        target: &LabeledTermRef<T>,
+    ) -> Self {
+        let mut numbers = HashMap::new();
+        TermRelation {
+            source: renumber(source, &mut numbers),
+            target: renumber(target, &mut numbers),
+        }
+    }
+
+    /// Relates every leaf of `term` to itself.
+    #[allow(dead_code)]
+    pub fn identity(term: &TermRef) -> Self {
+        TermMap::identity(term.clone()).into()
+    }
+
+    /// The number of distinct variables on both sides.
+    #[allow(dead_code)]
+    pub fn variables(&self) -> usize {
+        let mut labels = self.source.leaf_labels();
+        labels.extend(self.target.leaf_labels());
+        labels.into_iter().max().map_or(0, |&max| max as usize + 1)
+    }
+
+    /// The same equation read from right to left. Where a duplication was a function of the
+    /// source leaves, its backward identifies the source leaves of the duplicates, so it only
+    /// applies to terms with equal subterms there.
+    #[allow(dead_code)]
+    pub fn backward(&self) -> Self {
+        TermRelation::new(&self.target, &self.source)
+    }
+
+    /// The `TermMap` relating the same leaves, `None` unless every variable occurs exactly once
+    /// on each side.
+    pub fn to_map(&self) -> Option<TermMap<'static>> {
+        let (mut source, mut target) = (self.source.leaf_labels(), self.target.leaf_labels());
+        source.sort_unstable();
+        target.sort_unstable();
+        let distinct = source.windows(2).all(|pair| pair[0] != pair[1]);
+        (distinct && source == target).then(|| self.source.clone().map_to(self.target.clone()))
+    }
+
+    /// The relation from the source of `self` to the target of `rhs`, which requires the target
+    /// of `self` to be the source of `rhs`. Variables meeting at a leaf in between become one.
+    #[allow(dead_code)]
+    pub fn compose(&self, rhs: &TermRelation) -> Result<TermRelation, ComposeError<TermRef>> {
+        let (target, source) = (self.target.skeleton(), rhs.source.skeleton());
+        if target != source {
+            return Err(ComposeError { target, source });
+        }
+        let offset = self.variables() as NodeIndex;
+        let unifier = self
+            .target
+            .unify(&shift(&rhs.source, offset))
+            .expect("terms of the same shape with variable leaves unify");
+        Ok(TermRelation::new(
+            &unifier.apply(&self.source),
+            &unifier.apply(&shift(&rhs.target, offset)),
+        ))
+    }
+
+    /// Rewrites the subterm of the target at `position` with `rule`, giving the relation from
+    /// the source to the result. The leaves bound to a repeated variable of the rule's source
+    /// have to be related to the same leaves, otherwise the rule does not apply and the result
+    /// is `None`. Variables only in the rule's target become new variables.
+    #[allow(dead_code)]
+    pub fn substitute_at(&self, position: &Position, rule: &TermRelation) -> Option<TermRelation> {
+        let subterm = subterm_at(&self.target, position)?;
+        let offset = self.variables() as NodeIndex;
+        let bindings = shift(&rule.source, offset).match_onto(subterm)?;
+        let rewritten = bindings.apply(&shift(&rule.target, offset));
+        Some(TermRelation::new(
+            &self.source,
+            &graft(&self.target, position.steps(), rewritten),
+        ))
+    }
+}
+
+impl<P: TermPointer> From<TermMap<'_, P>> for TermRelation {
+    fn from(map: TermMap<'_, P>) -> Self {
+        let (source, target) = map.labeled();
+        TermRelation::new(&source, &target)
+    }
+}
+
+impl Debug for TermRelation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TermRelation[{}]", self)
+    }
+}
+
+impl Display for TermRelation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} = {}", self.source, self.target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lterm, term};
+
+    fn relation(source: LabeledTermRef<String>, target: LabeledTermRef<String>) -> TermRelation {
+        TermRelation::new(&source, &target)
+    }
+
+    #[test]
+    fn term_relation() {
+        let rotate = lterm!((a * b) * c).map_to(lterm!(a * (b * c)));
+        let relation_map = TermRelation::from(rotate.clone());
+        assert_eq!(relation_map.to_map().unwrap().perm(), rotate.perm());
+        assert_eq!(
+            relation_map.backward().to_map().unwrap().perm(),
+            rotate.backward().perm()
+        );
+
+        let idempotent = relation(lterm!(a * a), lterm!(a));
+        let project = relation(lterm!(a * b), lterm!(a));
+        assert!(idempotent.to_map().is_none());
+        assert_eq!(idempotent.backward().to_string(), "0 = 0 * 0");
+
+        // duplicating and merging again relates both copies of the leaf to it
+        let square = idempotent.backward().compose(&idempotent).unwrap();
+        assert_eq!(square.to_string(), "0 = 0");
+        // projecting after duplicating keeps one copy
+        let kept = idempotent.backward().compose(&project).unwrap();
+        assert_eq!(kept.to_string(), "0 = 0");
+        // a projection followed by its backward forgets the deleted leaf
+        let forgotten = project.compose(&project.backward()).unwrap();
+        assert_eq!(forgotten.to_string(), "0 * 1 = 0 * 2");
+        assert!(project.compose(&project).is_err());
+
+        // rewriting only applies where the repeated variable meets equal subterms
+        let term = relation(lterm!(a * (b * b)), lterm!(a * (b * b)));
+        assert_eq!(
+            term.substitute_at(&Position::root().right(), &idempotent)
+                .unwrap()
+                .to_string(),
+            "0 * (1 * 1) = 0 * 1"
+        );
+        assert!(term.substitute_at(&Position::root(), &idempotent).is_none());
+        let identity = TermRelation::identity(&term!((a * b) * c));
+        assert_eq!(
+            identity
+                .substitute_at(&Position::root().left(), &project)
+                .unwrap()
+                .to_string(),
+            "(0 * 1) * 2 = 0 * 2"
+        );
+        assert_eq!(
+            identity
+                .substitute_at(&Position::root(), &project.backward())
+                .unwrap()
+                .to_string(),
+            "(0 * 1) * 2 = ((0 * 1) * 2) * 3"
+        );
+    }
+}
@@ -0,0 +1,558 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    bidag::BinaryChildren,
+    completion::{CriticalPair, critical_pairs},
+    indexing::Match,
+    maps::{NodeIndex, TermMap},
+    ordering::{Kbo, Lpo, Status, TermOrdering},
+    patindex::PatternIndex,
+    proof::{Proof, ProofStep},
+    term::TermRef,
+};
+
+pub type RuleId = usize;
+
+/// Which redex `RewriteSystem::normalize` rewrites next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// The leftmost of the redexes closest to the root.
+    #[allow(dead_code)]
+    Outermost,
+    /// The leftmost of the redexes without another redex below them.
+    Innermost,
+    /// All redexes closest to the root at once. They are disjoint, so the order doesn't matter.
+    #[allow(dead_code)]
+    ParallelOutermost,
+}
+
+/// The shape reached by `map` and where the leaves of its source went, which identifies maps
+/// from the same source.
+pub fn leaf_places(map: &TermMap<'static>) -> (TermRef, Vec<NodeIndex>) {
+    let leaves = map.source().leaves() as NodeIndex;
+    let places = (0..leaves).map(|leaf| map.perm().get(leaf)).collect();
+    (map.target().clone(), places)
+}
+
+/// Two terms rewritten to the same shape.
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct Join {
+    pub meeting: TermRef,
+    pub left: Proof,
+    pub right: Proof,
+}
+
+/// A term rewritten to normal form, with the rules applied on the way.
+pub struct Normalization {
+    /// The leaf correspondence between the term and its normal form.
+    pub map: TermMap<'static>,
+    /// The applied rules with the positions and bindings of their redexes, in order.
+    #[allow(dead_code)]
+    pub proof: Proof,
+    /// How often the redexes were searched, the number of steps unless they were contracted in
+    /// parallel.
+    #[allow(dead_code)]
+    pub rounds: usize,
+}
+
+impl Normalization {
+    #[allow(dead_code)]
+    pub fn normal_form(&self) -> &TermRef {
+        self.map.target()
+    }
+}
+
+/// Oriented rules, each rewriting instances of the source of a `TermMap` to the corresponding
+/// instances of its target. Nothing checks that rewriting terminates.
+pub struct RewriteSystem {
+    rules: Vec<TermMap<'static>>,
+    patterns: PatternIndex,
+}
+
+impl RewriteSystem {
+    pub fn new(rules: Vec<TermMap<'static>>) -> Self {
+        // a single leaf would be a redex everywhere, so rewriting could never stop
+        assert!(
+            rules.iter().all(|rule| !rule.source().is_leaf()),
+            "rule rewrites a single leaf"
+        );
+        let mut patterns = PatternIndex::new();
+        for rule in &rules {
+            patterns.insert(rule.source().clone());
+        }
+        RewriteSystem { rules, patterns }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn rule(&self, id: RuleId) -> &TermMap<'static> {
+        &self.rules[id]
+    }
+
+    pub fn rules(&self) -> &[TermMap<'static>] {
+        &self.rules
+    }
+
+    /// The redexes `strategy` contracts next in `term`, with the rules that apply there, in
+    /// pre-order. If several rules apply at the same position, the first one wins. Empty for
+    /// normal forms.
+    pub fn redexes(&self, term: &TermRef, strategy: Strategy) -> Vec<(RuleId, Match)> {
+        match strategy {
+            Strategy::Outermost => self.patterns.leftmost_outermost(term).into_iter().collect(),
+            Strategy::Innermost => self.patterns.leftmost_innermost(term).into_iter().collect(),
+            Strategy::ParallelOutermost => self.patterns.outermost_matches(term),
+        }
+    }
+
+    /// Every redex of `term` with every rule that applies there, ordered by position in pre-order
+    /// and then by rule.
+    pub fn all_redexes(&self, term: &TermRef) -> Vec<(RuleId, Match)> {
+        self.patterns.matches(term)
+    }
+
+    /// The rules that don't rewrite to a smaller term under `ordering`. If there are none,
+    /// rewriting terminates, since every step goes down in a well-founded ordering.
+    pub fn violations(&self, ordering: &(impl TermOrdering + ?Sized)) -> Vec<RuleId> {
+        (0..self.rules.len())
+            .filter(|&id| {
+                let (source, target) = self.rules[id].labeled();
+                !ordering.greater(&source, &target)
+            })
+            .collect()
+    }
+
+    /// Tries KBO with unit weights and LPO, each with both statuses. If none of them orders all
+    /// rules, the violations of the one that comes closest.
+    #[allow(dead_code)]
+    pub fn check_termination(&self) -> Result<(), Vec<RuleId>> {
+        let mut orderings: Vec<Box<dyn TermOrdering>> = Vec::new();
+        for status in [Status::LeftToRight, Status::RightToLeft] {
+            orderings.push(Box::new(Kbo::new(1, 1, status)));
+            orderings.push(Box::new(Lpo { status }));
+        }
+        let violations = orderings
+            .iter()
+            .map(|ordering| self.violations(ordering.as_ref()))
+            .min_by_key(|violations| violations.len())
+            .unwrap();
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// Rewrites `term` until no rule applies anymore. Loops forever if the rules don't
+    /// terminate, which `check_termination` can rule out.
+    pub fn normalize(&self, term: &TermRef, strategy: Strategy) -> Normalization {
+        self.normalize_within(term, strategy, usize::MAX)
+            .expect("more than usize::MAX rewrite steps")
+    }
+
+    /// Like `normalize`, but gives up with `None` instead of taking more than `max_steps` steps,
+    /// for rules that need not terminate.
+    pub fn normalize_within(
+        &self,
+        term: &TermRef,
+        strategy: Strategy,
+        max_steps: usize,
+    ) -> Option<Normalization> {
+        let mut map = term.identity_map();
+        let mut proof = Proof::new(term.clone());
+        let mut rounds = 0;
+        loop {
+            let redexes = self.redexes(map.target(), strategy);
+            if redexes.is_empty() {
+                return Some(Normalization { map, proof, rounds });
+            }
+            if redexes.len() > max_steps - proof.steps.len() {
+                return None;
+            }
+            rounds += 1;
+            // rewriting keeps the number of leaves, so the other redexes stay where they are
+            for (id, matched) in redexes {
+                let step = map.target().substitute_match(&matched, &self.rules[id]);
+                map *= step;
+                proof.steps.push(ProofStep {
+                    rule: id,
+                    position: matched.position,
+                    bindings: matched.bindings,
+                });
+            }
+        }
+    }
+
+    /// Drops the rules whose source contains an instance of the source of another rule, and
+    /// rewrites the targets of the others to normal form. Rules with the same source are all kept,
+    /// unless their targets have the same normal form with the leaves in the same places. For a
+    /// convergent system, the result has the same normal forms with fewer and shorter rules.
+    /// Gives up with `None` if a target takes more than `max_steps` steps to normalize, since the
+    /// rules need not terminate.
+    pub fn interreduce(&self, max_steps: usize) -> Option<RewriteSystem> {
+        let mut kept = Vec::new();
+        let mut seen = HashSet::new();
+        for (id, rule) in self.rules.iter().enumerate() {
+            let redundant =
+                self.patterns
+                    .matches(rule.source())
+                    .into_iter()
+                    .any(|(other, matched)| {
+                        other != id
+                            && (!matched.position.is_root()
+                                || self.rules[other].source() != rule.source())
+                    });
+            if redundant {
+                continue;
+            }
+            let target = self
+                .normalize_within(rule.target(), Strategy::Innermost, max_steps)?
+                .map;
+            let rule = rule * target;
+            if seen.insert((rule.source().clone(), leaf_places(&rule))) {
+                kept.push(rule);
+            }
+        }
+        Some(RewriteSystem::new(kept))
+    }
+
+    // The terms reachable from the target of `map` in at most `depth` steps, by any rule at any
+    // position, in the order of a breadth-first search, with the maps from the source of `map` to
+    // them and the steps taken. Terms are told apart by shape and the places of the leaves.
+    fn descendants(
+        &self,
+        map: TermMap<'static>,
+        depth: usize,
+    ) -> Vec<(TermMap<'static>, Vec<ProofStep>)> {
+        let mut seen = HashSet::from([leaf_places(&map)]);
+        let mut reached = vec![(map, Vec::new())];
+        let mut frontier = 0..1;
+        for _ in 0..depth {
+            let end = reached.len();
+            for index in frontier {
+                let (map, steps) = reached[index].clone();
+                for (id, matched) in self.patterns.matches(map.target()) {
+                    let next = &map * map.target().substitute_match(&matched, &self.rules[id]);
+                    if seen.insert(leaf_places(&next)) {
+                        let mut steps = steps.clone();
+                        steps.push(ProofStep {
+                            rule: id,
+                            position: matched.position,
+                            bindings: matched.bindings,
+                        });
+                        reached.push((next, steps));
+                    }
+                }
+            }
+            frontier = end..reached.len();
+        }
+        reached
+    }
+
+    /// The critical pairs whose sides have no common descendant within `depth` steps, with the
+    /// ids of the outer and the inner rule.
+    #[allow(dead_code)]
+    pub fn non_joinable_pairs(&self, depth: usize) -> Vec<(RuleId, RuleId, CriticalPair)> {
+        let mut non_joinable = Vec::new();
+        for (outer_id, outer) in self.rules.iter().enumerate() {
+            for (inner_id, inner) in self.rules.iter().enumerate() {
+                for pair in critical_pairs(outer, inner) {
+                    if self.joinable(&pair.equation, depth).is_none() {
+                        non_joinable.push((outer_id, inner_id, pair));
+                    }
+                }
+            }
+        }
+        non_joinable
+    }
+
+    /// Whether the sides of `equation` rewrite to the same shape within `depth` steps each, by any
+    /// rules at any positions, with every leaf of the source ending up in the same place on both
+    /// ways. Prefers few steps from the source, then few steps from the target.
+    pub fn joinable(&self, equation: &TermMap<'static>, depth: usize) -> Option<Join> {
+        let mut from_target = HashMap::new();
+        for (map, steps) in self.descendants(equation.clone(), depth) {
+            from_target.entry(leaf_places(&map)).or_insert(steps);
+        }
+        let source = equation.source();
+        self.descendants(source.identity_map(), depth)
+            .into_iter()
+            .find_map(|(map, steps)| {
+                let right = from_target.get(&leaf_places(&map))?;
+                Some(Join {
+                    meeting: map.target().clone(),
+                    left: Proof {
+                        term: source.clone(),
+                        steps,
+                    },
+                    right: Proof {
+                        term: equation.target().clone(),
+                        steps: right.clone(),
+                    },
+                })
+            })
+    }
+
+    /// Whether all critical pairs are joinable within `depth` steps. A terminating system is
+    /// confluent if and only if it is locally confluent.
+    #[allow(dead_code)]
+    pub fn is_locally_confluent(&self, depth: usize) -> bool {
+        self.non_joinable_pairs(depth).is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cmp::Ordering;
+
+    use crate::{
+        iter::TermIterator,
+        lterm,
+        position::{Direction, Position},
+        term,
+        term::TermPointer,
+    };
+
+    // the order in which a post-order traversal visits the positions
+    fn post_order(a: &Position, b: &Position) -> Ordering {
+        for (x, y) in a.steps().iter().zip(b.steps()) {
+            match (x, y) {
+                (Direction::Left, Direction::Right) => return Ordering::Less,
+                (Direction::Right, Direction::Left) => return Ordering::Greater,
+                _ => {}
+            }
+        }
+        b.len().cmp(&a.len())
+    }
+
+    #[test]
+    fn redexes_like_naive() {
+        // a few hundred rules, which would be slow to try at every position one by one
+        let sources: Vec<_> = TermIterator::new(3..=7).collect();
+        let system =
+            RewriteSystem::new(sources.iter().map(|source| source.identity_map()).collect());
+        assert!(system.len() > 150);
+
+        for term in TermIterator::new(9).step_by(7) {
+            // the least rule matching at every position, in pre-order
+            let naive: Vec<_> = term
+                .subterms()
+                .filter_map(|(position, _)| {
+                    sources
+                        .iter()
+                        .enumerate()
+                        .find_map(|(id, source)| Some((id, source.match_at(&term, &position)?)))
+                })
+                .collect();
+            let positions = |redexes: &[(RuleId, Match)]| -> Vec<_> {
+                redexes
+                    .iter()
+                    .map(|(id, matched)| (*id, matched.position.clone()))
+                    .collect()
+            };
+
+            let outermost = system.redexes(&term, Strategy::Outermost);
+            assert_eq!(positions(&outermost), positions(&naive[..1]));
+            let innermost = system.redexes(&term, Strategy::Innermost);
+            let expected = naive
+                .iter()
+                .min_by(|(_, a), (_, b)| post_order(&a.position, &b.position))
+                .unwrap();
+            assert_eq!(
+                positions(&innermost),
+                positions(std::slice::from_ref(expected))
+            );
+
+            let mut expected: Vec<(RuleId, Match)> = Vec::new();
+            for (id, matched) in &naive {
+                if expected
+                    .iter()
+                    .all(|(_, above)| !above.position.is_prefix_of(&matched.position))
+                {
+                    expected.push((*id, matched.clone()));
+                }
+            }
+            let parallel = system.redexes(&term, Strategy::ParallelOutermost);
+            assert_eq!(positions(&parallel), positions(&expected));
+        }
+    }
+
+    #[test]
+    fn normalize() {
+        let system = RewriteSystem::new(vec![lterm!((a * b) * c).map_to(lterm!(a * (b * c)))]);
+        let term = term!(((a * b) * c) * d);
+
+        let outermost = system.normalize(&term, Strategy::Outermost);
+        assert_eq!(outermost.normal_form(), &term!(a * (b * (c * d))));
+        assert!(outermost.map.perm().is_identity());
+        let positions: Vec<_> = outermost
+            .proof
+            .steps
+            .iter()
+            .map(|step| step.position.to_string())
+            .collect();
+        assert_eq!(positions, ["ε", "ε"]);
+
+        let innermost = system.normalize(&term, Strategy::Innermost);
+        assert_eq!(innermost.normal_form(), outermost.normal_form());
+        let positions: Vec<_> = innermost
+            .proof
+            .steps
+            .iter()
+            .map(|step| step.position.to_string())
+            .collect();
+        assert_eq!(positions, ["L", "ε", "R"]);
+
+        // the map keeps track of where the leaves go
+        let system = RewriteSystem::new(vec![lterm!((a * b) * c).map_to(lterm!(c * (a * b)))]);
+        let normalized = system.normalize(&term!((a * b) * c), Strategy::Innermost);
+        assert_eq!(normalized.normal_form(), &term!(a * (b * c)));
+        assert_eq!(
+            (0..3).map(|leaf| normalized.map[leaf]).collect::<Vec<_>>(),
+            [1, 2, 0]
+        );
+        assert!(
+            system
+                .redexes(normalized.normal_form(), Strategy::Outermost)
+                .is_empty()
+        );
+
+        // commutativity loops, so the bound is what ends it
+        let system = RewriteSystem::new(vec![lterm!(a * b).map_to(lterm!(b * a))]);
+        let term = term!((a * b) * c);
+        assert!(
+            system
+                .normalize_within(&term, Strategy::Innermost, 10)
+                .is_none()
+        );
+        let system = RewriteSystem::new(vec![lterm!((a * b) * c).map_to(lterm!(a * (b * c)))]);
+        assert!(
+            system
+                .normalize_within(&term, Strategy::Innermost, 0)
+                .is_none()
+        );
+        let normalized = system
+            .normalize_within(&term, Strategy::Innermost, 1)
+            .unwrap();
+        assert_eq!(normalized.proof.steps.len(), 1);
+    }
+
+    #[test]
+    fn local_confluence() {
+        let associativity = lterm!((a * b) * c).map_to(lterm!(a * (b * c)));
+        let system = RewriteSystem::new(vec![associativity.clone()]);
+        assert!(system.is_locally_confluent(2));
+        // joining the sides of the overlap takes two steps
+        assert!(!system.is_locally_confluent(1));
+
+        // rewriting at the root and at the left child puts the leaves in different places
+        let rule = lterm!((a * b) * c).map_to(lterm!(b * (a * c)));
+        let system = RewriteSystem::new(vec![rule]);
+        let non_joinable = system.non_joinable_pairs(4);
+        assert_eq!(non_joinable.len(), 1);
+        let (outer, inner, pair) = &non_joinable[0];
+        assert_eq!((*outer, *inner), (0, 0));
+        assert_eq!(pair.position.to_string(), "L");
+    }
+
+    #[test]
+    fn termination() {
+        let associativity = lterm!((a * b) * c).map_to(lterm!(a * (b * c)));
+        let system = RewriteSystem::new(vec![associativity.clone()]);
+        assert_eq!(system.violations(&Kbo::default()), Vec::<RuleId>::new());
+        assert!(system.check_termination().is_ok());
+
+        // with both directions, no ordering works for the second rule
+        let system = RewriteSystem::new(vec![associativity.clone(), associativity.backward()]);
+        assert_eq!(system.violations(&Lpo::default()), [1]);
+        assert_eq!(system.check_termination(), Err(vec![1]));
+
+        let commutativity = lterm!(a * b).map_to(lterm!(b * a));
+        let system = RewriteSystem::new(vec![commutativity]);
+        assert_eq!(system.check_termination(), Err(vec![0]));
+    }
+
+    #[test]
+    fn parallel_outermost() {
+        let system = RewriteSystem::new(vec![
+            lterm!(((a * b) * c) * d).map_to(lterm!((a * b) * (c * d))),
+        ]);
+        let term = term!((a * (((b * c) * d) * e)) * (((f * g) * h) * i));
+
+        let parallel = system.normalize(&term, Strategy::ParallelOutermost);
+        let sequential = system.normalize(&term, Strategy::Outermost);
+        assert_eq!(parallel.normal_form(), sequential.normal_form());
+        assert_eq!(parallel.proof.steps.len(), 2);
+        assert_eq!((parallel.rounds, sequential.rounds), (1, 2));
+        let positions: Vec<_> = parallel
+            .proof
+            .steps
+            .iter()
+            .map(|step| step.position.to_string())
+            .collect();
+        assert_eq!(positions, ["LR", "R"]);
+    }
+
+    #[test]
+    fn joinable() {
+        let system = RewriteSystem::new(vec![lterm!((a * b) * c).map_to(lterm!(a * (b * c)))]);
+        let equation = lterm!(((a * b) * c) * d).map_to(lterm!(a * ((b * c) * d)));
+
+        let join = system.joinable(&equation, 2).unwrap();
+        assert_eq!(join.meeting, term!(a * (b * (c * d))));
+        assert_eq!((join.left.steps.len(), join.right.steps.len()), (2, 1));
+        assert_eq!(join.left.replay(&system).unwrap().target(), &join.meeting);
+        assert_eq!(join.right.replay(&system).unwrap().target(), &join.meeting);
+        assert!(system.joinable(&equation, 1).is_none());
+        // the sides meet in shape, but with the first two leaves swapped
+        let swapped = lterm!(((a * b) * c) * d).map_to(lterm!(b * ((a * c) * d)));
+        assert!(system.joinable(&swapped, 2).is_none());
+
+        let s = equation.source();
+        let join = system.joinable(&s.identity_map(), 0).unwrap();
+        assert_eq!(&join.meeting, s);
+    }
+
+    #[test]
+    fn interreduce() {
+        let pairs = lterm!((a * b) * (c * d)).map_to(lterm!(a * (b * (c * d))));
+        let system = RewriteSystem::new(vec![
+            lterm!(a * ((b * c) * d)).map_to(lterm!((a * b) * (c * d))),
+            pairs.clone(),
+            lterm!(((a * b) * (c * d)) * e).map_to(lterm!((a * b) * (c * (d * e)))),
+            pairs.clone(),
+        ]);
+        let reduced = system.interreduce(100).unwrap();
+        let rules: Vec<_> = reduced
+            .rules()
+            .iter()
+            .map(|rule| rule.to_string())
+            .collect();
+        assert_eq!(
+            rules,
+            [
+                "0 * ((1 * 2) * 3) -> 0 * (1 * (2 * 3))",
+                "(0 * 1) * (2 * 3) -> 0 * (1 * (2 * 3))"
+            ]
+        );
+
+        // a rule with the same source but another target is an equation of its own
+        let system = RewriteSystem::new(vec![
+            lterm!((a * b) * c).map_to(lterm!(a * (b * c))),
+            lterm!((a * b) * c).map_to(lterm!(b * (a * c))),
+        ]);
+        assert_eq!(system.interreduce(100).unwrap().len(), 2);
+
+        // commutativity rewrites its own target forever
+        let commutativity = RewriteSystem::new(vec![lterm!(a * b).map_to(lterm!(b * a))]);
+        assert!(commutativity.interreduce(100).is_none());
+    }
+}
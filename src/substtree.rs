@@ -0,0 +1,534 @@
+use std::collections::HashMap;
+
+use crate::{
+    bidag::BinaryChildren,
+    codec::{ByteReader, ByteWriter, DecodeError},
+};
+
+type Hole = usize;
+
+// Terms are stored as pre-order sequences, holes are the variables of the substitutions in the
+// tree and stand for subterms that are filled in further down.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Symbol {
+    Operation,
+    Variable,
+    Hole(Hole),
+}
+
+static VARIABLE: [Symbol; 1] = [Symbol::Variable];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Retrieval {
+    Unifiable,
+    Instances,
+    Generalizations,
+}
+
+// the index after the subterm that starts at `start`
+fn subterm_end(symbols: &[Symbol], start: usize) -> usize {
+    let mut open = 1;
+    let mut index = start;
+    while open > 0 {
+        if symbols[index] == Symbol::Operation {
+            open += 1;
+        } else {
+            open -= 1;
+        }
+        index += 1;
+    }
+    index
+}
+
+fn flatten<P: BinaryChildren>(term: &P) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    term.walk_preorder(&mut |node| {
+        symbols.push(if node.is_leaf() {
+            Symbol::Variable
+        } else {
+            Symbol::Operation
+        })
+    });
+    symbols
+}
+
+// The most specific common generalization of a stored binding and a term. `stored` and `term`
+// are what is left to bind below it of either.
+struct Generalization {
+    general: Vec<Symbol>,
+    stored: Vec<(Hole, Vec<Symbol>)>,
+    term: Vec<(Hole, Vec<Symbol>)>,
+}
+
+impl Generalization {
+    fn new(stored: &[Symbol], term: &[Symbol], next_hole: &mut Hole) -> Self {
+        let mut generalization = Generalization {
+            general: Vec::new(),
+            stored: Vec::new(),
+            term: Vec::new(),
+        };
+        let (mut i, mut j) = (0, 0);
+        while i < stored.len() {
+            match (stored[i], term[j]) {
+                (Symbol::Hole(hole), _) => {
+                    let end = subterm_end(term, j);
+                    generalization.general.push(stored[i]);
+                    generalization.term.push((hole, term[j..end].to_vec()));
+                    i += 1;
+                    j = end;
+                }
+                (a, b) if a == b => {
+                    generalization.general.push(a);
+                    i += 1;
+                    j += 1;
+                }
+                _ => {
+                    let hole = *next_hole;
+                    *next_hole += 1;
+                    let (stored_end, term_end) = (subterm_end(stored, i), subterm_end(term, j));
+                    generalization.general.push(Symbol::Hole(hole));
+                    generalization
+                        .stored
+                        .push((hole, stored[i..stored_end].to_vec()));
+                    generalization.term.push((hole, term[j..term_end].to_vec()));
+                    i = stored_end;
+                    j = term_end;
+                }
+            }
+        }
+        generalization
+    }
+
+    // whether the stored binding and the term have more in common than a hole
+    fn shares(&self) -> bool {
+        self.stored.is_empty() || self.general.len() > 1
+    }
+}
+
+struct Node<V> {
+    bindings: Vec<(Hole, Vec<Symbol>)>,
+    children: Vec<usize>,
+    values: Vec<V>,
+}
+
+impl<V> Node<V> {
+    fn new(bindings: Vec<(Hole, Vec<Symbol>)>) -> Self {
+        Node {
+            bindings,
+            children: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+}
+
+// Matches a stored binding against a subterm of the query, binding the holes in it.
+fn bind<'q>(
+    stored: &[Symbol],
+    query: &'q [Symbol],
+    retrieval: Retrieval,
+    holes: &mut HashMap<Hole, &'q [Symbol]>,
+) -> bool {
+    let (mut i, mut j) = (0, 0);
+    while i < stored.len() {
+        match (stored[i], query[j]) {
+            (Symbol::Hole(hole), _) => {
+                let end = subterm_end(query, j);
+                holes.insert(hole, &query[j..end]);
+                i += 1;
+                j = end;
+            }
+            (a, b) if a == b => {
+                i += 1;
+                j += 1;
+            }
+            // a stored variable against an operation of the query
+            (Symbol::Variable, _) => {
+                if retrieval == Retrieval::Instances {
+                    return false;
+                }
+                i += 1;
+                j = subterm_end(query, j);
+            }
+            // an operation against a variable of the query, which covers all holes below it
+            _ => {
+                if retrieval == Retrieval::Generalizations {
+                    return false;
+                }
+                let end = subterm_end(stored, i);
+                for symbol in &stored[i..end] {
+                    if let Symbol::Hole(hole) = symbol {
+                        holes.insert(*hole, &VARIABLE);
+                    }
+                }
+                i = end;
+                j += 1;
+            }
+        }
+    }
+    true
+}
+
+/// Substitution tree over a set of terms whose leaves are distinct variables. Every node binds
+/// holes left open by its ancestors, and the bindings along a path to a leaf compose to a stored
+/// term. Terms with a common structure share the nodes for it.
+pub struct SubstitutionTree<V> {
+    nodes: Vec<Node<V>>,
+    next_hole: Hole,
+    len: usize,
+}
+
+impl<V> Default for SubstitutionTree<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> SubstitutionTree<V> {
+    pub fn new() -> Self {
+        SubstitutionTree {
+            nodes: vec![Node::new(Vec::new())],
+            next_hole: 1,
+            len: 0,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn insert<P: BinaryChildren>(&mut self, term: &P, value: V) {
+        self.len += 1;
+        let mut open = HashMap::from([(0, flatten(term))]);
+        let mut current = 0;
+
+        'descend: while !open.is_empty() {
+            for slot in 0..self.nodes[current].children.len() {
+                let child = self.nodes[current].children[slot];
+                let generalizations: Vec<_> = self.nodes[child]
+                    .bindings
+                    .iter()
+                    .map(|(hole, stored)| {
+                        Generalization::new(stored, &open[hole], &mut self.next_hole)
+                    })
+                    .collect();
+
+                if generalizations.iter().all(|g| g.stored.is_empty()) {
+                    for (g, (hole, _)) in
+                        generalizations.into_iter().zip(&self.nodes[child].bindings)
+                    {
+                        open.remove(hole);
+                        open.extend(g.term);
+                    }
+                    current = child;
+                    continue 'descend;
+                }
+                if !generalizations.iter().any(Generalization::shares) {
+                    continue;
+                }
+
+                // the common part moves into a new node above the child and the new term
+                let mut common = Vec::new();
+                let mut rest = Vec::new();
+                let old_bindings = std::mem::take(&mut self.nodes[child].bindings);
+                for (g, (hole, stored)) in generalizations.into_iter().zip(old_bindings) {
+                    let value = open.remove(&hole).unwrap();
+                    if g.shares() {
+                        common.push((hole, g.general));
+                        self.nodes[child].bindings.extend(g.stored);
+                        rest.extend(g.term);
+                    } else {
+                        self.nodes[child].bindings.push((hole, stored));
+                        rest.push((hole, value));
+                    }
+                }
+                rest.extend(open.drain());
+
+                let mut leaf = Node::new(rest);
+                leaf.values.push(value);
+                let mut split = Node::new(common);
+                split.children = vec![child, self.nodes.len()];
+                self.nodes.push(leaf);
+                self.nodes.push(split);
+                self.nodes[current].children[slot] = self.nodes.len() - 1;
+                return;
+            }
+
+            let leaf = self.nodes.len();
+            self.nodes.push(Node::new(open.drain().collect()));
+            self.nodes[current].children.push(leaf);
+            current = leaf;
+        }
+        self.nodes[current].values.push(value);
+    }
+
+    fn retrieve<P: BinaryChildren>(&self, query: &P, retrieval: Retrieval) -> Vec<&V> {
+        let query = flatten(query);
+        let mut found = Vec::new();
+        let mut stack = vec![(0, HashMap::from([(0, &query[..])]))];
+
+        while let Some((index, mut holes)) = stack.pop() {
+            let node = &self.nodes[index];
+            let matched = node.bindings.iter().all(|(hole, stored)| {
+                let subterm = holes.remove(hole).unwrap();
+                bind(stored, subterm, retrieval, &mut holes)
+            });
+            if matched {
+                found.extend(&node.values);
+                for &child in &node.children {
+                    stack.push((child, holes.clone()));
+                }
+            }
+        }
+        found
+    }
+
+    /// The values of stored terms that unify with `query`. With linear terms over one operation
+    /// these are all of them, but they are still found through the tree.
+    pub fn unifiable<P: BinaryChildren>(&self, query: &P) -> Vec<&V> {
+        self.retrieve(query, Retrieval::Unifiable)
+    }
+
+    /// The values of stored terms that are instances of `query`.
+    pub fn instances<P: BinaryChildren>(&self, query: &P) -> Vec<&V> {
+        self.retrieve(query, Retrieval::Instances)
+    }
+
+    /// The values of stored terms that `query` is an instance of.
+    pub fn generalizations<P: BinaryChildren>(&self, query: &P) -> Vec<&V> {
+        self.retrieve(query, Retrieval::Generalizations)
+    }
+}
+
+// symbols as u32: 0 for operations, 1 for variables and 2 + h for hole h
+fn encode_symbols(symbols: &[Symbol], writer: &mut ByteWriter) {
+    writer.u32(symbols.len() as u32);
+    for symbol in symbols {
+        writer.u32(match symbol {
+            Symbol::Operation => 0,
+            Symbol::Variable => 1,
+            Symbol::Hole(hole) => *hole as u32 + 2,
+        });
+    }
+}
+
+fn decode_symbols(reader: &mut ByteReader) -> Result<Vec<Symbol>, DecodeError> {
+    let len = reader.u32()?;
+    (0..len)
+        .map(|_| {
+            Ok(match reader.u32()? {
+                0 => Symbol::Operation,
+                1 => Symbol::Variable,
+                hole => Symbol::Hole(hole as usize - 2),
+            })
+        })
+        .collect()
+}
+
+impl SubstitutionTree<usize> {
+    pub fn encode_into(&self, writer: &mut ByteWriter) {
+        writer.u32(self.next_hole as u32);
+        writer.u32(self.len as u32);
+        writer.u32(self.nodes.len() as u32);
+        for node in &self.nodes {
+            writer.u32(node.bindings.len() as u32);
+            for (hole, symbols) in &node.bindings {
+                writer.u32(*hole as u32);
+                encode_symbols(symbols, writer);
+            }
+            writer.u32(node.children.len() as u32);
+            node.children
+                .iter()
+                .for_each(|child| writer.u32(*child as u32));
+            writer.u32(node.values.len() as u32);
+            node.values
+                .iter()
+                .for_each(|value| writer.u32(*value as u32));
+        }
+    }
+
+    pub fn decode_from(reader: &mut ByteReader) -> Result<Self, DecodeError> {
+        let next_hole = reader.u32()? as usize;
+        let len = reader.u32()? as usize;
+        let node_count = reader.u32()?;
+        let mut nodes = Vec::new();
+        for _ in 0..node_count {
+            let mut node = Node::new(Vec::new());
+            for _ in 0..reader.u32()? {
+                let hole = reader.u32()? as usize;
+                node.bindings.push((hole, decode_symbols(reader)?));
+            }
+            for _ in 0..reader.u32()? {
+                let child = reader.u32()?;
+                if child >= node_count {
+                    return Err(DecodeError::InvalidIndex(child));
+                }
+                node.children.push(child as usize);
+            }
+            for _ in 0..reader.u32()? {
+                node.values.push(reader.u32()? as usize);
+            }
+            nodes.push(node);
+        }
+        if nodes.is_empty() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let tree = SubstitutionTree {
+            nodes,
+            next_hole,
+            len,
+        };
+        tree.validate()?;
+        Ok(tree)
+    }
+
+    // Whether the nodes form a tree below the root where every node binds holes left open by its
+    // ancestors to single subterms, and all holes are bound where values are stored, so that
+    // retrieval can neither loop nor run out of symbols.
+    fn validate(&self) -> Result<(), DecodeError> {
+        let mut visited = vec![false; self.nodes.len()];
+        let mut values = 0;
+        let mut stack = vec![(0, vec![0])];
+        while let Some((index, mut open)) = stack.pop() {
+            if std::mem::replace(&mut visited[index], true) {
+                return Err(DecodeError::InvalidTree);
+            }
+            let node = &self.nodes[index];
+            for (hole, symbols) in &node.bindings {
+                let position = open.iter().position(|open| open == hole);
+                open.swap_remove(position.ok_or(DecodeError::InvalidTree)?);
+                let mut pending = 1usize;
+                for symbol in symbols {
+                    pending = pending.checked_sub(1).ok_or(DecodeError::InvalidTree)?;
+                    match *symbol {
+                        Symbol::Operation => pending += 2,
+                        Symbol::Variable => {}
+                        Symbol::Hole(hole) if hole < self.next_hole && !open.contains(&hole) => {
+                            open.push(hole)
+                        }
+                        Symbol::Hole(_) => return Err(DecodeError::InvalidTree),
+                    }
+                }
+                if pending != 0 {
+                    return Err(DecodeError::InvalidTree);
+                }
+            }
+            if !node.values.is_empty() && !open.is_empty() {
+                return Err(DecodeError::InvalidTree);
+            }
+            values += node.values.len();
+            for &child in &node.children {
+                stack.push((child, open.clone()));
+            }
+        }
+        if values != self.len || visited.contains(&false) {
+            return Err(DecodeError::InvalidTree);
+        }
+        Ok(())
+    }
+
+    /// The stored values, in no particular order.
+    pub fn values(&self) -> impl Iterator<Item = &usize> {
+        self.nodes.iter().flat_map(|node| node.values.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{iter::TermIterator, term::TermPointer};
+
+    #[test]
+    fn substitution_tree() {
+        let mut terms: Vec<_> = TermIterator::new(1..=5).collect();
+        terms.push(terms[3].clone());
+        let mut tree = SubstitutionTree::new();
+        for (i, term) in terms.iter().enumerate() {
+            tree.insert(term, i);
+        }
+        assert_eq!(tree.len(), terms.len());
+
+        let sorted = |mut found: Vec<&usize>| {
+            found.sort();
+            found.into_iter().copied().collect::<Vec<_>>()
+        };
+        for query in &terms {
+            let instances: Vec<_> = (0..terms.len())
+                .filter(|&i| query.instance_bindings(&terms[i]).is_some())
+                .collect();
+            let generalizations: Vec<_> = (0..terms.len())
+                .filter(|&i| terms[i].instance_bindings(query).is_some())
+                .collect();
+            assert_eq!(sorted(tree.instances(query)), instances);
+            assert_eq!(sorted(tree.generalizations(query)), generalizations);
+            assert_eq!(tree.unifiable(query).len(), tree.len());
+        }
+    }
+
+    // a tree of a root and one child, which binds hole `hole` to `symbols`
+    fn encoded(hole: u32, symbols: &[u32], children: &[u32]) -> Vec<u8> {
+        let mut writer = ByteWriter::new();
+        [2, 1, 2, 0, 1, 1, 0].iter().for_each(|&n| writer.u32(n));
+        [1, hole, symbols.len() as u32]
+            .iter()
+            .for_each(|&n| writer.u32(n));
+        symbols.iter().for_each(|&symbol| writer.u32(symbol));
+        writer.u32(children.len() as u32);
+        children.iter().for_each(|&child| writer.u32(child));
+        [1, 7].iter().for_each(|&n| writer.u32(n));
+        writer.into_bytes()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<SubstitutionTree<usize>, DecodeError> {
+        SubstitutionTree::decode_from(&mut ByteReader::new(bytes))
+    }
+
+    #[test]
+    fn decode_validates() {
+        let mut tree = SubstitutionTree::new();
+        for (i, term) in TermIterator::new(1..=5).enumerate() {
+            tree.insert(&term, i);
+        }
+        let mut writer = ByteWriter::new();
+        tree.encode_into(&mut writer);
+        let decoded = decode(&writer.into_bytes()).unwrap();
+        let query = crate::term!((a * b) * c);
+        assert_eq!(decoded.instances(&query), tree.instances(&query));
+
+        assert!(decode(&encoded(0, &[0, 1, 1], &[])).is_ok());
+        // not a single subterm
+        assert_eq!(
+            decode(&encoded(0, &[0, 1], &[])).err(),
+            Some(DecodeError::InvalidTree)
+        );
+        assert_eq!(
+            decode(&encoded(0, &[1, 1], &[])).err(),
+            Some(DecodeError::InvalidTree)
+        );
+        // a hole that is not open
+        assert_eq!(
+            decode(&encoded(1, &[1], &[])).err(),
+            Some(DecodeError::InvalidTree)
+        );
+        // a hole beyond `next_hole`
+        assert_eq!(
+            decode(&encoded(0, &[4], &[])).err(),
+            Some(DecodeError::InvalidTree)
+        );
+        // a hole left open where a value is stored
+        assert_eq!(
+            decode(&encoded(0, &[0, 1, 3], &[])).err(),
+            Some(DecodeError::InvalidTree)
+        );
+        // a cycle back to the root
+        assert_eq!(
+            decode(&encoded(0, &[1], &[0])).err(),
+            Some(DecodeError::InvalidTree)
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    fmt::{Debug, Display},
+    hash::{Hash, Hasher},
+    sync::{Arc, LazyLock},
+};
+
+use crate::{
+    bidag::{BinaryChildren, FromChildren},
+    term::{TermPointer, TermRef},
+};
+
+/// `Term` with `Arc` children, so terms can be shared between threads.
+#[derive(Clone)]
+pub enum SyncTerm {
+    Variable,
+    Operation(SyncTermRef, SyncTermRef),
+}
+
+pub type SyncTermRef = Arc<SyncTerm>;
+
+impl TermPointer for SyncTermRef {}
+
+impl SyncTerm {
+    /// Converts `term`, keeping physically shared subterms shared.
+    pub fn from_term(term: &TermRef) -> SyncTermRef {
+        term.map_shared(&mut |_| ())
+    }
+
+    pub fn to_term(self: &SyncTermRef) -> TermRef {
+        self.map_shared(&mut |_| ())
+    }
+}
+
+impl BinaryChildren for SyncTerm {
+    fn children(&self) -> Option<(&Self, &Self)> {
+        match self {
+            SyncTerm::Variable => None,
+            SyncTerm::Operation(left, right) => Some((left, right)),
+        }
+    }
+}
+
+impl BinaryChildren for SyncTermRef {
+    fn children(&self) -> Option<(&Self, &Self)> {
+        match self.as_ref() {
+            SyncTerm::Variable => None,
+            SyncTerm::Operation(left, right) => Some((left, right)),
+        }
+    }
+
+    fn address(&self) -> *const () {
+        Arc::as_ptr(self) as *const ()
+    }
+}
+
+impl FromChildren<()> for SyncTermRef {
+    fn from_children(left: Self, right: Self) -> Self {
+        Arc::new(SyncTerm::Operation(left, right))
+    }
+
+    fn from_leaf(_value: ()) -> Self {
+        Arc::new(SyncTerm::Variable)
+    }
+}
+
+impl PartialEq for SyncTerm {
+    fn eq(&self, other: &Self) -> bool {
+        self.shape_eq(other)
+    }
+}
+
+impl Eq for SyncTerm {}
+
+impl Hash for SyncTerm {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.hash_shape(state);
+    }
+}
+
+static DROP_PLACEHOLDER: LazyLock<SyncTermRef> = LazyLock::new(|| Arc::new(SyncTerm::Variable));
+
+// see `Drop for Term`
+impl Drop for SyncTerm {
+    fn drop(&mut self) {
+        let SyncTerm::Operation(left, right) = self else {
+            return;
+        };
+        if Arc::strong_count(left) > 1 && Arc::strong_count(right) > 1 {
+            return;
+        }
+
+        let detach = |child: &mut SyncTermRef| std::mem::replace(child, DROP_PLACEHOLDER.clone());
+
+        let mut stack = vec![detach(left), detach(right)];
+        while let Some(node) = stack.pop() {
+            if let Some(mut term) = Arc::into_inner(node)
+                && let SyncTerm::Operation(left, right) = &mut term
+            {
+                stack.push(detach(left));
+                stack.push(detach(right));
+            }
+        }
+    }
+}
+
+impl Debug for SyncTerm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SyncTerm[{}]", self)
+    }
+}
+
+impl Display for SyncTerm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut leaf_count = 0;
+        self.display_helper(
+            f,
+            &mut |_, f| write!(f, "("),
+            &mut |_, f| write!(f, ")"),
+            &mut |_, f| write!(f, " * "),
+            &mut |_, f| {
+                leaf_count += 1;
+                write!(f, "{}", leaf_count - 1)
+            },
+        )
+    }
+}
+
+// the whole exploration state has to be shareable between threads when built from `SyncTermRef`
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<crate::eqclass::EquivalenceClasses<SyncTermRef>>();
+    assert_send_sync::<crate::maps::TermMap<'static, SyncTermRef>>();
+    assert_send_sync::<crate::indexing::IndexedTerm<SyncTermRef>>();
+};
@@ -1,25 +1,242 @@
 use std::{
     fmt::{Debug, Display},
+    hash::{Hash, Hasher},
     rc::Rc,
 };
 
 use crate::{
     bidag::{BinaryChildren, FromChildren},
     byaddr::TermByAddress,
+    codec::{ByteReader, ByteWriter, DecodeError},
+    indexing::Match,
     labeled::LabeledTermRef,
     maps::{NodeIndex, TermMap},
-    perm::perms::PermIndex,
+    perm::perms::Permutation,
+    position::{Direction, Occurrences, Position},
 };
 
-#[derive(Eq, PartialEq, Hash, Clone)]
+#[derive(Clone)]
 pub enum Term {
     Variable,
-    Operation(TermRef, TermRef),
+    /// Construct with `Term::operation`, which computes the `TermInfo`.
+    Operation(TermRef, TermRef, TermInfo),
 }
 
 pub type TermRef = Rc<Term>;
 
+/// Sizes cached in every operation node, computed bottom-up at construction.
+#[derive(Clone, Copy, Debug)]
+pub struct TermInfo {
+    leaves: u32,
+    depth: u32,
+    hash: u64,
+}
+
+const VARIABLE_HASH: u64 = 0x9e37_79b9_7f4a_7c15;
+
+// the rotation makes the combination order dependent, the multiplication and final xor-shift
+// spread it over all bits
+fn combine_hashes(left: u64, right: u64) -> u64 {
+    let mixed = (left.rotate_left(23) ^ right).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    mixed ^ (mixed >> 31)
+}
+
+/// Shared handles to terms, i.e. `TermRef` and its thread safe counterpart `SyncTermRef`.
+pub trait TermPointer: BinaryChildren + FromChildren<()> + Clone + Eq + Hash + Display {
+    fn leaf_count(&self) -> NodeIndex {
+        self.reduce(&mut |_, left, right| left + right, &mut |_| 1)
+    }
+
+    /// If `instance` is an instance of `self` as a pattern, the subterms of `instance` at the
+    /// leaves of `self`, in order.
+    fn instance_bindings(&self, instance: &Self) -> Option<Vec<Self>> {
+        let mut bindings = Vec::new();
+        let mut stack = vec![(self, instance)];
+        while let Some((pattern, node)) = stack.pop() {
+            match (pattern.children(), node.children()) {
+                (None, _) => bindings.push(node.clone()),
+                (Some((pattern_left, pattern_right)), Some((left, right))) => {
+                    stack.push((pattern_right, right));
+                    stack.push((pattern_left, left));
+                }
+                (Some(_), None) => return None,
+            }
+        }
+        Some(bindings)
+    }
+
+    /// The match of `self` as a pattern at `position` of `term`, checked top-down without
+    /// building an index, for one-shot queries.
+    fn match_at(&self, term: &Self, position: &Position) -> Option<Match<Self>> {
+        let root = position.steps().iter().try_fold(term, |node, direction| {
+            node.children().map(|(left, right)| match direction {
+                Direction::Left => left,
+                Direction::Right => right,
+            })
+        })?;
+        let bindings = self.instance_bindings(root)?;
+        Some(Match {
+            position: position.clone(),
+            root: root.clone(),
+            bindings,
+        })
+    }
+
+    /// All matches of `self` as a pattern in `term` in pre-order, found by trying every position.
+    /// Unlike `IndexedTerm`, nothing is precomputed.
+    fn match_top_down(&self, term: &Self) -> Vec<Match<Self>> {
+        let mut matches = Vec::new();
+        term.walk_with_positions(&mut |position, node| {
+            if let Some(bindings) = self.instance_bindings(node) {
+                matches.push(Match {
+                    position: position.clone(),
+                    root: node.clone(),
+                    bindings,
+                });
+            }
+        });
+        matches
+    }
+
+    /// The permutation of the leaves from `self` to the result of rewriting `matched` with a map
+    /// whose inverse permutation is `backward`. It only needs the leaf counts, so it can be
+    /// computed on any pointer type and the result built with `Term::substitute_with_perm`.
+    fn rewrite_perm(
+        &self,
+        matched: &Match<Self>,
+        backward: &Permutation<'_>,
+    ) -> Permutation<'static> {
+        let mut offset = 0;
+        let mut node = self;
+        for direction in matched.position.steps() {
+            let (left, right) = node.children().expect("position is not in the term");
+            node = match direction {
+                Direction::Left => left,
+                Direction::Right => {
+                    offset += left.leaf_count();
+                    right
+                }
+            };
+        }
+        let sizes: Vec<_> = matched.bindings.iter().map(Self::leaf_count).collect();
+        Permutation::from(rewritten_leaves(
+            self.leaf_count(),
+            offset,
+            &sizes,
+            backward,
+        ))
+        .inverse()
+    }
+}
+
+// The leaf of the term at every leaf of the result of rewriting a match with `offset` leaves left
+// of it, in a term with `leaves` leaves. `sizes` are the numbers of leaves of the bindings and
+// `backward` is the inverse permutation of the applied map.
+fn rewritten_leaves(
+    leaves: NodeIndex,
+    offset: NodeIndex,
+    sizes: &[NodeIndex],
+    backward: &Permutation<'_>,
+) -> Vec<NodeIndex> {
+    // the i-th binding has the leaves [starts[i], starts[i] + sizes[i]) of the match
+    let mut starts = Vec::with_capacity(sizes.len());
+    let mut matched = 0;
+    for size in sizes {
+        starts.push(matched);
+        matched += size;
+    }
+
+    // leaves outside of the match stay where they are
+    let mut result = Vec::with_capacity(leaves as usize);
+    result.extend(0..offset);
+    for target_leaf in 0..sizes.len() as NodeIndex {
+        let binding = backward.get(target_leaf) as usize;
+        result.extend((starts[binding] + offset)..(starts[binding] + sizes[binding] + offset));
+    }
+    result.extend((offset + matched)..leaves);
+    result
+}
+
+// the target of `backward.source()` with the leaves replaced by the bindings of the source
+fn replaced_target(bindings: &[TermRef], backward: &TermMap<'_>) -> TermRef {
+    backward
+        .source()
+        .counted_replace_leaves(&mut |_, target_leaf| {
+            bindings[backward[target_leaf] as usize].clone()
+        })
+}
+
+impl TermPointer for TermRef {
+    fn leaf_count(&self) -> NodeIndex {
+        NodeIndex::try_from(self.leaves()).expect("term has too many leaves to index")
+    }
+}
+
 impl Term {
+    pub fn operation(left: TermRef, right: TermRef) -> TermRef {
+        let info = TermInfo {
+            leaves: (left.leaves() as u32)
+                .checked_add(right.leaves() as u32)
+                .expect("too many leaves"),
+            depth: (left.depth().max(right.depth()) as u32)
+                .checked_add(1)
+                .expect("term is too deep"),
+            hash: combine_hashes(left.shape_hash(), right.shape_hash()),
+        };
+        Rc::new(Term::Operation(left, right, info))
+    }
+
+    pub fn leaves(&self) -> usize {
+        match self {
+            Term::Variable => 1,
+            Term::Operation(_, _, info) => info.leaves as usize,
+        }
+    }
+
+    /// Length of the longest path from the root to a leaf.
+    pub fn depth(&self) -> usize {
+        match self {
+            Term::Variable => 0,
+            Term::Operation(_, _, info) => info.depth as usize,
+        }
+    }
+
+    /// Hash of the shape, cached in every node so hashing a term takes constant time.
+    pub fn shape_hash(&self) -> u64 {
+        match self {
+            Term::Variable => VARIABLE_HASH,
+            Term::Operation(_, _, info) => info.hash,
+        }
+    }
+
+    /// Terms over one associative operation only differ in their number of leaves. See
+    /// `LabeledTerm::eq_modulo_assoc` and `TermMap::trivial_modulo_assoc` for the versions that
+    /// respect leaf identities.
+    pub fn eq_modulo_assoc(&self, other: &Term) -> bool {
+        self.leaves() == other.leaves()
+    }
+
+    pub fn node_count(&self) -> usize {
+        2 * self.leaves() - 1
+    }
+
+    /// All subterms with their positions in pre-order, i.e. every node comes before its left
+    /// subterm, which comes before its right subterm.
+    pub fn subterms<'t>(self: &'t TermRef) -> Subterms<'t> {
+        Subterms {
+            stack: vec![(Position::root(), self)],
+        }
+    }
+
+    pub fn subterm_at<'t>(self: &'t TermRef, position: &Position) -> Option<&'t TermRef> {
+        position.steps().iter().try_fold(self, |node, direction| {
+            node.children().map(|(left, right)| match direction {
+                Direction::Left => left,
+                Direction::Right => right,
+            })
+        })
+    }
+
     pub fn label<T, I: Iterator<Item = T>>(
         self: &TermRef,
         mut iter: I,
@@ -43,115 +260,280 @@ impl Term {
     }
 
     pub fn counted_clone(&self) -> (TermRef, NodeIndex) {
-        let mut leaf_count = 0;
         (
-            self.replace_leaves(&mut |_| {
-                leaf_count += 1;
-                Rc::new(Term::Variable)
-            }),
-            leaf_count,
+            self.replace_leaves(&mut |_| Rc::new(Term::Variable)),
+            NodeIndex::try_from(self.leaves()).expect("term has too many leaves to index"),
         )
     }
 
-    fn insert_replacements_helper(
-        self: &TermRef,
-        match_root: &TermByAddress,
-        replacements: &Vec<(TermRef, NodeIndex, NodeIndex)>,
-        backward_map: &TermMap<'_>,
-        leaf_index: &mut NodeIndex,
-        computed_map: &mut Vec<NodeIndex>,
-    ) -> TermRef {
-        match self.children() {
-            None => {
-                computed_map.push(*leaf_index);
-                *leaf_index += 1;
-                Rc::new(Self::Variable)
-            }
-            Some((left, right)) => {
-                if &TermByAddress::from(self.as_ref()) == match_root {
-                    let offset_leaf_index = *leaf_index;
-                    backward_map
-                        .source()
-                        .counted_replace_leaves(&mut |_, target_leaf_index| {
-                            let translated_index = backward_map[target_leaf_index];
-                            let (replacement, start, end) =
-                                &replacements[translated_index as usize];
-                            computed_map
-                                .extend((start + offset_leaf_index)..(end + offset_leaf_index));
-                            *leaf_index += end - start;
-                            replacement.clone()
-                        })
-                } else {
-                    let left_result = left.insert_replacements_helper(
-                        match_root,
-                        replacements,
-                        backward_map,
-                        leaf_index,
-                        computed_map,
-                    );
-                    let right_result = right.insert_replacements_helper(
-                        match_root,
-                        replacements,
-                        backward_map,
-                        leaf_index,
-                        computed_map,
-                    );
-
-                    Rc::new(Term::Operation(left_result, right_result))
+    /// Replaces the subterm at `position` by `subterm`. Subterms off the path are shared.
+    pub fn graft(self: &TermRef, position: &Position, subterm: TermRef) -> TermRef {
+        let mut spine = Vec::with_capacity(position.len());
+        let mut node = self;
+        for direction in position.steps() {
+            let (left, right) = node.children().expect("position is not in the term");
+            spine.push((node, *direction));
+            node = match direction {
+                Direction::Left => left,
+                Direction::Right => right,
+            };
+        }
+
+        spine
+            .into_iter()
+            .rev()
+            .fold(subterm, |child, (parent, direction)| {
+                let (left, right) = parent.children().unwrap();
+                match direction {
+                    Direction::Left => Term::operation(child, right.clone()),
+                    Direction::Right => Term::operation(left.clone(), child),
+                }
+            })
+    }
+
+    /// The subterm at `position` together with the number of leaves left of it.
+    fn locate<'t>(self: &'t TermRef, position: &Position) -> (&'t TermRef, NodeIndex) {
+        let mut offset = 0;
+        let mut node = self;
+        for direction in position.steps() {
+            let (left, right) = node.children().expect("position is not in the term");
+            node = match direction {
+                Direction::Left => left,
+                Direction::Right => {
+                    offset += left.leaves() as NodeIndex;
+                    right
                 }
+            };
+        }
+        (node, offset)
+    }
+
+    /// Rewrites the match at `position` with `offset` leaves left of it, where `bindings` are the
+    /// subterms matched by the leaves of the source of the applied map. `backward` is the inverse
+    /// of that map.
+    fn rewrite_located(
+        self: &TermRef,
+        position: &Position,
+        bindings: &[TermRef],
+        offset: NodeIndex,
+        backward: &TermMap<'_>,
+    ) -> TermMap<'static> {
+        let sizes: Vec<_> = bindings.iter().map(TermPointer::leaf_count).collect();
+        let computed_map = rewritten_leaves(self.leaf_count(), offset, &sizes, backward.perm());
+        let result = self.graft(position, replaced_target(bindings, backward));
+        let result_map_backward = TermMap::new(result, self.clone(), computed_map.into());
+        result_map_backward.into_backward()
+    }
+
+    /// Compact binary encoding of the shape: 2 bits per operation node, as balanced parentheses
+    /// `(left) right`.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = ByteWriter::new();
+        self.encode_into(&mut writer);
+        writer.into_bytes()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<TermRef, DecodeError> {
+        let mut reader = ByteReader::new(bytes);
+        let term = Term::decode_from(&mut reader)?;
+        reader.finish()?;
+        Ok(term)
+    }
+
+    pub fn encode_into(&self, writer: &mut ByteWriter) {
+        // the pre-order sequence with 1 for operations and 0 for leaves, without its last bit
+        // (always a leaf), is exactly the balanced parentheses word
+        let mut bits = Vec::with_capacity(2 * self.leaves());
+        self.walk_preorder(&mut |node| bits.push(!node.is_leaf()));
+        bits.pop();
+        writer.bits(&bits);
+    }
+
+    pub fn decode_from(reader: &mut ByteReader) -> Result<TermRef, DecodeError> {
+        let mut bits = reader.bits()?;
+        bits.push(false);
+
+        // reversed pre-order visits the right subtree, then the left one, then the node itself
+        let mut stack = Vec::new();
+        for bit in bits.into_iter().rev() {
+            if bit {
+                let left = stack.pop().ok_or(DecodeError::InvalidShape)?;
+                let right = stack.pop().ok_or(DecodeError::InvalidShape)?;
+                stack.push(Term::operation(left, right));
+            } else {
+                stack.push(Rc::new(Term::Variable));
             }
         }
+
+        match (stack.pop(), stack.is_empty()) {
+            (Some(term), true) => Ok(term),
+            _ => Err(DecodeError::InvalidShape),
+        }
     }
 
     pub fn identity_map(self: &TermRef) -> TermMap<'static> {
-        let (_, leaf_count) = self.counted_clone();
-        TermMap::new(
-            self.clone(),
-            self.clone(),
-            (0..leaf_count as PermIndex).collect::<Vec<_>>().into(),
-        )
+        TermMap::identity(self.clone())
     }
 
+    /// Applies `map` at the first occurrence (in pre-order) of `match_root`. Prefer
+    /// `substitute_at`, which also works when `match_root` is shared within the term.
     pub fn substitute(
         self: &TermRef,
         match_root: TermByAddress,
         map: &TermMap<'_>,
     ) -> TermMap<'static> {
-        // replacements[i] = (replacement, a, b) such that replacment is a copy of the tree at
-        // the i-th leaf of the embedded source. The origial tree has the leaves [a, b) in `match_root`.
-        let mut replacements = Vec::new();
-        let mut replacement_leaf_index = 0;
-
-        map.source().propagate(
-            match_root.as_ref(),
-            &mut |_, embedded_node| {
-                embedded_node
-                    .children()
-                    .expect("match_root not embedded here")
-            },
-            &mut |_, embedded_node| {
-                let (replacement, replace_size) = embedded_node.counted_clone();
-                replacements.push((
-                    replacement,
-                    replacement_leaf_index,
-                    replacement_leaf_index + replace_size,
-                ));
-                replacement_leaf_index += replace_size;
-            },
-        );
+        let (position, _) = self
+            .subterms()
+            .find(|(_, node)| TermByAddress::from(node.as_ref()) == match_root)
+            .expect("match_root is not a subterm");
+        self.substitute_at(&position, map)
+    }
+
+    /// Rewrites the subterm at `position`, which has to be an instance of `map.source()`, to the
+    /// corresponding instance of `map.target()`. Returns the leaf correspondence between `self`
+    /// and the result.
+    pub fn substitute_at(
+        self: &TermRef,
+        position: &Position,
+        map: &TermMap<'_>,
+    ) -> TermMap<'static> {
+        let (match_root, offset) = self.locate(position);
+        let bindings = map
+            .source()
+            .instance_bindings(match_root)
+            .expect("match_root not embedded here");
+        self.rewrite_located(position, &bindings, offset, &map.backward())
+    }
 
-        let mut computed_map = Vec::new();
-        let mut result_leaf_index = 0;
-        let result = self.insert_replacements_helper(
-            &match_root,
-            &replacements,
+    /// Like `substitute_at`, but takes the bindings from the match instead of recomputing them.
+    pub fn substitute_match(
+        self: &TermRef,
+        matched: &Match,
+        map: &TermMap<'_>,
+    ) -> TermMap<'static> {
+        let (_, offset) = self.locate(&matched.position);
+        self.rewrite_located(
+            &matched.position,
+            &matched.bindings,
+            offset,
             &map.backward(),
-            &mut result_leaf_index,
-            &mut computed_map,
+        )
+    }
+
+    /// Like `substitute_at`, with the permutation of the leaves already computed by
+    /// `rewrite_perm`, e.g. on another thread. Only the new nodes of the result are built here.
+    pub fn substitute_with_perm(
+        self: &TermRef,
+        position: &Position,
+        map: &TermMap<'_>,
+        perm: Permutation<'static>,
+    ) -> TermMap<'static> {
+        let (match_root, _) = self.locate(position);
+        let bindings = map
+            .source()
+            .instance_bindings(match_root)
+            .expect("match_root not embedded here");
+        let replaced = replaced_target(&bindings, &map.backward());
+        TermMap::new(self.clone(), self.graft(position, replaced), perm)
+    }
+
+    /// Applies `map` at every occurrence, each time to `self`, giving one result per occurrence.
+    /// The occurrences are located in a single pre-order pass over the term.
+    pub fn substitute_all(
+        self: &TermRef,
+        occurrences: &Occurrences,
+        map: &TermMap<'_>,
+    ) -> Vec<TermMap<'static>> {
+        let backward = map.backward();
+        let mut results = Vec::with_capacity(occurrences.len());
+        let mut pending = occurrences.iter().peekable();
+        let mut offset = 0;
+
+        for (position, node) in self.subterms() {
+            let Some(next) = pending.peek() else {
+                break;
+            };
+            if **next == position {
+                let bindings = map
+                    .source()
+                    .instance_bindings(node)
+                    .expect("match_root not embedded here");
+                results.push(self.rewrite_located(&position, &bindings, offset, &backward));
+                pending.next();
+            }
+            if node.children().is_none() {
+                offset += 1;
+            }
+        }
+
+        assert!(pending.next().is_none(), "occurrence is not in the term");
+        results
+    }
+
+    /// Rewrites `a * (b * c)` at `position` to `(a * b) * c`. Returns `None` if the right child of
+    /// the subterm is a leaf. Rotations keep the order of the leaves, so the map is the identity
+    /// on leaf indices.
+    pub fn rotate_left_at(self: &TermRef, position: &Position) -> Option<TermMap<'static>> {
+        let subterm = self
+            .subterm_at(position)
+            .expect("position is not in the term");
+        let (a, right) = subterm.children()?;
+        let (b, c) = right.children()?;
+        let rotated = Term::operation(Term::operation(a.clone(), b.clone()), c.clone());
+        Some(self.replace_at(position, rotated).1)
+    }
+
+    /// Rewrites `(a * b) * c` at `position` to `a * (b * c)`. Returns `None` if the left child of
+    /// the subterm is a leaf.
+    pub fn rotate_right_at(self: &TermRef, position: &Position) -> Option<TermMap<'static>> {
+        let subterm = self
+            .subterm_at(position)
+            .expect("position is not in the term");
+        let (left, c) = subterm.children()?;
+        let (a, b) = left.children()?;
+        let rotated = Term::operation(a.clone(), Term::operation(b.clone(), c.clone()));
+        Some(self.replace_at(position, rotated).1)
+    }
+
+    /// Replaces the subterm at `position` by `replacement`, which needs the same number of
+    /// leaves. The leaves of the old subterm correspond to those of `replacement` in order.
+    pub fn replace_at(
+        self: &TermRef,
+        position: &Position,
+        replacement: TermRef,
+    ) -> (TermRef, TermMap<'static>) {
+        let (subterm, _) = self.locate(position);
+        assert_eq!(
+            subterm.leaves(),
+            replacement.leaves(),
+            "replacement has a different number of leaves"
         );
 
-        let result_map_backward = TermMap::new(result, self.clone(), computed_map.into());
-        result_map_backward.into_backward()
+        let result = self.graft(position, replacement);
+        let leaves = self.leaves() as NodeIndex;
+        let map = TermMap::new(
+            self.clone(),
+            result.clone(),
+            (0..leaves).collect::<Vec<_>>().into(),
+        );
+        (result, map)
+    }
+}
+
+pub struct Subterms<'t> {
+    stack: Vec<(Position, &'t TermRef)>,
+}
+
+impl<'t> Iterator for Subterms<'t> {
+    type Item = (Position, &'t TermRef);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (position, node) = self.stack.pop()?;
+        if let Some((left, right)) = node.children() {
+            self.stack.push((position.right(), right));
+            self.stack.push((position.left(), left));
+        }
+        Some((position, node))
     }
 }
 
@@ -159,7 +541,7 @@ impl BinaryChildren for Term {
     fn children(&self) -> Option<(&Self, &Self)> {
         match self {
             Term::Variable => None,
-            Term::Operation(left, right) => Some((left, right)),
+            Term::Operation(left, right, _) => Some((left, right)),
         }
     }
 }
@@ -168,14 +550,18 @@ impl BinaryChildren for Rc<Term> {
     fn children(&self) -> Option<(&Self, &Self)> {
         match self.as_ref() {
             Term::Variable => None,
-            Term::Operation(left, right) => Some((left, right)),
+            Term::Operation(left, right, _) => Some((left, right)),
         }
     }
+
+    fn address(&self) -> *const () {
+        Rc::as_ptr(self) as *const ()
+    }
 }
 
 impl FromChildren<()> for TermRef {
     fn from_children(left: Self, right: Self) -> Self {
-        Rc::new(Term::Operation(left, right))
+        Term::operation(left, right)
     }
 
     fn from_leaf(_value: ()) -> Self {
@@ -183,6 +569,59 @@ impl FromChildren<()> for TermRef {
     }
 }
 
+impl PartialEq for Term {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+            || self.shape_hash() == other.shape_hash()
+                && self.leaves() == other.leaves()
+                && self.shape_eq(other)
+    }
+}
+
+impl Eq for Term {}
+
+impl Hash for Term {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.shape_hash());
+    }
+}
+
+thread_local! {
+    static DROP_PLACEHOLDER: TermRef = Rc::new(Term::Variable);
+}
+
+// The derived drop recurses into both children, which overflows the stack for deep terms.
+// Instead, uniquely owned children are detached and dropped from an explicit stack.
+impl Drop for Term {
+    fn drop(&mut self) {
+        let Term::Operation(left, right, _) = self else {
+            return;
+        };
+        if Rc::strong_count(left) > 1 && Rc::strong_count(right) > 1 {
+            return;
+        }
+
+        let detach = |child: &mut TermRef| {
+            DROP_PLACEHOLDER
+                .try_with(|placeholder| std::mem::replace(child, placeholder.clone()))
+                .ok()
+        };
+
+        let mut stack = Vec::new();
+        stack.extend(detach(left));
+        stack.extend(detach(right));
+
+        while let Some(node) = stack.pop() {
+            if let Ok(mut term) = Rc::try_unwrap(node)
+                && let Term::Operation(left, right, _) = &mut term
+            {
+                stack.extend(detach(left));
+                stack.extend(detach(right));
+            }
+        }
+    }
+}
+
 impl Debug for Term {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut leaf_count = 0;
@@ -216,3 +655,249 @@ impl Display for Term {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashSet, hash_map::DefaultHasher},
+        hash::Hasher,
+    };
+
+    use super::*;
+    use crate::{indexing::IndexedTerm, iter::TermIterator, labeled::LabeledTerm, lterm, term};
+
+    fn right_comb(leaves: usize) -> TermRef {
+        (1..leaves).fold(Rc::new(Term::Variable), |right, _| {
+            Term::operation(Rc::new(Term::Variable), right)
+        })
+    }
+
+    #[test]
+    fn deep_terms() {
+        let leaves = 50_000;
+        let term = right_comb(leaves);
+        let other = right_comb(leaves);
+
+        assert_eq!(term.leaf_count() as usize, leaves);
+        assert_eq!(term.depth(), leaves - 1);
+        assert!(term == other);
+        assert_eq!(term.to_string().len(), other.to_string().len());
+
+        let (mut a, mut b) = (DefaultHasher::new(), DefaultHasher::new());
+        term.hash(&mut a);
+        other.hash(&mut b);
+        assert_eq!(a.finish(), b.finish());
+
+        let mut count = 0;
+        term.propagate((), &mut |_, _| ((), ()), &mut |_, _| count += 1);
+        assert_eq!(count, leaves);
+
+        drop(term);
+        drop(other);
+    }
+
+    #[test]
+    #[should_panic(expected = "too many leaves")]
+    fn leaf_count_overflow() {
+        right_comb(NodeIndex::MAX as usize + 1).leaf_count();
+    }
+
+    #[test]
+    fn subterms() {
+        let term = term!((a * (b * c)) * d);
+        let positions: Vec<_> = term
+            .subterms()
+            .map(|(position, subterm)| {
+                assert!(Rc::ptr_eq(term.subterm_at(&position).unwrap(), subterm));
+                position.to_string()
+            })
+            .collect();
+        assert_eq!(positions, ["ε", "L", "LL", "LR", "LRL", "LRR", "R"]);
+        assert_eq!(term.subterm_at(&"LLL".parse().unwrap()), None);
+
+        // grafting a subterm back leaves the term as it was, and shares what is off the path
+        let position = "LR".parse().unwrap();
+        let subterm = term.subterm_at(&position).unwrap().clone();
+        let grafted = term.graft(&position, term!(x * (y * z)));
+        assert_eq!(grafted, term!((a * (b * (c * d))) * e));
+        assert!(Rc::ptr_eq(
+            grafted.subterm_at(&"R".parse().unwrap()).unwrap(),
+            term.children().unwrap().1
+        ));
+        assert_eq!(grafted.graft(&position, subterm), term);
+    }
+
+    #[test]
+    #[should_panic(expected = "too many leaves")]
+    fn operation_leaf_overflow() {
+        // sharing both children doubles the leaves with every node
+        let mut term = Rc::new(Term::Variable);
+        for _ in 0..32 {
+            term = Term::operation(term.clone(), term);
+        }
+    }
+
+    #[test]
+    fn structural_hash() {
+        let terms: Vec<_> = crate::iter::TermIterator::new(8).collect();
+        let hashes: HashSet<_> = terms.iter().map(|term| term.shape_hash()).collect();
+        assert_eq!(hashes.len(), terms.len());
+
+        let built = term!((a * b) * (c * d));
+        let decoded = Term::decode(&built.encode()).unwrap();
+        assert_eq!(built.shape_hash(), decoded.shape_hash());
+    }
+
+    #[test]
+    fn encode_roundtrip() {
+        for term in crate::iter::TermIterator::new(7) {
+            let bytes = term.encode();
+            // bit count, then 12 bits for the 6 operations
+            assert_eq!(bytes.len(), 4 + 2);
+            assert!(Term::decode(&bytes).unwrap() == term);
+        }
+        assert_eq!(
+            Term::decode(&[1, 0, 0, 0, 0]).unwrap_err(),
+            DecodeError::InvalidShape
+        );
+
+        let term = right_comb(50_000);
+        assert!(Term::decode(&term.encode()).unwrap() == term);
+
+        let labeled = lterm!((a * b) * (a * (c * b)));
+        let decoded = LabeledTerm::<String>::decode(&labeled.encode()).unwrap();
+        assert_eq!(decoded.to_string(), labeled.to_string());
+    }
+
+    #[test]
+    fn shared_reduce() {
+        // 2^30 leaves as a DAG with 31 nodes
+        let doublings = 30;
+        let term = (0..doublings).fold(Rc::new(Term::Variable), |term, _| {
+            Term::operation(term.clone(), term)
+        });
+
+        let mut calls = 0;
+        let leaves: u64 = term.reduce_shared(
+            &mut |_, left, right| {
+                calls += 1;
+                left + right
+            },
+            &mut |_| 1,
+        );
+        assert_eq!(leaves, 1 << doublings);
+        assert_eq!(calls, doublings);
+
+        let copy: TermRef = term.map_shared(&mut |_| ());
+        let (left, right) = copy.children().unwrap();
+        assert!(Rc::ptr_eq(left, right));
+    }
+
+    #[test]
+    fn rotations() {
+        let assoc = lterm!((a * b) * c).map_to(lterm!(a * (b * c)));
+        let term = term!(a * ((b * c) * (d * e)));
+        let position = "R".parse().unwrap();
+
+        let rotated = term.rotate_right_at(&position).unwrap();
+        let expected = term.substitute_at(&position, &assoc);
+        assert!(rotated.target() == expected.target());
+        assert_eq!(rotated.perm(), expected.perm());
+
+        let back = rotated.target().rotate_left_at(&position).unwrap();
+        assert!(back.target() == &term);
+        assert!(term.rotate_left_at(&"RL".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn replace_at() {
+        let term = term!(a * ((b * c) * d));
+        let replacement = term!(a * (b * c));
+        let (result, map) = term.replace_at(&"R".parse().unwrap(), replacement);
+        assert_eq!(result.to_string(), "0 * (1 * (2 * 3))");
+        assert!(map.perm().is_identity());
+        assert!(Rc::ptr_eq(
+            result.children().unwrap().0,
+            term.children().unwrap().0
+        ));
+    }
+
+    #[test]
+    fn substitute_all_occurrences() {
+        let assoc = lterm!((a * b) * c).map_to(lterm!(a * (b * c)));
+        let pattern = IndexedTerm::from(assoc.source().clone());
+
+        // both children are the same node, so the match roots are shared
+        let left = term!((a * b) * c);
+        let term = Term::operation(left.clone(), left);
+
+        let occurrences = pattern.occurrences(&term);
+        let positions: Vec<_> = occurrences.iter().map(|p| p.to_string()).collect();
+        assert_eq!(positions, ["ε", "L", "R"]);
+
+        let results = term.substitute_all(&occurrences, &assoc);
+        for (position, result) in occurrences.iter().zip(&results) {
+            let expected = term.substitute_at(position, &assoc);
+            assert!(result.target() == expected.target());
+            assert_eq!(result.perm(), expected.perm());
+        }
+        let matches = pattern.match_with_bindings(&term);
+        assert_eq!(matches.len(), 3);
+        assert_eq!(matches[0].bindings[0].to_string(), "0 * 1");
+        for (matched, result) in matches.iter().zip(&results) {
+            assert_eq!(matched.bindings.len(), 3);
+            let direct = term.substitute_match(matched, &assoc);
+            assert_eq!(direct.perm(), result.perm());
+        }
+
+        // the untouched left child is shared with the original term
+        assert!(Rc::ptr_eq(
+            results[2].target().children().unwrap().0,
+            term.children().unwrap().0
+        ));
+    }
+
+    #[test]
+    fn match_top_down() {
+        let pattern = term!((x * y) * z);
+        let indexed = IndexedTerm::from(pattern.clone());
+        for term in TermIterator::new(6) {
+            let top_down = pattern.match_top_down(&term);
+            let indexed = indexed.match_with_bindings(&term);
+            assert_eq!(top_down.len(), indexed.len());
+            for (a, b) in top_down.iter().zip(&indexed) {
+                assert_eq!(a.position, b.position);
+                assert_eq!(a.bindings, b.bindings);
+                assert!(pattern.match_at(&term, &a.position).is_some());
+            }
+        }
+
+        // a repeated variable binds equal subterms
+        let square = IndexedTerm::from(&lterm!(x * x));
+        for term in TermIterator::new(6) {
+            let expected: Vec<_> = term
+                .subterms()
+                .filter_map(|(position, node)| {
+                    let (left, right) = node.children()?;
+                    (left == right).then(|| (position, vec![left.clone(), right.clone()]))
+                })
+                .collect();
+            let found: Vec<_> = square
+                .match_with_bindings(&term)
+                .into_iter()
+                .map(|matched| (matched.position, matched.bindings))
+                .collect();
+            assert_eq!(found, expected);
+        }
+
+        let term = term!((a * b) * (c * d));
+        assert!(pattern.match_at(&term, &Position::root()).is_some());
+        assert!(pattern.match_at(&term, &Position::root().left()).is_none());
+        assert!(
+            pattern
+                .match_at(&term, &Position::root().left().left())
+                .is_none()
+        );
+        assert_eq!(term!(x).match_top_down(&term).len(), 7);
+    }
+}
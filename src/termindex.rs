@@ -0,0 +1,203 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use crate::{
+    bidag::BinaryChildren,
+    codec::{ByteReader, ByteWriter, DecodeError},
+    position::Position,
+    substtree::SubstitutionTree,
+    term::{Term, TermRef},
+};
+
+pub type TermId = usize;
+
+/// Index over a growing set of terms that finds the occurrences of a pattern in all of them.
+/// Every distinct subterm shape is stored once in a substitution tree, so inserting a term only
+/// adds what is new about it and nothing is rebuilt.
+#[derive(Default)]
+pub struct TermIndex {
+    terms: Vec<TermRef>,
+    shapes: HashMap<TermRef, usize>,
+    occurrences: Vec<Vec<(TermId, Position)>>,
+    tree: SubstitutionTree<usize>,
+}
+
+impl TermIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.terms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    pub fn term(&self, id: TermId) -> &TermRef {
+        &self.terms[id]
+    }
+
+    pub fn insert(&mut self, term: TermRef) -> TermId {
+        let id = self.terms.len();
+        term.walk_with_positions(&mut |position, node| {
+            let shape = *self.shapes.entry(node.clone()).or_insert_with(|| {
+                self.tree.insert(node, self.occurrences.len());
+                self.occurrences.push(Vec::new());
+                self.occurrences.len() - 1
+            });
+            self.occurrences[shape].push((id, position.clone()));
+        });
+        self.terms.push(term);
+        id
+    }
+
+    /// Writes the terms together with everything derived from them, so decoding does not have to
+    /// rebuild the substitution tree.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = ByteWriter::new();
+        writer.u32(self.terms.len() as u32);
+        self.terms
+            .iter()
+            .for_each(|term| term.encode_into(&mut writer));
+        // every shape is found again at its first occurrence
+        writer.u32(self.occurrences.len() as u32);
+        for occurrences in &self.occurrences {
+            writer.u32(occurrences.len() as u32);
+            for (id, position) in occurrences {
+                writer.u32(*id as u32);
+                position.encode_into(&mut writer);
+            }
+        }
+        self.tree.encode_into(&mut writer);
+        writer.into_bytes()
+    }
+
+    /// Decodes what `encode` wrote, checking that the shapes and the tree fit the terms.
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = ByteReader::new(bytes);
+        let mut index = TermIndex::new();
+        for _ in 0..reader.u32()? {
+            index.terms.push(Term::decode_from(&mut reader)?);
+        }
+
+        for shape in 0..reader.u32()? as usize {
+            let mut occurrences = Vec::new();
+            for _ in 0..reader.u32()? {
+                let id = reader.u32()?;
+                let position = Position::decode_from(&mut reader)?;
+                if id as usize >= index.terms.len() {
+                    return Err(DecodeError::InvalidIndex(id));
+                }
+                occurrences.push((id as usize, position));
+            }
+            let (id, position) = occurrences.first().ok_or(DecodeError::InvalidShape)?;
+            let node = index.terms[*id]
+                .subterm_at(position)
+                .ok_or(DecodeError::InvalidShape)?;
+            let same = occurrences
+                .iter()
+                .all(|(id, position)| index.terms[*id].subterm_at(position) == Some(node));
+            if !same || index.shapes.insert(node.clone(), shape).is_some() {
+                return Err(DecodeError::InvalidShape);
+            }
+            index.occurrences.push(occurrences);
+        }
+
+        index.tree = SubstitutionTree::decode_from(&mut reader)?;
+        let mut stored = vec![false; index.occurrences.len()];
+        for &shape in index.tree.values() {
+            match stored.get_mut(shape) {
+                Some(seen) if !*seen => *seen = true,
+                _ => return Err(DecodeError::InvalidIndex(shape as u32)),
+            }
+        }
+        if stored.contains(&false) {
+            return Err(DecodeError::InvalidTree);
+        }
+        reader.finish()?;
+        Ok(index)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.encode())
+    }
+
+    /// Reads the whole file into memory and decodes it.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::decode(&fs::read(path)?)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string()))
+    }
+
+    /// All occurrences of `pattern` in the stored terms, by term and then in pre-order.
+    pub fn occurrences(&self, pattern: &TermRef) -> Vec<(TermId, Position)> {
+        let mut found: Vec<_> = self
+            .tree
+            .instances(pattern)
+            .into_iter()
+            .flat_map(|&shape| self.occurrences[shape].iter().cloned())
+            .collect();
+        found.sort_unstable();
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{indexing::IndexedTerm, iter::TermIterator, term};
+
+    #[test]
+    fn term_index() {
+        let patterns = [term!((a * b) * c), term!(a * (b * (c * d)))];
+        let mut index = TermIndex::new();
+        for term in TermIterator::new(4..=6) {
+            index.insert(term);
+            for pattern in &patterns {
+                let indexed = IndexedTerm::from(pattern.clone());
+                let expected: Vec<_> = (0..index.len())
+                    .flat_map(|id| {
+                        let occurrences = indexed.occurrences(index.term(id));
+                        occurrences
+                            .iter()
+                            .map(|position| (id, position.clone()))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect();
+                assert_eq!(index.occurrences(pattern), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut index = TermIndex::new();
+        for term in TermIterator::new(1..=6) {
+            index.insert(term);
+        }
+        let path = std::env::temp_dir().join(format!("trees-index-{}", std::process::id()));
+        index.save(&path).unwrap();
+        let loaded = TermIndex::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.len(), index.len());
+        for pattern in [term!(a * b), term!((a * b) * (c * d)), term!(a * (b * c))] {
+            assert_eq!(loaded.occurrences(&pattern), index.occurrences(&pattern));
+        }
+        assert_eq!(loaded.encode(), index.encode());
+
+        let bytes = index.encode();
+        assert!(TermIndex::decode(&bytes[..bytes.len() - 1]).is_err());
+
+        // the value of the last tree node names a shape that does not exist
+        let mut single = TermIndex::new();
+        single.insert(term!(a));
+        let mut bytes = single.encode();
+        let end = bytes.len();
+        bytes[end - 4..].copy_from_slice(&5u32.to_le_bytes());
+        assert_eq!(
+            TermIndex::decode(&bytes).err(),
+            Some(DecodeError::InvalidIndex(5))
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use std::{collections::HashMap, fmt::Display, hash::Hash, rc::Rc};
+
+use crate::{
+    bidag::{BinaryChildren, FromChildren},
+    labeled::{LabeledTerm, LabeledTermRef},
+};
+
+/// Simultaneous replacement of variables, i.e. leaves with the given labels, by terms. The bound
+/// terms never contain bound variables, so applying a substitution once is enough.
+#[derive(Clone)]
+pub struct Substitution<T> {
+    bindings: HashMap<T, LabeledTermRef<T>>,
+}
+
+impl<T: Clone + Hash + Eq> Substitution<T> {
+    pub fn new() -> Self {
+        Substitution {
+            bindings: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, variable: &T) -> Option<&LabeledTermRef<T>> {
+        self.bindings.get(variable)
+    }
+
+    /// Binds `variable`, returning its previous binding.
+    pub fn insert(&mut self, variable: T, term: LabeledTermRef<T>) -> Option<LabeledTermRef<T>> {
+        self.bindings.insert(variable, term)
+    }
+
+    pub fn len(&self) -> usize {
+        self.bindings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bindings.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&T, &LabeledTermRef<T>)> {
+        self.bindings.iter()
+    }
+
+    pub fn apply(&self, term: &LabeledTermRef<T>) -> LabeledTermRef<T> {
+        term.reduce(
+            &mut |_, left, right| LabeledTermRef::from_children(left, right),
+            &mut |leaf| {
+                self.bindings
+                    .get(leaf.label().unwrap())
+                    .unwrap_or(leaf)
+                    .clone()
+            },
+        )
+    }
+
+    // follows bindings of variables to variables
+    fn resolve<'s>(&'s self, mut term: &'s LabeledTermRef<T>) -> &'s LabeledTermRef<T> {
+        while let Some(bound) = term.label().and_then(|label| self.bindings.get(label)) {
+            term = bound;
+        }
+        term
+    }
+
+    fn occurs(&self, variable: &T, term: &LabeledTermRef<T>) -> bool {
+        let mut stack = vec![term];
+        while let Some(node) = stack.pop() {
+            match node.children() {
+                Some((left, right)) => stack.extend([left, right]),
+                None => {
+                    let label = node.label().unwrap();
+                    match self.bindings.get(label) {
+                        Some(bound) => stack.push(bound),
+                        None if label == variable => return true,
+                        None => {}
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    // turns the triangular bindings built during unification into idempotent ones
+    fn normalize(self) -> Self {
+        fn resolved<T: Clone + Hash + Eq>(
+            variable: &T,
+            triangular: &Substitution<T>,
+            done: &mut HashMap<T, LabeledTermRef<T>>,
+        ) -> LabeledTermRef<T> {
+            if let Some(term) = done.get(variable) {
+                return term.clone();
+            }
+            let term = triangular.bindings[variable].reduce(
+                &mut |_, left, right| LabeledTermRef::from_children(left, right),
+                &mut |leaf| {
+                    let label = leaf.label().unwrap();
+                    if triangular.bindings.contains_key(label) {
+                        resolved(label, triangular, done)
+                    } else {
+                        leaf.clone()
+                    }
+                },
+            );
+            done.insert(variable.clone(), term.clone());
+            term
+        }
+
+        let mut done = HashMap::new();
+        for variable in self.bindings.keys() {
+            resolved(variable, &self, &mut done);
+        }
+        Substitution { bindings: done }
+    }
+}
+
+impl<T: Clone + Hash + Eq> Default for Substitution<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone + Hash + Eq> LabeledTerm<T> {
+    /// Most general unifier of the two terms, treating all leaves as variables identified by
+    /// their labels. `None` if the terms are not unifiable.
+    pub fn unify(self: &LabeledTermRef<T>, other: &LabeledTermRef<T>) -> Option<Substitution<T>> {
+        let mut substitution = Substitution::new();
+        let mut pending = vec![(self.clone(), other.clone())];
+
+        while let Some((left, right)) = pending.pop() {
+            let left = substitution.resolve(&left).clone();
+            let right = substitution.resolve(&right).clone();
+            if Rc::ptr_eq(&left, &right) {
+                continue;
+            }
+
+            match (left.as_ref(), right.as_ref()) {
+                (LabeledTerm::Variable(x), LabeledTerm::Variable(y)) if x == y => {}
+                (LabeledTerm::Variable(x), _) => {
+                    if substitution.occurs(x, &right) {
+                        return None;
+                    }
+                    substitution.bindings.insert(x.clone(), right);
+                }
+                (_, LabeledTerm::Variable(y)) => {
+                    if substitution.occurs(y, &left) {
+                        return None;
+                    }
+                    substitution.bindings.insert(y.clone(), left);
+                }
+                (
+                    LabeledTerm::Operation(left_left, left_right),
+                    LabeledTerm::Operation(right_left, right_right),
+                ) => {
+                    pending.push((left_right.clone(), right_right.clone()));
+                    pending.push((left_left.clone(), right_left.clone()));
+                }
+            }
+        }
+
+        Some(substitution.normalize())
+    }
+
+    /// The substitution of the leaves of `self` that turns it into `instance`, whose leaves are
+    /// constants. `None` if `instance` is not an instance of `self`.
+    pub fn match_onto(
+        self: &LabeledTermRef<T>,
+        instance: &LabeledTermRef<T>,
+    ) -> Option<Substitution<T>> {
+        let mut substitution = Substitution::new();
+        let mut pending = vec![(self, instance)];
+
+        while let Some((pattern, node)) = pending.pop() {
+            match (pattern.as_ref(), node.as_ref()) {
+                (LabeledTerm::Variable(x), _) => match substitution.bindings.get(x) {
+                    Some(bound) if bound != node => return None,
+                    Some(_) => {}
+                    None => {
+                        substitution.bindings.insert(x.clone(), node.clone());
+                    }
+                },
+                (
+                    LabeledTerm::Operation(pattern_left, pattern_right),
+                    LabeledTerm::Operation(left, right),
+                ) => {
+                    pending.push((pattern_right, right));
+                    pending.push((pattern_left, left));
+                }
+                (LabeledTerm::Operation(_, _), LabeledTerm::Variable(_)) => return None,
+            }
+        }
+
+        Some(substitution)
+    }
+
+    /// Whether `self` is at least as general as `other`, i.e. `other` is an instance of `self`.
+    /// A pattern subsumed by another one adds no matches.
+    pub fn subsumes(self: &LabeledTermRef<T>, other: &LabeledTermRef<T>) -> bool {
+        self.match_onto(other).is_some()
+    }
+}
+
+impl<T: Display> Display for Substitution<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut bindings: Vec<_> = self
+            .bindings
+            .iter()
+            .map(|(variable, term)| format!("{} ↦ {}", variable, term))
+            .collect();
+        bindings.sort();
+        write!(f, "{{{}}}", bindings.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::lterm;
+
+    #[test]
+    fn unify() {
+        let left = lterm!((x * y) * z);
+        let right = lterm!(a * (b * c));
+        let unifier = left.unify(&right).unwrap();
+        assert_eq!(unifier.to_string(), "{a ↦ x * y, z ↦ b * c}");
+        assert_eq!(
+            unifier.apply(&left).to_string(),
+            unifier.apply(&right).to_string()
+        );
+
+        // bindings through chains of variables are resolved
+        let left = lterm!(x * (x * y));
+        let right = lterm!(y * (z * (a * b)));
+        let unifier = left.unify(&right).unwrap();
+        assert_eq!(
+            unifier.apply(&left).to_string(),
+            "(a * b) * ((a * b) * (a * b))"
+        );
+        assert_eq!(
+            unifier.apply(&left).to_string(),
+            unifier.apply(&right).to_string()
+        );
+
+        assert!(lterm!(x * y).unify(&lterm!(x)).is_none());
+    }
+
+    #[test]
+    fn subsumes() {
+        let general = lterm!(x * y);
+        let repeated = lterm!(x * x);
+        let specific = lterm!((a * b) * (a * b));
+
+        assert!(general.subsumes(&repeated));
+        assert!(!repeated.subsumes(&general));
+        assert!(repeated.subsumes(&specific));
+        assert!(!repeated.subsumes(&lterm!((a * b) * (b * a))));
+        assert!(lterm!(x).subsumes(&specific));
+        assert!(!specific.subsumes(&lterm!(x)));
+
+        // subsumption in both directions means the patterns are variants
+        let renamed = lterm!(u * v);
+        assert!(general.subsumes(&renamed) && renamed.subsumes(&general));
+
+        let substitution = repeated.match_onto(&specific).unwrap();
+        assert_eq!(substitution.to_string(), "{x ↦ a * b}");
+    }
+}
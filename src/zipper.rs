@@ -0,0 +1,179 @@
+use std::rc::Rc;
+
+use crate::{
+    bidag::BinaryChildren,
+    maps::{NodeIndex, TermMap},
+    position::{Direction, Position},
+    term::{Term, TermRef},
+};
+
+struct Frame {
+    direction: Direction,
+    parent: TermRef,
+    sibling: TermRef,
+}
+
+/// Cursor into a term that allows editing subterms without writing a recursive rebuild. Parents
+/// are only reallocated when a subterm below them actually changed.
+pub struct TermZipper {
+    original: TermRef,
+    focus: TermRef,
+    path: Vec<Frame>,
+    // number of leaves left of the focus
+    offset: NodeIndex,
+    // origin[i] is the leaf of the original term that became leaf i of the edited term
+    origin: Vec<NodeIndex>,
+}
+
+impl TermZipper {
+    pub fn new(term: TermRef) -> Self {
+        TermZipper {
+            origin: (0..term.leaves() as NodeIndex).collect(),
+            focus: term.clone(),
+            original: term,
+            path: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    pub fn focus(&self) -> &TermRef {
+        &self.focus
+    }
+
+    pub fn position(&self) -> Position {
+        self.path
+            .iter()
+            .map(|frame| frame.direction)
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.path.is_empty()
+    }
+
+    fn down(&mut self, direction: Direction) -> bool {
+        let Some((left, right)) = self.focus.children() else {
+            return false;
+        };
+        let (child, sibling) = match direction {
+            Direction::Left => (left.clone(), right.clone()),
+            Direction::Right => {
+                self.offset += left.leaves() as NodeIndex;
+                (right.clone(), left.clone())
+            }
+        };
+        let parent = std::mem::replace(&mut self.focus, child);
+        self.path.push(Frame {
+            direction,
+            parent,
+            sibling,
+        });
+        true
+    }
+
+    /// Moves to the left child. Returns false and stays put if the focus is a leaf.
+    pub fn down_left(&mut self) -> bool {
+        self.down(Direction::Left)
+    }
+
+    /// Moves to the right child. Returns false and stays put if the focus is a leaf.
+    pub fn down_right(&mut self) -> bool {
+        self.down(Direction::Right)
+    }
+
+    /// Moves to the parent. Returns false if the focus is the root.
+    pub fn up(&mut self) -> bool {
+        let Some(frame) = self.path.pop() else {
+            return false;
+        };
+        let (original_left, original_right) = frame.parent.children().unwrap();
+        let unchanged = match frame.direction {
+            Direction::Left => Rc::ptr_eq(&self.focus, original_left),
+            Direction::Right => Rc::ptr_eq(&self.focus, original_right),
+        };
+
+        if frame.direction == Direction::Right {
+            self.offset -= frame.sibling.leaves() as NodeIndex;
+        }
+
+        self.focus = match (unchanged, frame.direction) {
+            (true, _) => frame.parent,
+            (false, Direction::Left) => Term::operation(self.focus.clone(), frame.sibling),
+            (false, Direction::Right) => Term::operation(frame.sibling, self.focus.clone()),
+        };
+        true
+    }
+
+    /// Moves to `position` relative to the current focus. Returns false if the position does not
+    /// exist, in which case the focus is left at the deepest existing node on the way.
+    pub fn descend(&mut self, position: &Position) -> bool {
+        position
+            .steps()
+            .iter()
+            .all(|direction| self.down(*direction))
+    }
+
+    pub fn up_to_root(&mut self) {
+        while self.up() {}
+    }
+
+    /// Replaces the focus by `map.target()`, where `map.source()` has to be the focus. The leaves
+    /// of the focus are moved according to `map`.
+    pub fn replace_focus(&mut self, map: &TermMap<'_>) {
+        assert!(
+            map.source() == &self.focus,
+            "source of the map is not the focus"
+        );
+
+        let offset = self.offset as usize;
+        let leaves = self.focus.leaves();
+        let moved = self.origin[offset..offset + leaves].to_vec();
+        for (i, origin) in moved.into_iter().enumerate() {
+            self.origin[offset + map[i as NodeIndex] as usize] = origin;
+        }
+        self.focus = map.target().clone();
+    }
+
+    /// Returns the map from the original to the edited term.
+    pub fn rebuild(mut self) -> TermMap<'static> {
+        self.up_to_root();
+        let backward = TermMap::new(self.focus, self.original, self.origin.into());
+        backward.into_backward()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{lterm, perm::perms::Permutation, term};
+
+    #[test]
+    fn edit_deep_subterm() {
+        let term = term!(a * (((b * c) * d) * e));
+        // neither the shape change nor the leaf permutation is its own inverse
+        let rotate = lterm!((x * y) * z).map_to(lterm!(y * (z * x)));
+
+        let mut zipper = TermZipper::new(term.clone());
+        assert!(zipper.is_root());
+        assert!(zipper.descend(&"RL".parse().unwrap()));
+        assert!(!zipper.is_root());
+        assert_eq!(zipper.position().to_string(), "RL");
+        zipper.replace_focus(&rotate);
+        assert_eq!(zipper.focus(), &term!(a * (b * c)));
+        assert!(zipper.up() && zipper.down_right());
+        assert!(!zipper.down_left());
+        zipper.up_to_root();
+        assert!(Rc::ptr_eq(
+            zipper.focus().children().unwrap().0,
+            term.children().unwrap().0
+        ));
+
+        let map = zipper.rebuild();
+        let expected = lterm!(a * (((b * c) * d) * e)).map_to(lterm!(a * ((c * (d * b)) * e)));
+        assert_eq!(map.target(), expected.target());
+        assert_eq!(map.perm(), expected.perm());
+        assert_eq!(map.perm(), &Permutation::from(vec![0, 3, 1, 2, 4]));
+    }
+}